@@ -1,12 +1,86 @@
+use std::process::Command;
+
 fn main() {
-    cc::Build::new()
+    println!("cargo:rerun-if-changed=src/choco.mm");
+
+    // The mock runtime does not provide anything that could be used by the Objective-C shims.
+    if std::env::var_os("CARGO_FEATURE_MOCK_RUNTIME").is_some() {
+        return;
+    }
+
+    // Without the shims, only the libraries are needed, for the classes used with `msg_send!`.
+    let shims = std::env::var_os("CARGO_FEATURE_SHIMS").is_some();
+    let target_vendor = std::env::var("CARGO_CFG_TARGET_VENDOR").unwrap_or_default();
+    if target_vendor == "apple" {
+        build_apple(shims);
+    } else {
+        build_gnustep(shims);
+    }
+}
+
+fn common_build() -> cc::Build {
+    let mut build = cc::Build::new();
+    build
         .file("src/choco.mm")
         .flag("-fobjc-arc")
         .flag("-std=c++17")
         .flag("-Wall")
         .flag("-Wextra")
-        .flag("-Werror=conversion")
-        .compile("choco");
+        .flag("-Werror=conversion");
+    build
+}
+
+fn build_apple(shims: bool) {
+    if shims {
+        common_build().compile("choco");
+    }
     println!("cargo:rustc-link-lib=framework=Foundation");
     println!("cargo:rustc-link-lib=framework=AVFoundation");
 }
+
+/// Output of `gnustep-config` with the given option, split on whitespace.
+fn gnustep_config(option: &str) -> Vec<String> {
+    let output = Command::new("gnustep-config").arg(option).output().expect(
+        "gnustep-config is needed to build on non-Apple platforms (is gnustep-base installed?)",
+    );
+    if !output.status.success() {
+        panic!(
+            "gnustep-config {} failed: {}",
+            option,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    String::from_utf8(output.stdout)
+        .expect("expecting gnustep-config output to be valid UTF-8")
+        .split_whitespace()
+        .map(str::to_owned)
+        .collect()
+}
+
+// libobjc2's ARC and blocks support is only available with clang, GCC's Objective-C runtime not being supported.
+fn build_gnustep(shims: bool) {
+    if shims {
+        let mut build = common_build();
+        if std::env::var_os("CC").is_none() && std::env::var_os("CXX").is_none() {
+            build.compiler("clang");
+        }
+        build.flag("-fblocks");
+        for flag in gnustep_config("--objc-flags") {
+            // We compile as C++17, so skip any language standard gnustep-config might be asking for.
+            if !flag.starts_with("-std=") {
+                build.flag(&flag);
+            }
+        }
+        build.compile("choco");
+    }
+
+    for flag in gnustep_config("--base-libs") {
+        if let Some(path) = flag.strip_prefix("-L") {
+            println!("cargo:rustc-link-search=native={}", path);
+        } else if let Some(lib) = flag.strip_prefix("-l") {
+            println!("cargo:rustc-link-lib={}", lib);
+        }
+    }
+    // CoreFoundation functions (CFRetain, CFRelease...) are provided by gnustep-corebase.
+    println!("cargo:rustc-link-lib=gnustep-corebase");
+}
@@ -0,0 +1,316 @@
+//-------------------------------------------------------------------
+// Associated objects
+//
+// Values attached to an object by the runtime, kept until they are replaced or the object is deallocated.
+// Useful to hang state off objects created by a framework:
+//
+// ```ignore
+// static THUMBNAILS: AssociationKey = AssociationKey::new();
+//
+// asset.set_associated(&THUMBNAILS, Mutex::new(HashMap::<u64, Thumbnail>::new()));
+// if let Some(thumbnails) = asset.get_associated::<Mutex<HashMap<u64, Thumbnail>>>(&THUMBNAILS) {
+//     thumbnails.lock().unwrap().insert(time, thumbnail);
+// }
+// ```
+
+use super::objc::autorelease_pool;
+use super::{
+    declared_class, ClassDecl, DeclareClass, IsKindOf, ObjCClass, Ownership, Ptr, RawClassPtr,
+    RawObjPtr, Retained, Type, TypeKind,
+};
+use std::any::Any;
+use std::ffi::c_void;
+use std::marker::PhantomData;
+
+/// How the runtime keeps an associated object, `objc_AssociationPolicy`.
+///
+/// The atomic policies (`Retain` and `Copy`) make getting the object safe while another thread replaces it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(usize)]
+pub enum AssociationPolicy {
+    /// The object is not retained, and must outlive the association.
+    Assign = 0,
+    RetainNonatomic = 1,
+    CopyNonatomic = 3,
+    Retain = 0o1401,
+    Copy = 0o1403,
+}
+
+/// Key of associations, identified by its address so it has to be kept in a `static`:
+///
+/// ```ignore
+/// static CACHE: AssociationKey = AssociationKey::new();
+/// ```
+pub struct AssociationKey {
+    // Not zero-sized, so that each key has its own address.
+    _unique: u8,
+}
+
+impl AssociationKey {
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Self {
+        Self { _unique: 0 }
+    }
+
+    fn as_ptr(&'static self) -> *const c_void {
+        (self as *const Self).cast()
+    }
+}
+
+#[cfg_attr(not(feature = "mock-runtime"), link(name = "objc", kind = "dylib"))]
+extern "C" {
+    fn objc_setAssociatedObject(
+        object: RawObjPtr,
+        key: *const c_void,
+        value: Option<RawObjPtr>,
+        policy: AssociationPolicy,
+    );
+    fn objc_getAssociatedObject(object: RawObjPtr, key: *const c_void) -> Option<RawObjPtr>;
+}
+
+/// Root class of the runtime, that `ValueBox` is a subclass of.
+struct RootObject {}
+
+impl Type for RootObject {
+    const KIND: TypeKind = TypeKind::ObjC;
+}
+
+impl ObjCClass for RootObject {
+    #[cfg(not(feature = "mock-runtime"))]
+    fn class() -> RawClassPtr {
+        super::Class::get("NSObject")
+            .expect("expecting the NSObject class to exist")
+            .as_raw()
+    }
+
+    #[cfg(feature = "mock-runtime")]
+    fn class() -> RawClassPtr {
+        super::mock_runtime::register_class("NSObject")
+    }
+}
+
+/// Objective-C object keeping a Rust value, so that it can be associated to an object.
+struct ValueBox {
+    _private: PhantomData<*const ()>,
+}
+
+impl Type for ValueBox {
+    const KIND: TypeKind = TypeKind::ObjC;
+}
+
+impl ObjCClass for ValueBox {
+    fn class() -> RawClassPtr {
+        declared_class::<Self>()
+    }
+}
+
+unsafe impl IsKindOf<RootObject> for ValueBox {}
+
+unsafe impl DeclareClass for ValueBox {
+    type Super = RootObject;
+    type State = Box<dyn Any + Send + Sync>;
+    const NAME: &'static str = "ChocoAssociatedValueBox";
+
+    fn declare(_decl: &mut ClassDecl<Self>) {}
+}
+
+/// Rust value associated to an object, as returned by `Ptr::get_associated`.
+///
+/// Keeps the value alive even if the association is replaced or the object deallocated.
+pub struct Associated<V> {
+    holder: Ptr<ValueBox, Retained>,
+    _marker: PhantomData<V>,
+}
+
+impl<V: 'static> std::ops::Deref for Associated<V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        // The type was checked when creating `self`.
+        self.holder.state().downcast_ref().unwrap()
+    }
+}
+
+// The value is `Send` and `Sync`, and the runtime functions can be used from any thread.
+unsafe impl<V: Send + Sync> Send for Associated<V> {}
+unsafe impl<V: Send + Sync> Sync for Associated<V> {}
+
+impl<T: ObjCClass, O: Ownership> Ptr<T, O> {
+    /// Associates `value` to the object under `key`, or removes the association if `value` is `None`,
+    /// replacing the one there was.
+    ///
+    /// # Safety
+    /// With `AssociationPolicy::Assign`, `value` must outlive the association,
+    /// or the association be replaced or removed before `value` is deallocated.
+    /// With the non-atomic policies, the association under `key` must not be got or replaced
+    /// by another thread while it is kept with that policy.
+    pub unsafe fn set_associated_object<U: ObjCClass, UO: Ownership>(
+        &self,
+        key: &'static AssociationKey,
+        value: Option<&Ptr<U, UO>>,
+        policy: AssociationPolicy,
+    ) {
+        let value = value.map(|value| value.as_raw());
+        objc_setAssociatedObject(self.as_raw(), key.as_ptr(), value, policy);
+    }
+
+    /// Object associated under `key`.
+    ///
+    /// # Safety
+    /// The object associated under `key` must be of type `U`.
+    /// If it was associated with `AssociationPolicy::Assign` it must still be alive,
+    /// and with the non-atomic policies it must not be replaced at the same time by another thread.
+    pub unsafe fn associated_object<U: ObjCClass>(
+        &self,
+        key: &'static AssociationKey,
+    ) -> Option<Ptr<U, Retained>> {
+        // With the atomic policies, the runtime retains and autoreleases the object.
+        autorelease_pool(|_| {
+            let raw = objc_getAssociatedObject(self.as_raw(), key.as_ptr())?;
+            Some(raw.retain())
+        })
+    }
+
+    /// Associates a Rust value to the object under `key`, replacing what was associated there.
+    ///
+    /// The value is dropped once the association gets replaced or the object gets deallocated
+    /// (and the `Associated` returned by `get_associated` for it dropped), on whichever thread that happens.
+    pub fn set_associated<V: Send + Sync + 'static>(&self, key: &'static AssociationKey, value: V) {
+        let holder = ValueBox::with_state(Box::new(value));
+        // `Retain` keeps the holder alive, and is atomic.
+        unsafe { self.set_associated_object(key, Some(&holder), AssociationPolicy::Retain) };
+    }
+
+    /// Rust value associated under `key` by `set_associated`.
+    ///
+    /// Returns `None` if nothing is associated under `key`, or something else than a `V`.
+    pub fn get_associated<V: Send + Sync + 'static>(
+        &self,
+        key: &'static AssociationKey,
+    ) -> Option<Associated<V>> {
+        let obj = autorelease_pool(|_| unsafe {
+            let raw = objc_getAssociatedObject(self.as_raw(), key.as_ptr())?;
+            Some(raw.retain::<RootObject>())
+        })?;
+        // Something else than a Rust value could have been associated with `set_associated_object`.
+        let holder: Ptr<ValueBox, Retained> = obj.downcast().ok()?;
+        if !holder.state().is::<V>() {
+            return None;
+        }
+        Some(Associated {
+            holder,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Removes the association under `key`, whatever it is.
+    pub fn remove_associated(&self, key: &'static AssociationKey) {
+        unsafe {
+            objc_setAssociatedObject(self.as_raw(), key.as_ptr(), None, AssociationPolicy::Assign)
+        };
+    }
+}
+
+#[cfg(all(test, feature = "mock-runtime"))]
+mod associated_tests {
+    use super::*;
+    use crate::base::mock_runtime::{create_object, register_class, retain_count, LeakCheck};
+    use std::sync::{Arc, Mutex};
+
+    struct MockHost {}
+
+    impl Type for MockHost {
+        const KIND: TypeKind = TypeKind::ObjC;
+    }
+
+    impl ObjCClass for MockHost {
+        fn class() -> RawClassPtr {
+            register_class("MockAssociationHost")
+        }
+    }
+
+    fn new_host() -> Ptr<MockHost> {
+        unsafe { Ptr::from_raw_unchecked(create_object(MockHost::class())) }
+    }
+
+    static CACHE: AssociationKey = AssociationKey::new();
+    static OTHER: AssociationKey = AssociationKey::new();
+
+    #[test]
+    fn values() {
+        let check = LeakCheck::start();
+        let first = Arc::new(Mutex::new(1));
+        let host = new_host();
+        assert!(host.get_associated::<Arc<Mutex<i32>>>(&CACHE).is_none());
+        host.set_associated(&CACHE, first.clone());
+        host.set_associated(&OTHER, "other");
+        assert_eq!(Arc::strong_count(&first), 2);
+
+        *host
+            .get_associated::<Arc<Mutex<i32>>>(&CACHE)
+            .unwrap()
+            .lock()
+            .unwrap() += 1;
+        assert_eq!(*first.lock().unwrap(), 2);
+        assert_eq!(*host.get_associated::<&str>(&OTHER).unwrap(), "other");
+        // Wrong type.
+        assert!(host.get_associated::<String>(&OTHER).is_none());
+
+        // Replacing drops the previous value.
+        let second = Arc::new(Mutex::new(10));
+        host.set_associated(&CACHE, second.clone());
+        assert_eq!(Arc::strong_count(&first), 1);
+        assert_eq!(Arc::strong_count(&second), 2);
+
+        // The value is kept as long as it is used.
+        let associated = host.get_associated::<Arc<Mutex<i32>>>(&CACHE).unwrap();
+        drop(host);
+        assert_eq!(Arc::strong_count(&second), 2);
+        drop(associated);
+        assert_eq!(Arc::strong_count(&second), 1);
+        check.assert_balanced();
+    }
+
+    #[test]
+    fn remove() {
+        let check = LeakCheck::start();
+        let value = Arc::new(());
+        let host = new_host();
+        host.set_associated(&CACHE, value.clone());
+        host.remove_associated(&CACHE);
+        assert_eq!(Arc::strong_count(&value), 1);
+        assert!(host.get_associated::<Arc<()>>(&CACHE).is_none());
+        drop(host);
+        check.assert_balanced();
+    }
+
+    #[test]
+    fn objects() {
+        let check = LeakCheck::start();
+        let host = new_host();
+        let retained = new_host();
+        let assigned = new_host();
+        unsafe {
+            host.set_associated_object(&CACHE, Some(&retained), AssociationPolicy::RetainNonatomic);
+            // `assigned` outlives `host`.
+            host.set_associated_object(&OTHER, Some(&assigned), AssociationPolicy::Assign);
+        }
+        assert_eq!(retain_count(retained.as_raw()), 2);
+        assert_eq!(retain_count(assigned.as_raw()), 1);
+
+        let got = unsafe { host.associated_object::<MockHost>(&CACHE) }.unwrap();
+        assert_eq!(got.as_raw().ptr, retained.as_raw().ptr);
+        drop(got);
+        let got = unsafe { host.associated_object::<MockHost>(&OTHER) }.unwrap();
+        assert_eq!(got.as_raw().ptr, assigned.as_raw().ptr);
+        drop(got);
+        // Not a Rust value.
+        assert!(host.get_associated::<()>(&CACHE).is_none());
+
+        drop(host);
+        assert_eq!(retain_count(retained.as_raw()), 1);
+        drop(retained);
+        drop(assigned);
+        check.assert_balanced();
+    }
+}
@@ -6,28 +6,58 @@ use super::OpaqueClass;
 /// - You can also look at clang or Swift's source code or the LLVM IR or assembly they generate.
 ///
 /// Note that that even if blocks technically can be used from C, they are really for Objective-C.
-///
-// TODO:
-// - Support for returning structures from blocks? That would need adding proper support for signature.
-use std::ffi::c_void;
+use super::{function_signature, Encode, EncodeArguments, Encoding};
+use std::collections::HashMap;
+use std::ffi::{c_void, CStr};
 use std::marker::PhantomData;
-use std::os::raw::{c_int, c_ulong};
+use std::os::raw::{c_char, c_int, c_ulong};
+use std::sync::{Mutex, Once, OnceLock};
 
-#[link(name = "System", kind = "dylib")]
+// On Apple platforms the blocks runtime is part of libSystem, libobjc2 provides its own.
+// With the `blocks-runtime` feature (enabled by `mock-runtime`), these are provided by `base::blocks_runtime`.
+#[cfg(not(feature = "blocks-runtime"))]
+#[cfg_attr(target_vendor = "apple", link(name = "System", kind = "dylib"))]
+#[cfg_attr(not(target_vendor = "apple"), link(name = "objc", kind = "dylib"))]
 extern "C" {
     static _NSConcreteStackBlock: OpaqueClass;
+    static _NSConcreteGlobalBlock: OpaqueClass;
 
     fn _Block_copy(block: *const c_void) -> *mut c_void;
     fn _Block_release(block: *const c_void);
+    fn _Block_object_assign(dest: *mut c_void, object: *const c_void, flags: c_int);
+    fn _Block_object_dispose(object: *const c_void, flags: c_int);
+}
+
+// Used directly instead of through `extern` declarations so that Miri can run the tests.
+#[cfg(feature = "blocks-runtime")]
+use super::blocks_runtime::{
+    _Block_copy, _Block_object_assign, _Block_object_dispose, _Block_release,
+    _NSConcreteGlobalBlock, _NSConcreteStackBlock,
+};
+
+/// The isa of blocks created on the stack.
+fn stack_block_class() -> *const OpaqueClass {
+    std::ptr::addr_of!(_NSConcreteStackBlock).cast()
+}
+
+/// The isa of blocks that are never copied, stored in a `static`.
+fn global_block_class() -> *const OpaqueClass {
+    std::ptr::addr_of!(_NSConcreteGlobalBlock).cast()
 }
 
 /// Indicates that the block descriptor's `copy` and `dispose` should be called.
 const BLOCK_HAS_COPY_DISPOSE: c_int = 1 << 25;
+/// Indicates that the block is never copied, and has no need to be.
+const BLOCK_IS_GLOBAL: c_int = 1 << 28;
+/// Indicates that the block returns a structure through a hidden pointer, see `use_stret`.
+const BLOCK_USE_STRET: c_int = 1 << 29;
 /// Indicates that the block has (C++) constructors.
 ///
 /// It looks like something we might be interested in, but looking at the compiler-rt code,
 /// it does not seem to do anything when the Objective-C GC is disabled, and Apple does not support that GC anymore.
 const BLOCK_HAS_CTOR: c_int = 1 << 26;
+/// Indicates that the block descriptor's `signature` is set.
+const BLOCK_HAS_SIGNATURE: c_int = 1 << 30;
 
 #[repr(C)]
 struct BlockDescriptor {
@@ -39,14 +69,107 @@ struct BlockDescriptor {
     copy: unsafe extern "C" fn(*mut c_void, *const c_void),
     /// Function called to destruct a block that has been copied to the heap (the system will free memory just after).
     dispose: unsafe extern "C" fn(*mut c_void),
-    // `signature` being a pointer will prevent Rust from auto-deriving `Sync` for this structure,
-    // and we do not use it (keeping it at `null`) so we could make it a `usize`, but I prefer having the proper type.
-    // Of course if it is not `null`, the value it points to must have a `'static` lifetime.
-    signature: *const u8,
+    /// Type encoding of the block (only used by the runtime if `BLOCK_HAS_SIGNATURE` is set), see `signature`.
+    /// The string pointed to has a `'static` lifetime.
+    signature: *const c_char,
 }
 // By default `*const` are not shareable between threads and so Rust won't less us make a `static` from it.
 unsafe impl Sync for BlockDescriptor {}
 
+unsafe extern "C" fn no_copy(_dst: *mut c_void, _src: *const c_void) {}
+
+unsafe extern "C" fn no_dispose(_src: *mut c_void) {}
+
+/// Descriptor of a `GlobalBlock` before it gets used for the first time.
+const PLACEHOLDER_DESCRIPTOR: BlockDescriptor = BlockDescriptor {
+    _reserved: 0,
+    block_size: 0,
+    copy: no_copy,
+    dispose: no_dispose,
+    signature: std::ptr::null(),
+};
+
+/// Signature of a block with the given arguments and return type, in the format clang uses:
+/// the return type, the size of the arguments, and each argument (the block itself being the first)
+/// followed by its offset, for example `v24@?0@8Q16` for `void (^)(id, NSUInteger)`.
+///
+/// The string is only built once for each signature.
+fn signature<Args: EncodeArguments, Ret: Encode>() -> &'static CStr {
+    function_signature::<Args, Ret>(&[Encoding::Block])
+}
+
+/// Descriptor with the given content.
+///
+/// As the signature is only known at runtime, descriptors can't be constants.
+/// Instead one is leaked the first time a given descriptor is needed.
+fn descriptor(
+    block_size: usize,
+    copy: unsafe extern "C" fn(*mut c_void, *const c_void),
+    dispose: unsafe extern "C" fn(*mut c_void),
+    signature: &'static CStr,
+) -> &'static BlockDescriptor {
+    type Key = (usize, usize, usize, usize);
+    static DESCRIPTORS: OnceLock<Mutex<HashMap<Key, &'static BlockDescriptor>>> = OnceLock::new();
+
+    // The whole content is used as a key: even if the compiler decided to merge
+    // the copy or dispose functions of different types of blocks, their descriptors would be equivalent.
+    let key = (
+        block_size,
+        copy as usize,
+        dispose as usize,
+        signature.as_ptr() as usize,
+    );
+    let mut descriptors = DESCRIPTORS.get_or_init(Default::default).lock().unwrap();
+    descriptors.entry(key).or_insert_with(|| {
+        Box::leak(Box::new(BlockDescriptor {
+            _reserved: 0,
+            block_size: block_size as c_ulong,
+            copy,
+            dispose,
+            signature: signature.as_ptr(),
+        }))
+    })
+}
+
+/// Whether a block returning `Ret` returns it in memory, through a hidden pointer given before the block itself,
+/// instead of in registers. Only structures and unions can be, depending on their size and the architecture.
+///
+/// The calling convention is the one of C functions so Rust takes care of it,
+/// but `BLOCK_USE_STRET` must be set for the runtime (for example `imp_implementationWithBlock`) to know about it.
+pub(super) fn use_stret<Ret: Encode>() -> bool {
+    if !matches!(
+        Ret::encoding(),
+        Encoding::Struct { .. } | Encoding::Union { .. }
+    ) {
+        return false;
+    }
+    let size = std::mem::size_of::<Ret>();
+    if cfg!(target_arch = "x86_64") {
+        size > 16
+    } else if cfg!(target_arch = "x86") {
+        // Darwin returns the small structures in registers, when the System V ABI (GNUstep) never does.
+        !cfg!(target_vendor = "apple") || !matches!(size, 1 | 2 | 4 | 8)
+    } else if cfg!(target_arch = "arm") {
+        size > 4
+    } else {
+        // arm64 uses a dedicated register for the hidden pointer, so it is never considered stret.
+        false
+    }
+}
+
+/// Flags of the blocks we create (before the runtime adds its own).
+fn block_flags<F, Args, Ret>() -> c_int
+where
+    F: BlockInvoke<Args, Ret>,
+{
+    let flags = BLOCK_HAS_COPY_DISPOSE | BLOCK_HAS_SIGNATURE;
+    if F::use_stret() {
+        flags | BLOCK_USE_STRET
+    } else {
+        flags
+    }
+}
+
 #[repr(C)]
 pub struct BlockHeader {
     isa: *const OpaqueClass,
@@ -97,24 +220,25 @@ where
         std::ptr::drop_in_place(src);
     }
 
-    const DESCRIPTOR: BlockDescriptor = BlockDescriptor {
-        _reserved: 0,
-        block_size: std::mem::size_of::<InnerBlock<F, Args, Ret>>() as u64,
-        copy: Self::copy,
-        dispose: Self::dispose,
-        signature: std::ptr::null(),
-    };
+    fn descriptor() -> &'static BlockDescriptor {
+        descriptor(
+            std::mem::size_of::<InnerBlock<F, Args, Ret>>(),
+            Self::copy,
+            Self::dispose,
+            F::signature(),
+        )
+    }
 
     pub fn new(f: F) -> Self {
         let inner = InnerBlock {
             header: std::cell::UnsafeCell::new(BlockHeader {
-                isa: unsafe { &_NSConcreteStackBlock },
-                flags: BLOCK_HAS_COPY_DISPOSE,
+                isa: stack_block_class(),
+                flags: block_flags::<F, Args, Ret>(),
                 _reserved: 0,
                 // invoke is a function pointer taking a pointer to the block as a first parameter,
                 // but the rest of its type depends on the block parameters and return type.
-                invoke: F::invoker() as *const c_void,
-                descriptor: &Self::DESCRIPTOR,
+                invoke: F::invoker(),
+                descriptor: Self::descriptor(),
             }),
             f,
             _ret_marker: PhantomData,
@@ -127,9 +251,16 @@ where
     /// You must make sure the StackBlock is kept alive while that reference is being accessed,
     /// at least until the block gets copied.
     /// Mainly `unsafe` to have an API closer to `HeapBlock`.
+    ///
+    /// A pointer obtained from the reference only covers the header, use `as_ptr` for code calling the block.
     pub unsafe fn block_ref(&self) -> &std::cell::UnsafeCell<BlockHeader> {
         &self.inner.header
     }
+
+    /// Pointer to pass to Objective-C code expecting a block, valid as long as the StackBlock is alive.
+    pub fn as_ptr(&self) -> *const BlockHeader {
+        &self.inner as *const InnerBlock<F, Args, Ret> as *const BlockHeader
+    }
 }
 
 /// Block that only leaves in the heap.
@@ -155,24 +286,25 @@ where
         std::ptr::drop_in_place(src);
     }
 
-    const DESCRIPTOR: BlockDescriptor = BlockDescriptor {
-        _reserved: 0,
-        block_size: std::mem::size_of::<InnerBlock<F, Args, Ret>>() as u64,
-        copy: Self::copy,
-        dispose: Self::dispose,
-        signature: std::ptr::null(),
-    };
+    fn descriptor() -> &'static BlockDescriptor {
+        descriptor(
+            std::mem::size_of::<InnerBlock<F, Args, Ret>>(),
+            Self::copy,
+            Self::dispose,
+            F::signature(),
+        )
+    }
 
     pub fn new(f: F) -> Self {
         let inner = InnerBlock {
             header: std::cell::UnsafeCell::new(BlockHeader {
-                isa: unsafe { &_NSConcreteStackBlock },
-                flags: BLOCK_HAS_COPY_DISPOSE,
+                isa: stack_block_class(),
+                flags: block_flags::<F, Args, Ret>(),
                 _reserved: 0,
                 // invoke is a function pointer taking a pointer to the block as a first parameter,
                 // but the rest of its type depends on the block parameters and return type.
-                invoke: F::invoker() as *const c_void,
-                descriptor: &Self::DESCRIPTOR,
+                invoke: F::invoker(),
+                descriptor: Self::descriptor(),
             }),
             f,
             _ret_marker: PhantomData,
@@ -191,9 +323,16 @@ where
 
     /// # Safety
     /// You must be sure the internal ref count doesn't get to zero while the reference is alive.
+    ///
+    /// A pointer obtained from the reference only covers the header, use `as_ptr` for code calling the block.
     pub unsafe fn block_ref(&self) -> &std::cell::UnsafeCell<BlockHeader> {
         &(*self.ptr).header
     }
+
+    /// Pointer to pass to Objective-C code expecting a block, valid as long as a reference to the block is kept.
+    pub fn as_ptr(&self) -> *const BlockHeader {
+        self.ptr as *const BlockHeader
+    }
 }
 
 impl<F, Args, Ret> Drop for HeapBlock<F, Args, Ret>
@@ -232,86 +371,323 @@ where
     }
 }
 
-pub trait BlockInvoke<Args, Ret> {
-    fn invoker() -> *const c_void;
+/// Block stored in a `static`, for functions or closures that do not capture anything. See `global_block!`.
+///
+/// Contrary to other blocks it never allocates, and copying it does nothing.
+pub struct GlobalBlock<F, Args, Ret>
+where
+    F: BlockInvoke<Args, Ret> + Sync + 'static,
+{
+    inner: InnerBlock<F, Args, Ret>,
+    /// Parts of the header can only be known at runtime, so it is completed before the block gets used for the first time.
+    header_init: Once,
+}
+
+// The header is only modified once, before any pointer to the block is given out, and `F` is `Sync`.
+unsafe impl<F, Args, Ret> Sync for GlobalBlock<F, Args, Ret> where
+    F: BlockInvoke<Args, Ret> + Sync + 'static
+{
 }
 
-impl<F, Ret> BlockInvoke<(), Ret> for F
+impl<F, Args, Ret> GlobalBlock<F, Args, Ret>
 where
-    F: Fn() -> Ret,
+    F: BlockInvoke<Args, Ret> + Sync + 'static,
 {
-    fn invoker() -> *const c_void {
-        unsafe extern "C" fn invoke<F, Ret>(block: &InnerBlock<F, (), Ret>) -> Ret
-        where
-            F: Fn() -> Ret,
-        {
-            (block.f)()
+    /// Usually used through `global_block!`, `f` being a function pointer.
+    pub const fn new(f: F) -> Self {
+        Self {
+            inner: InnerBlock {
+                header: std::cell::UnsafeCell::new(BlockHeader {
+                    isa: std::ptr::null(),
+                    flags: 0,
+                    _reserved: 0,
+                    invoke: std::ptr::null(),
+                    descriptor: &PLACEHOLDER_DESCRIPTOR,
+                }),
+                f,
+                _ret_marker: PhantomData,
+                _args_marker: PhantomData,
+            },
+            header_init: Once::new(),
         }
-        invoke::<Self, Ret> as _
+    }
+
+    /// Pointer to pass to Objective-C code expecting a block.
+    pub fn as_ptr(&'static self) -> *const BlockHeader {
+        self.header_init.call_once(|| {
+            let header = unsafe { &mut *self.inner.header.get() };
+            header.isa = global_block_class();
+            // Global blocks are never copied or disposed, but the helpers (doing nothing) must be there for the signature.
+            header.flags = BLOCK_IS_GLOBAL | block_flags::<F, Args, Ret>();
+            header.invoke = F::invoker();
+            header.descriptor = descriptor(
+                std::mem::size_of::<InnerBlock<F, Args, Ret>>(),
+                no_copy,
+                no_dispose,
+                F::signature(),
+            );
+        });
+        // From the whole block, as the invoker also reads what follows the header.
+        &self.inner as *const InnerBlock<F, Args, Ret> as *const BlockHeader
     }
 }
 
-impl<F, Ret, Arg1> BlockInvoke<(Arg1,), Ret> for F
+/// Defines a `static` `GlobalBlock` from a closure that does not capture anything:
+///
+/// ```ignore
+/// global_block!(static ADD: |a: i32, b: i32| -> i32 { a + b });
+/// ```
+///
+/// or from a function:
+///
+/// ```ignore
+/// global_block!(pub static COMPARE: fn(RawObjPtr, RawObjPtr) -> NSComparisonResult = compare);
+/// ```
+#[macro_export]
+macro_rules! global_block {
+    // `||` is a single token, so closures without arguments need their own rule.
+    ($(#[$attr:meta])* $vis:vis static $name:ident: || $(-> $ret:ty)? $body:block) => {
+        $crate::global_block!($(#[$attr])* $vis static $name: fn() $(-> $ret)? = || $body);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: |$($arg:ident: $arg_ty:ty),* $(,)?| $(-> $ret:ty)? $body:block) => {
+        $crate::global_block!(
+            $(#[$attr])* $vis static $name: fn($($arg_ty),*) $(-> $ret)? = |$($arg: $arg_ty),*| $body
+        );
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: fn($($arg_ty:ty),* $(,)?) $(-> $ret:ty)? = $f:expr) => {
+        $(#[$attr])*
+        $vis static $name: $crate::base::block::GlobalBlock<
+            fn($($arg_ty),*) $(-> $ret)?,
+            ($($arg_ty,)*),
+            $crate::global_block!(@ret $($ret)?),
+        > = $crate::base::block::GlobalBlock::new($f);
+    };
+    (@ret) => { () };
+    (@ret $ret:ty) => { $ret };
+}
+
+/// Block calling a `FnMut` closure.
+///
+/// As the block can be called from any thread, calls are serialized by a lock,
+/// so the closure must not call the block itself (directly or not), or it would deadlock.
+pub struct MutBlock<F, Args, Ret>
 where
-    F: Fn(Arg1) -> Ret,
+    MutFn<F>: BlockInvoke<Args, Ret> + Send + 'static,
 {
-    fn invoker() -> *const c_void {
-        unsafe extern "C" fn invoke<F, Arg1, Ret>(
-            block: &InnerBlock<F, (Arg1,), Ret>,
-            arg1: Arg1,
-        ) -> Ret
-        where
-            F: Fn(Arg1) -> Ret,
-        {
-            (block.f)(arg1)
+    heap_block: HeapBlock<MutFn<F>, Args, Ret>,
+}
+
+impl<F, Args, Ret> MutBlock<F, Args, Ret>
+where
+    MutFn<F>: BlockInvoke<Args, Ret> + Send + 'static,
+{
+    pub fn new(f: F) -> Self {
+        Self {
+            heap_block: HeapBlock::new(MutFn(Mutex::new(f))),
         }
-        invoke::<Self, Arg1, Ret> as _
+    }
+
+    /// # Safety
+    /// You must be sure the internal ref count doesn't get to zero while the reference is alive.
+    pub unsafe fn block_ref(&self) -> &std::cell::UnsafeCell<BlockHeader> {
+        self.heap_block.block_ref()
+    }
+
+    /// Same as `HeapBlock::as_ptr`.
+    pub fn as_ptr(&self) -> *const BlockHeader {
+        self.heap_block.as_ptr()
     }
 }
 
-impl<F, Ret, Arg1, Arg2> BlockInvoke<(Arg1, Arg2), Ret> for F
+impl<F, Args, Ret> Clone for MutBlock<F, Args, Ret>
 where
-    F: Fn(Arg1, Arg2) -> Ret,
+    MutFn<F>: BlockInvoke<Args, Ret> + Send + Sync + 'static,
 {
-    fn invoker() -> *const c_void {
-        unsafe extern "C" fn invoke<F, Arg1, Arg2, Ret>(
-            block: &InnerBlock<F, (Arg1, Arg2), Ret>,
-            arg1: Arg1,
-            arg2: Arg2,
-        ) -> Ret
-        where
-            F: Fn(Arg1, Arg2) -> Ret,
-        {
-            (block.f)(arg1, arg2)
+    /// The clone shares the same closure.
+    fn clone(&self) -> Self {
+        Self {
+            heap_block: self.heap_block.clone(),
+        }
+    }
+}
+
+/// Block calling a `FnOnce` closure, for example for a completion handler that sends its result through a channel.
+///
+/// The closure is dropped after having been called, or with the block if it never is.
+/// Calling the block a second time panics, which aborts the process as panics can't unwind out of a block.
+pub struct OnceBlock<F, Args, Ret>
+where
+    OnceFn<F>: BlockInvoke<Args, Ret> + Send + 'static,
+{
+    heap_block: HeapBlock<OnceFn<F>, Args, Ret>,
+}
+
+impl<F, Args, Ret> OnceBlock<F, Args, Ret>
+where
+    OnceFn<F>: BlockInvoke<Args, Ret> + Send + 'static,
+{
+    pub fn new(f: F) -> Self {
+        Self {
+            heap_block: HeapBlock::new(OnceFn(Mutex::new(Some(f)))),
         }
-        invoke::<Self, Arg1, Arg2, Ret> as _
+    }
+
+    /// # Safety
+    /// You must be sure the internal ref count doesn't get to zero while the reference is alive.
+    pub unsafe fn block_ref(&self) -> &std::cell::UnsafeCell<BlockHeader> {
+        self.heap_block.block_ref()
+    }
+
+    /// Same as `HeapBlock::as_ptr`.
+    pub fn as_ptr(&self) -> *const BlockHeader {
+        self.heap_block.as_ptr()
     }
 }
 
-impl<F, Ret, Arg1, Arg2, Arg3> BlockInvoke<(Arg1, Arg2, Arg3), Ret> for F
+impl<F, Args, Ret> Clone for OnceBlock<F, Args, Ret>
 where
-    F: Fn(Arg1, Arg2, Arg3) -> Ret,
+    OnceFn<F>: BlockInvoke<Args, Ret> + Send + Sync + 'static,
 {
-    fn invoker() -> *const c_void {
-        unsafe extern "C" fn invoke<F, Arg1, Arg2, Arg3, Ret>(
-            block: &InnerBlock<F, (Arg1, Arg2, Arg3), Ret>,
-            arg1: Arg1,
-            arg2: Arg2,
-            arg3: Arg3,
-        ) -> Ret
+    /// The clone shares the same closure, so only one of them can be called.
+    fn clone(&self) -> Self {
+        Self {
+            heap_block: self.heap_block.clone(),
+        }
+    }
+}
+
+/// A `FnMut` closure as called by a `MutBlock`.
+pub struct MutFn<F>(Mutex<F>);
+
+/// A `FnOnce` closure as called by a `OnceBlock`, `None` once called.
+pub struct OnceFn<F>(Mutex<Option<F>>);
+
+pub trait BlockInvoke<Args, Ret> {
+    fn invoker() -> *const c_void;
+    fn signature() -> &'static CStr;
+    fn use_stret() -> bool;
+}
+
+// The implementations for `MutFn` and `OnceFn` do not conflict with the one for `Fn` closures
+// as the `Fn` traits are `#[fundamental]`: the compiler knows these types do not implement them.
+macro_rules! impl_block_invoke {
+    ($($arg:ident $name:ident),*) => {
+        impl<F, Ret, $($arg),*> BlockInvoke<($($arg,)*), Ret> for F
         where
-            F: Fn(Arg1, Arg2, Arg3) -> Ret,
+            F: Fn($($arg),*) -> Ret,
+            Ret: Encode,
+            $($arg: Encode,)*
         {
-            (block.f)(arg1, arg2, arg3)
+            fn signature() -> &'static CStr {
+                signature::<($($arg,)*), Ret>()
+            }
+
+            fn use_stret() -> bool {
+                use_stret::<Ret>()
+            }
+
+            fn invoker() -> *const c_void {
+                unsafe extern "C" fn invoke<F, Ret, $($arg),*>(
+                    block: &InnerBlock<F, ($($arg,)*), Ret>,
+                    $($name: $arg),*
+                ) -> Ret
+                where
+                    F: Fn($($arg),*) -> Ret,
+                    Ret: Encode,
+                    $($arg: Encode,)*
+                {
+                    (block.f)($($name),*)
+                }
+                invoke::<Self, Ret, $($arg),*> as _
+            }
         }
-        invoke::<Self, Arg1, Arg2, Arg3, Ret> as _
-    }
+
+        impl<F, Ret, $($arg),*> BlockInvoke<($($arg,)*), Ret> for MutFn<F>
+        where
+            F: FnMut($($arg),*) -> Ret,
+            Ret: Encode,
+            $($arg: Encode,)*
+        {
+            fn signature() -> &'static CStr {
+                signature::<($($arg,)*), Ret>()
+            }
+
+            fn use_stret() -> bool {
+                use_stret::<Ret>()
+            }
+
+            fn invoker() -> *const c_void {
+                unsafe extern "C" fn invoke<F, Ret, $($arg),*>(
+                    block: &InnerBlock<MutFn<F>, ($($arg,)*), Ret>,
+                    $($name: $arg),*
+                ) -> Ret
+                where
+                    F: FnMut($($arg),*) -> Ret,
+                    Ret: Encode,
+                    $($arg: Encode,)*
+                {
+                    // A panic while calling the closure aborts the process, so the lock can't be poisoned.
+                    let mut f = block.f.0.lock().unwrap();
+                    (f)($($name),*)
+                }
+                invoke::<F, Ret, $($arg),*> as _
+            }
+        }
+
+        impl<F, Ret, $($arg),*> BlockInvoke<($($arg,)*), Ret> for OnceFn<F>
+        where
+            F: FnOnce($($arg),*) -> Ret,
+            Ret: Encode,
+            $($arg: Encode,)*
+        {
+            fn signature() -> &'static CStr {
+                signature::<($($arg,)*), Ret>()
+            }
+
+            fn use_stret() -> bool {
+                use_stret::<Ret>()
+            }
+
+            fn invoker() -> *const c_void {
+                unsafe extern "C" fn invoke<F, Ret, $($arg),*>(
+                    block: &InnerBlock<OnceFn<F>, ($($arg,)*), Ret>,
+                    $($name: $arg),*
+                ) -> Ret
+                where
+                    F: FnOnce($($arg),*) -> Ret,
+                    Ret: Encode,
+                    $($arg: Encode,)*
+                {
+                    // The lock is released before calling the closure, which can then drop the block.
+                    let f = block.f.0.lock().unwrap().take();
+                    let f = f.expect("a OnceBlock can only be called once");
+                    f($($name),*)
+                }
+                invoke::<F, Ret, $($arg),*> as _
+            }
+        }
+    };
 }
 
-/// A type erased block.
+impl_block_invoke!();
+impl_block_invoke!(A1 a1);
+impl_block_invoke!(A1 a1, A2 a2);
+impl_block_invoke!(A1 a1, A2 a2, A3 a3);
+impl_block_invoke!(A1 a1, A2 a2, A3 a3, A4 a4);
+impl_block_invoke!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5);
+impl_block_invoke!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6);
+impl_block_invoke!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7);
+impl_block_invoke!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8);
+impl_block_invoke!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8, A9 a9);
+impl_block_invoke!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8, A9 a9, A10 a10);
+impl_block_invoke!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8, A9 a9, A10 a10, A11 a11);
+impl_block_invoke!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8, A9 a9, A10 a10, A11 a11, A12 a12);
+
+/// A type erased block taking `Args` (as a tuple) and returning `Ret`.
 ///
-/// It can reference either a stack or heap block.
-struct Block<'a, Args, Ret> {
+/// It can reference either a stack block, valid for `'a`, or a heap block we own a reference to,
+/// be it one of ours or one received from Objective-C code.
+pub struct Block<'a, Args, Ret> {
     ptr: *const c_void,
     /// Whether we own the block or not. We always own heap blocks, and never stack block.
     owning_ptr: bool,
@@ -322,7 +698,7 @@ struct Block<'a, Args, Ret> {
 }
 
 impl<'a, Args, Ret> Block<'a, Args, Ret> {
-    fn from_stack<F>(stack_block: &'a StackBlock<F, Args, Ret>) -> Self
+    pub fn from_stack<F>(stack_block: &'a StackBlock<F, Args, Ret>) -> Self
     where
         F: BlockInvoke<Args, Ret> + Clone + Send + Sync + 'static,
     {
@@ -335,12 +711,15 @@ impl<'a, Args, Ret> Block<'a, Args, Ret> {
         }
     }
 
-    fn into_owned(self) -> Block<'static, Args, Ret> {
+    /// Copies the block to the heap if it is not there yet, so that it can be kept as long as needed.
+    pub fn into_owned(self) -> Block<'static, Args, Ret> {
         let ptr = if self.owning_ptr {
             self.ptr
         } else {
             unsafe { _Block_copy(self.ptr) }
         };
+        // The reference we had (if any) now belongs to the new `Block`.
+        std::mem::forget(self);
         Block {
             ptr,
             owning_ptr: true,
@@ -349,12 +728,25 @@ impl<'a, Args, Ret> Block<'a, Args, Ret> {
             _args_marker: PhantomData,
         }
     }
+
+    /// Pointer to pass to Objective-C code expecting a block.
+    pub fn as_ptr(&self) -> *const BlockHeader {
+        self.ptr as *const BlockHeader
+    }
+
+    /// Calls the block, the arguments being given as a tuple.
+    pub fn call(&self, args: Args) -> Ret
+    where
+        Args: BlockArguments,
+    {
+        unsafe { Args::call_block(self.as_ptr(), args) }
+    }
 }
 
 impl<Args, Ret> Block<'static, Args, Ret> {
-    fn from_heap<F>(heap_block: HeapBlock<F, Args, Ret>) -> Self
+    pub fn from_heap<F>(heap_block: HeapBlock<F, Args, Ret>) -> Self
     where
-        F: BlockInvoke<Args, Ret> + Clone + Send + Sync + 'static,
+        F: BlockInvoke<Args, Ret> + Send + 'static,
     {
         let block = Block {
             ptr: heap_block.ptr as _,
@@ -367,6 +759,96 @@ impl<Args, Ret> Block<'static, Args, Ret> {
         std::mem::forget(heap_block);
         block
     }
+
+    /// Wraps a block received from Objective-C code, returning `None` for `nil`.
+    ///
+    /// The block is copied to the heap if it was on the stack, or just retained if it was already there,
+    /// so that it can be kept after the function that received it has returned.
+    ///
+    /// # Safety
+    /// `ptr` must point to a valid block taking `Args` and returning `Ret`.
+    pub unsafe fn copy_from_raw(ptr: *const BlockHeader) -> Option<Self> {
+        if ptr.is_null() {
+            return None;
+        }
+        Some(Block {
+            ptr: _Block_copy(ptr as *const c_void),
+            owning_ptr: true,
+            _lifetime_marker: PhantomData,
+            _ret_marker: PhantomData,
+            _args_marker: PhantomData,
+        })
+    }
+}
+
+impl<'a, Args, Ret> Clone for Block<'a, Args, Ret> {
+    fn clone(&self) -> Self {
+        let ptr = if self.owning_ptr {
+            // The block is on the heap (or global) so that only increases its reference count.
+            unsafe { _Block_copy(self.ptr) }
+        } else {
+            self.ptr
+        };
+        Block {
+            ptr,
+            owning_ptr: self.owning_ptr,
+            _lifetime_marker: PhantomData,
+            _ret_marker: PhantomData,
+            _args_marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, F, Args, Ret> From<&'a StackBlock<F, Args, Ret>> for Block<'a, Args, Ret>
+where
+    F: BlockInvoke<Args, Ret> + Clone + Send + Sync + 'static,
+{
+    fn from(stack_block: &'a StackBlock<F, Args, Ret>) -> Self {
+        Block::from_stack(stack_block)
+    }
+}
+
+impl<F, Args, Ret> From<HeapBlock<F, Args, Ret>> for Block<'static, Args, Ret>
+where
+    F: BlockInvoke<Args, Ret> + Send + 'static,
+{
+    fn from(heap_block: HeapBlock<F, Args, Ret>) -> Self {
+        Block::from_heap(heap_block)
+    }
+}
+
+impl<F, Args, Ret> From<&'static GlobalBlock<F, Args, Ret>> for Block<'static, Args, Ret>
+where
+    F: BlockInvoke<Args, Ret> + Sync + 'static,
+{
+    fn from(global_block: &'static GlobalBlock<F, Args, Ret>) -> Self {
+        // Global blocks do not need to be owned, the reference is always valid.
+        Block {
+            ptr: global_block.as_ptr() as _,
+            owning_ptr: false,
+            _lifetime_marker: PhantomData,
+            _ret_marker: PhantomData,
+            _args_marker: PhantomData,
+        }
+    }
+}
+
+impl<F, Args, Ret> From<MutBlock<F, Args, Ret>> for Block<'static, Args, Ret>
+where
+    MutFn<F>: BlockInvoke<Args, Ret> + Send + 'static,
+{
+    fn from(mut_block: MutBlock<F, Args, Ret>) -> Self {
+        Block::from_heap(mut_block.heap_block)
+    }
+}
+
+impl<F, Args, Ret> From<OnceBlock<F, Args, Ret>> for Block<'static, Args, Ret>
+where
+    OnceFn<F>: BlockInvoke<Args, Ret> + Send + 'static,
+{
+    fn from(once_block: OnceBlock<F, Args, Ret>) -> Self {
+        Block::from_heap(once_block.heap_block)
+    }
 }
 
 impl<'a, Args, Ret> Drop for Block<'a, Args, Ret> {
@@ -376,3 +858,737 @@ impl<'a, Args, Ret> Drop for Block<'a, Args, Ret> {
         }
     }
 }
+
+/// Tuples of arguments a block can be called with.
+///
+/// # Safety
+/// `call_block` must call the block's `invoke` with the C ABI, the block first then each element of the tuple.
+pub unsafe trait BlockArguments: Sized {
+    /// # Safety
+    /// `block` must point to a valid block taking these arguments and returning `Ret`.
+    unsafe fn call_block<Ret>(block: *const BlockHeader, args: Self) -> Ret;
+}
+
+macro_rules! impl_block_arguments {
+    ($($arg:ident $name:ident),*) => {
+        unsafe impl<$($arg),*> BlockArguments for ($($arg,)*) {
+            unsafe fn call_block<Ret>(block: *const BlockHeader, args: Self) -> Ret {
+                let ($($name,)*) = args;
+                let invoke: unsafe extern "C" fn(*const BlockHeader $(, $arg)*) -> Ret =
+                    std::mem::transmute((*block).invoke);
+                invoke(block $(, $name)*)
+            }
+        }
+    };
+}
+
+impl_block_arguments!();
+impl_block_arguments!(A1 a1);
+impl_block_arguments!(A1 a1, A2 a2);
+impl_block_arguments!(A1 a1, A2 a2, A3 a3);
+impl_block_arguments!(A1 a1, A2 a2, A3 a3, A4 a4);
+impl_block_arguments!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5);
+impl_block_arguments!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6);
+impl_block_arguments!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7);
+impl_block_arguments!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8);
+impl_block_arguments!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8, A9 a9);
+impl_block_arguments!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8, A9 a9, A10 a10);
+impl_block_arguments!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8, A9 a9, A10 a10, A11 a11);
+impl_block_arguments!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8, A9 a9, A10 a10, A11 a11, A12 a12);
+
+/// Indicates that the `__block` variable has keep and destroy helpers.
+const BLOCK_BYREF_HAS_COPY_DISPOSE: c_int = 1 << 25;
+/// `flags` of `_Block_object_assign` and `_Block_object_dispose` for a `__block` variable.
+const BLOCK_FIELD_IS_BYREF: c_int = 8;
+
+/// Layout of a `__block` variable with helpers, as clang generates it.
+#[repr(C)]
+struct ByRefStorage<T> {
+    isa: *const c_void,
+    /// Points to the variable itself while it is on the stack, and to the heap copy once there is one.
+    forwarding: *mut ByRefStorage<T>,
+    flags: c_int,
+    /// Size of the whole structure.
+    size: u32,
+    /// Moves the variable from the stack to the heap, called with the destination first.
+    keep: unsafe extern "C" fn(*mut ByRefStorage<T>, *mut ByRefStorage<T>),
+    /// Destroys the variable once on the heap, just before it gets freed.
+    destroy: unsafe extern "C" fn(*mut ByRefStorage<T>),
+    value: T,
+}
+
+/// Types that can be kept in a `ByRef`, whose content can change while shared references to it exist.
+///
+/// Implemented for the atomic types, that have the same layout as the corresponding C types:
+/// a `ByRef<AtomicBool>` can be used where Objective-C code would use a `__block BOOL`.
+///
+/// # Safety
+/// All of the content of the type must be in an `UnsafeCell`,
+/// so that a `&Self` stays valid while Objective-C code writes the value through `ByRef::as_ptr`.
+pub unsafe trait ByRefValue {}
+
+macro_rules! impl_byref_value {
+    ($($width:literal: $($atomic:ident),*;)*) => {
+        $($(
+            #[cfg(target_has_atomic = $width)]
+            unsafe impl ByRefValue for std::sync::atomic::$atomic {}
+        )*)*
+    };
+}
+
+impl_byref_value! {
+    "8": AtomicBool, AtomicI8, AtomicU8;
+    "16": AtomicI16, AtomicU16;
+    "32": AtomicI32, AtomicU32;
+    "64": AtomicI64, AtomicU64;
+    "ptr": AtomicIsize, AtomicUsize;
+}
+
+#[cfg(target_has_atomic = "ptr")]
+unsafe impl<T> ByRefValue for std::sync::atomic::AtomicPtr<T> {}
+
+/// Equivalent of a `__block` variable: storage shared by the blocks using it and the code that created it.
+///
+/// The storage lives on the heap and is reference counted by the blocks runtime, cloning a `ByRef` giving another
+/// reference to the same storage. It follows the `Block_byref` layout so `as_ptr` can be given to Objective-C code,
+/// and Rust code mutates it through interior mutability, for example with `ByRef<AtomicBool>`.
+pub struct ByRef<T> {
+    /// Always the heap copy.
+    ptr: *mut ByRefStorage<T>,
+}
+
+// Sharing a `ByRef` is sharing a `T`.
+unsafe impl<T: Send + Sync> Send for ByRef<T> {}
+unsafe impl<T: Send + Sync> Sync for ByRef<T> {}
+
+impl<T: ByRefValue> ByRef<T> {
+    unsafe extern "C" fn keep(dst: *mut ByRefStorage<T>, src: *mut ByRefStorage<T>) {
+        // The variable on the stack is forgotten once copied, so it can just be moved.
+        std::ptr::copy_nonoverlapping(
+            std::ptr::addr_of!((*src).value),
+            std::ptr::addr_of_mut!((*dst).value),
+            1,
+        );
+    }
+
+    unsafe extern "C" fn destroy(byref: *mut ByRefStorage<T>) {
+        std::ptr::drop_in_place(std::ptr::addr_of_mut!((*byref).value));
+    }
+
+    pub fn new(value: T) -> Self {
+        // The heap copy is allocated by the blocks runtime, which only guarantees the alignment of `malloc`.
+        const {
+            assert!(std::mem::align_of::<T>() <= 16);
+            assert!(std::mem::size_of::<ByRefStorage<T>>() <= u32::MAX as usize);
+        }
+        // The variable is created on the stack the same way clang does, and then copied to the heap by the runtime.
+        let mut stack = ByRefStorage {
+            isa: std::ptr::null(),
+            forwarding: std::ptr::null_mut(),
+            flags: BLOCK_BYREF_HAS_COPY_DISPOSE,
+            size: std::mem::size_of::<ByRefStorage<T>>() as u32,
+            keep: Self::keep,
+            destroy: Self::destroy,
+            value,
+        };
+        let stack_ptr = std::ptr::addr_of_mut!(stack);
+        let mut ptr: *mut ByRefStorage<T> = std::ptr::null_mut();
+        unsafe {
+            (*stack_ptr).forwarding = stack_ptr;
+            _Block_object_assign(
+                std::ptr::addr_of_mut!(ptr) as *mut c_void,
+                stack_ptr as *const c_void,
+                BLOCK_FIELD_IS_BYREF,
+            );
+            // The stack variable goes out of scope, only the reference we got stays.
+            _Block_object_dispose(stack_ptr as *const c_void, BLOCK_FIELD_IS_BYREF);
+        }
+        // Its value was moved to the heap by `keep`.
+        std::mem::forget(stack);
+        Self { ptr }
+    }
+
+    /// Pointer to the `__block` variable itself (not to its value), as captured by blocks.
+    pub fn as_byref_ptr(&self) -> *mut c_void {
+        self.ptr as *mut c_void
+    }
+
+    /// Pointer to the value, that Objective-C code can read or write.
+    ///
+    /// Objective-C code must use atomic accesses if Rust code can access the value at the same time.
+    pub fn as_ptr(&self) -> *mut T {
+        unsafe { std::ptr::addr_of_mut!((*(*self.ptr).forwarding).value) }
+    }
+}
+
+// `T` being `ByRefValue`, writes through `as_ptr` do not invalidate the reference.
+impl<T: ByRefValue> std::ops::Deref for ByRef<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.as_ptr() }
+    }
+}
+
+impl<T> Clone for ByRef<T> {
+    fn clone(&self) -> Self {
+        // What the copy helper of a block capturing the variable does.
+        let mut ptr: *mut ByRefStorage<T> = std::ptr::null_mut();
+        unsafe {
+            _Block_object_assign(
+                std::ptr::addr_of_mut!(ptr) as *mut c_void,
+                self.ptr as *const c_void,
+                BLOCK_FIELD_IS_BYREF,
+            )
+        };
+        Self { ptr }
+    }
+}
+
+impl<T> Drop for ByRef<T> {
+    fn drop(&mut self) {
+        unsafe { _Block_object_dispose(self.ptr as *const c_void, BLOCK_FIELD_IS_BYREF) };
+    }
+}
+
+#[cfg(test)]
+mod block_tests {
+    use super::*;
+    use crate::base::{RawObjPtr, BOOL};
+    use std::mem::offset_of;
+
+    #[test]
+    fn layout() {
+        let pointer_size = std::mem::size_of::<*const c_void>();
+        assert_eq!(offset_of!(BlockHeader, isa), 0);
+        assert_eq!(offset_of!(BlockHeader, flags), pointer_size);
+        assert_eq!(offset_of!(BlockHeader, _reserved), pointer_size + 4);
+        assert_eq!(offset_of!(BlockHeader, invoke), pointer_size + 8);
+        assert_eq!(offset_of!(BlockHeader, descriptor), 2 * pointer_size + 8);
+        assert_eq!(offset_of!(BlockDescriptor, _reserved), 0);
+        assert_eq!(offset_of!(BlockDescriptor, block_size), pointer_size);
+        assert_eq!(offset_of!(BlockDescriptor, copy), 2 * pointer_size);
+        assert_eq!(offset_of!(BlockDescriptor, dispose), 3 * pointer_size);
+        assert_eq!(offset_of!(BlockDescriptor, signature), 4 * pointer_size);
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn signatures() {
+        let text = |s: &'static CStr| s.to_str().unwrap();
+        assert_eq!(text(signature::<(), ()>()), "v8@?0");
+        assert_eq!(text(signature::<(RawObjPtr, usize), ()>()), "v24@?0@8Q16");
+        // Small integers take the space of an int.
+        assert_eq!(
+            text(signature::<(u8, i16, BOOL, f32), f64>()),
+            format!("d24@?0C8s12{}16f20", BOOL::encoding())
+        );
+        assert_eq!(
+            text(signature::<(*mut BOOL,), RawObjPtr>()),
+            format!("@16@?0^{}8", BOOL::encoding())
+        );
+        // Signatures are only built once.
+        assert_eq!(
+            signature::<(RawObjPtr, usize), ()>().as_ptr(),
+            signature::<(RawObjPtr, usize), ()>().as_ptr()
+        );
+    }
+
+    #[cfg(all(feature = "blocks-runtime", target_pointer_width = "64"))]
+    #[test]
+    fn descriptors() {
+        let make_block = |x: i32| StackBlock::new(move |a: i32, _b: RawObjPtr| (a + x) as f64);
+        let block = make_block(1);
+        let header = unsafe { &*block.block_ref().get() };
+        assert_eq!(header.flags, BLOCK_HAS_COPY_DISPOSE | BLOCK_HAS_SIGNATURE);
+        let descriptor = header.descriptor;
+        assert_eq!(
+            descriptor.block_size as usize,
+            std::mem::size_of_val(&block)
+        );
+        let signature = unsafe { CStr::from_ptr(descriptor.signature) };
+        assert_eq!(signature.to_str().unwrap(), "d20@?0i8@12");
+
+        // Blocks of the same type share the same descriptor,
+        // except under Miri where each use of a function can get a different address.
+        let other_block = make_block(2);
+        let other_header = unsafe { &*other_block.block_ref().get() };
+        let descriptor = other_header.descriptor;
+        assert!(cfg!(miri) || std::ptr::eq(descriptor, header.descriptor));
+
+        // The signature stays when copied to the heap.
+        let heap_block: HeapBlock<_, (i32, RawObjPtr), f64> = other_block.into();
+        let heap_header = unsafe { &*heap_block.block_ref().get() };
+        assert_eq!(heap_header.flags & BLOCK_HAS_SIGNATURE, BLOCK_HAS_SIGNATURE);
+        assert!(std::ptr::eq(heap_header.descriptor, descriptor));
+    }
+
+    /// Calls a block taking one argument, the way C code would.
+    #[cfg(feature = "blocks-runtime")]
+    unsafe fn call<Arg, Ret>(block: *const c_void, arg: Arg) -> Ret {
+        let header = &*(block as *const BlockHeader);
+        let invoke: unsafe extern "C" fn(*const c_void, Arg) -> Ret =
+            std::mem::transmute(header.invoke);
+        invoke(block, arg)
+    }
+
+    #[cfg(feature = "blocks-runtime")]
+    #[test]
+    fn stack_and_heap_blocks() {
+        use std::sync::Arc;
+
+        let captured = Arc::new(10);
+        let f = {
+            let captured = captured.clone();
+            move |x: i32| x + *captured
+        };
+        let stack_block = StackBlock::new(f);
+        let stack_ptr = stack_block.as_ptr() as *const c_void;
+        assert_eq!(unsafe { call::<i32, i32>(stack_ptr, 1) }, 11);
+        assert_eq!(Arc::strong_count(&captured), 2);
+
+        // The copy to the heap clones the closure, and the stack version gets dropped.
+        let heap_block: HeapBlock<_, (i32,), i32> = stack_block.into();
+        assert_eq!(Arc::strong_count(&captured), 2);
+        let heap_ptr = heap_block.as_ptr() as *const c_void;
+        assert_ne!(heap_ptr, stack_ptr);
+        let header = unsafe { &*heap_block.block_ref().get() };
+        assert_eq!(
+            header.flags & BLOCK_HAS_COPY_DISPOSE,
+            BLOCK_HAS_COPY_DISPOSE
+        );
+        assert_eq!(unsafe { call::<i32, i32>(heap_ptr, 2) }, 12);
+
+        // Cloning a heap block only increments its reference count.
+        let heap_block2 = heap_block.clone();
+        assert_eq!(heap_block2.as_ptr() as *const c_void, heap_ptr);
+        assert_eq!(Arc::strong_count(&captured), 2);
+        drop(heap_block);
+        assert_eq!(unsafe { call::<i32, i32>(heap_ptr, 3) }, 13);
+        drop(heap_block2);
+        assert_eq!(Arc::strong_count(&captured), 1);
+
+        // A heap block created directly never clones the closure.
+        let captured_clone = captured.clone();
+        let heap_block = HeapBlock::new(move |x: i32| x * *captured_clone);
+        assert_eq!(Arc::strong_count(&captured), 2);
+        let heap_ptr = heap_block.as_ptr() as *const c_void;
+        assert_eq!(unsafe { call::<i32, i32>(heap_ptr, 3) }, 30);
+        drop(heap_block);
+        assert_eq!(Arc::strong_count(&captured), 1);
+    }
+
+    #[cfg(feature = "blocks-runtime")]
+    #[test]
+    fn into_owned() {
+        use std::sync::Arc;
+
+        let captured = Arc::new(());
+        let owned = {
+            let captured_clone = captured.clone();
+            let stack_block = StackBlock::new(move |x: u64| {
+                let _ = &captured_clone;
+                x + 1
+            });
+            let block = Block::from_stack(&stack_block);
+            assert!(!block.owning_ptr);
+            // The block is copied to the heap, so it can outlive the stack block.
+            let owned = block.into_owned();
+            assert_ne!(owned.ptr, &stack_block as *const _ as *const c_void);
+            assert_eq!(Arc::strong_count(&captured), 3);
+            owned
+        };
+        assert_eq!(Arc::strong_count(&captured), 2);
+        assert!(owned.owning_ptr);
+        assert_eq!(unsafe { call::<u64, u64>(owned.ptr, 1) }, 2);
+
+        // Already owned blocks are not copied again.
+        let ptr = owned.ptr;
+        let owned = owned.into_owned();
+        assert_eq!(owned.ptr, ptr);
+        drop(owned);
+        assert_eq!(Arc::strong_count(&captured), 1);
+
+        let captured_clone = captured.clone();
+        let heap_block = HeapBlock::new(move |x: u64| {
+            let _ = &captured_clone;
+            x + 2
+        });
+        let block = Block::from_heap(heap_block);
+        assert!(block.owning_ptr);
+        assert_eq!(unsafe { call::<u64, u64>(block.ptr, 1) }, 3);
+        drop(block);
+        assert_eq!(Arc::strong_count(&captured), 1);
+    }
+
+    /// A block the way clang would create it for `^(long long a1, ..., long long a12) { return captured + a1 + ... + a12; }`.
+    #[cfg(feature = "blocks-runtime")]
+    #[repr(C)]
+    struct ForeignBlock {
+        header: BlockHeader,
+        captured: i64,
+    }
+
+    #[cfg(feature = "blocks-runtime")]
+    #[allow(clippy::too_many_arguments)]
+    unsafe extern "C" fn foreign_invoke(
+        block: *const ForeignBlock,
+        a1: i64,
+        a2: i64,
+        a3: i64,
+        a4: i64,
+        a5: i64,
+        a6: i64,
+        a7: i64,
+        a8: i64,
+        a9: i64,
+        a10: i64,
+        a11: i64,
+        a12: i64,
+    ) -> i64 {
+        (*block).captured + a1 + a2 + a3 + a4 + a5 + a6 + a7 + a8 + a9 + a10 + a11 + a12
+    }
+
+    #[cfg(feature = "blocks-runtime")]
+    unsafe extern "C" fn unused_copy(_dst: *mut c_void, _src: *const c_void) {
+        unreachable!("the block does not have BLOCK_HAS_COPY_DISPOSE")
+    }
+
+    #[cfg(feature = "blocks-runtime")]
+    unsafe extern "C" fn unused_dispose(_src: *mut c_void) {
+        unreachable!("the block does not have BLOCK_HAS_COPY_DISPOSE")
+    }
+
+    #[cfg(feature = "blocks-runtime")]
+    #[test]
+    fn foreign_blocks() {
+        type Args = (i64, i64, i64, i64, i64, i64, i64, i64, i64, i64, i64, i64);
+
+        assert!(unsafe { Block::<(), ()>::copy_from_raw(std::ptr::null()) }.is_none());
+
+        let owned = {
+            let mut foreign = ForeignBlock {
+                header: BlockHeader {
+                    isa: stack_block_class(),
+                    flags: BLOCK_HAS_SIGNATURE,
+                    _reserved: 0,
+                    invoke: foreign_invoke as *const c_void,
+                    descriptor: descriptor(
+                        std::mem::size_of::<ForeignBlock>(),
+                        unused_copy,
+                        unused_dispose,
+                        signature::<Args, i64>(),
+                    ),
+                },
+                captured: 100,
+            };
+            let block: Block<'static, Args, i64> =
+                unsafe { Block::copy_from_raw(std::ptr::addr_of_mut!(foreign).cast()) }.unwrap();
+            assert_ne!(block.as_ptr(), &foreign.header as *const BlockHeader);
+            block
+        };
+        // The stack version is gone but the copy is still valid.
+        let args = (1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12);
+        assert_eq!(owned.call(args), 178);
+        let cloned = owned.clone();
+        assert_eq!(cloned.as_ptr(), owned.as_ptr());
+        drop(owned);
+        assert_eq!(cloned.call(args), 178);
+    }
+
+    #[cfg(feature = "blocks-runtime")]
+    #[test]
+    fn calling_blocks() {
+        let stack_block = StackBlock::new(|| 42u8);
+        assert_eq!(Block::from(&stack_block).call(()), 42);
+
+        let stack_block = StackBlock::new(|a: i32, b: f64, c: u8| a as f64 * b + c as f64);
+        let block = Block::from(&stack_block);
+        assert_eq!(block.call((2, 1.5, 1)), 4.);
+        // Borrowed blocks are not copied when cloned.
+        assert_eq!(block.clone().as_ptr(), block.as_ptr());
+
+        let heap_block = HeapBlock::new(|a: u64, b: u64| a - b);
+        let block = Block::from(heap_block);
+        assert_eq!(block.call((10, 3)), 7);
+        // Already owned, so it is the same block.
+        let ptr = block.as_ptr();
+        let owned = block.into_owned();
+        assert_eq!(owned.as_ptr(), ptr);
+        assert_eq!(owned.call((10, 3)), 7);
+    }
+
+    #[cfg(feature = "blocks-runtime")]
+    #[test]
+    fn mut_blocks() {
+        let mut calls = Vec::new();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut_block = MutBlock::new(move |x: i32| {
+            calls.push(x);
+            sender.send(calls.clone()).unwrap();
+            calls.len()
+        });
+        let block = Block::from(mut_block.clone());
+        assert_eq!(block.call((1,)), 1);
+        assert_eq!(block.call((2,)), 2);
+        std::thread::scope(|scope| {
+            let ptr = block.as_ptr() as usize;
+            scope.spawn(move || {
+                let block: Block<'static, (i32,), usize> =
+                    unsafe { Block::copy_from_raw(ptr as *const BlockHeader) }.unwrap();
+                assert_eq!(block.call((3,)), 3);
+            });
+        });
+        drop(block);
+        drop(mut_block);
+        assert_eq!(
+            receiver.iter().collect::<Vec<_>>(),
+            vec![vec![1], vec![1, 2], vec![1, 2, 3]]
+        );
+    }
+
+    #[cfg(feature = "blocks-runtime")]
+    #[test]
+    fn once_blocks() {
+        use std::sync::Arc;
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let captured = Arc::new(());
+        let captured_clone = captured.clone();
+        let block = Block::from(OnceBlock::new(move |result: u64, error: BOOL| {
+            let _ = &captured_clone;
+            sender.send((result, bool::from(error))).unwrap();
+        }));
+        block.call((42, false.into()));
+        // The closure is dropped as soon as it has been called.
+        assert_eq!(Arc::strong_count(&captured), 1);
+        assert_eq!(receiver.recv().unwrap(), (42, false));
+        drop(block);
+
+        // Or with the block if it never is.
+        let captured_clone = captured.clone();
+        let once_block = OnceBlock::new(move || drop(captured_clone));
+        assert_eq!(Arc::strong_count(&captured), 2);
+        drop(once_block);
+        assert_eq!(Arc::strong_count(&captured), 1);
+    }
+
+    #[cfg(feature = "blocks-runtime")]
+    #[test]
+    fn many_arguments() {
+        let stack_block = StackBlock::new(
+            |a1: u8,
+             a2: i16,
+             a3: i32,
+             a4: i64,
+             a5: f32,
+             a6: f64,
+             a7: u8,
+             a8: u16,
+             a9: u32,
+             a10: u64,
+             a11: f64,
+             a12: isize| {
+                a1 as f64
+                    + a2 as f64
+                    + a3 as f64
+                    + a4 as f64
+                    + a5 as f64
+                    + a6
+                    + a7 as f64
+                    + a8 as f64
+                    + a9 as f64
+                    + a10 as f64
+                    + a11
+                    + a12 as f64
+            },
+        );
+        let block = Block::from(&stack_block);
+        assert_eq!(block.call((1, 2, 3, 4, 5., 6., 7, 8, 9, 10, 11., 12)), 78.);
+        let once_block = OnceBlock::new(
+            move |a1: u8,
+                  a2: u8,
+                  a3: u8,
+                  a4: u8,
+                  a5: u8,
+                  a6: u8,
+                  a7: u8,
+                  a8: u8,
+                  a9: u8,
+                  a10: u8,
+                  a11: u8,
+                  a12: u8| {
+                [a1, a2, a3, a4, a5, a6, a7, a8, a9, a10, a11, a12]
+                    .iter()
+                    .map(|&a| a as u32)
+                    .sum::<u32>()
+            },
+        );
+        let block = Block::from(once_block);
+        assert_eq!(block.call((1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12)), 78);
+    }
+
+    crate::global_block!(static ADD: |a: i32, b: i32| -> i32 { a + b });
+
+    fn is_positive(value: f64) -> BOOL {
+        (value > 0.).into()
+    }
+
+    crate::global_block!(
+        /// Doc comments can be given.
+        static IS_POSITIVE: fn(f64) -> BOOL = is_positive
+    );
+
+    crate::global_block!(static DO_NOTHING: || {});
+
+    #[cfg(feature = "blocks-runtime")]
+    #[test]
+    fn global_blocks() {
+        let block = Block::from(&ADD);
+        assert_eq!(block.call((1, 2)), 3);
+        let header = unsafe { &*ADD.as_ptr() };
+        assert_eq!(header.isa, global_block_class());
+        assert_eq!(header.flags & BLOCK_IS_GLOBAL, BLOCK_IS_GLOBAL);
+        assert_eq!(
+            unsafe { CStr::from_ptr(header.descriptor.signature) },
+            signature::<(i32, i32), i32>()
+        );
+
+        // Copying a global block does nothing.
+        let owned = block.into_owned();
+        assert_eq!(owned.as_ptr(), ADD.as_ptr());
+        let copied: Block<'static, (i32, i32), i32> =
+            unsafe { Block::copy_from_raw(ADD.as_ptr()) }.unwrap();
+        assert_eq!(copied.as_ptr(), ADD.as_ptr());
+        assert_eq!(copied.call((3, 4)), 7);
+        drop(owned);
+        drop(copied);
+        assert_eq!(Block::from(&ADD).call((5, 6)), 11);
+
+        assert!(bool::from(Block::from(&IS_POSITIVE).call((1.,))));
+        assert!(!bool::from(Block::from(&IS_POSITIVE).call((-1.,))));
+        Block::from(&DO_NOTHING).call(());
+    }
+
+    #[test]
+    fn stret() {
+        use crate::core_graphics::{CGPoint, CGRect, CGSize};
+        use crate::core_media::{CMTime, CMTimeRange};
+
+        assert!(!use_stret::<i64>());
+        assert!(!use_stret::<f64>());
+        // Up to 16 bytes, structures are returned in registers on x86_64,
+        // and with a 32-bit `CGFloat` (8 bytes) on Apple's x86.
+        assert_eq!(
+            use_stret::<CGPoint>(),
+            !cfg!(any(
+                target_arch = "x86_64",
+                target_arch = "aarch64",
+                all(target_arch = "x86", target_vendor = "apple")
+            ))
+        );
+        assert_eq!(use_stret::<CGRect>(), !cfg!(target_arch = "aarch64"));
+        assert_eq!(use_stret::<CMTime>(), !cfg!(target_arch = "aarch64"));
+        assert_eq!(use_stret::<CMTimeRange>(), !cfg!(target_arch = "aarch64"));
+
+        let stack_block = StackBlock::new(|x: f64, y: f64| CGRect {
+            origin: CGPoint {
+                x: x as _,
+                y: y as _,
+            },
+            size: CGSize {
+                width: 1.,
+                height: 2.,
+            },
+        });
+        let header = unsafe { &*stack_block.block_ref().get() };
+        let expected = if use_stret::<CGRect>() {
+            BLOCK_USE_STRET
+        } else {
+            0
+        };
+        assert_eq!(header.flags & BLOCK_USE_STRET, expected);
+        let stack_block = StackBlock::new(|| 42);
+        let header = unsafe { &*stack_block.block_ref().get() };
+        assert_eq!(header.flags & BLOCK_USE_STRET, 0);
+    }
+
+    #[cfg(feature = "blocks-runtime")]
+    #[test]
+    fn struct_returns() {
+        use crate::core_graphics::{CGPoint, CGRect, CGSize};
+        use crate::core_media::{CMTime, CMTimeRange};
+
+        let rect_block = HeapBlock::new(|x: f64, y: f64| CGRect {
+            origin: CGPoint {
+                x: x as _,
+                y: y as _,
+            },
+            size: CGSize {
+                width: 1.,
+                height: 2.,
+            },
+        });
+        let rect = Block::from(rect_block).call((3., 4.));
+        assert_eq!(rect.origin.x, 3.);
+        assert_eq!(rect.origin.y, 4.);
+        assert_eq!(rect.size.width, 1.);
+        assert_eq!(rect.size.height, 2.);
+
+        let time_block = StackBlock::new(|value: i64| CMTime::new(value, 600));
+        assert_eq!(Block::from(&time_block).call((1200,)).seconds(), 2.);
+
+        let range_block = StackBlock::new(|start: i64, duration: i64| CMTimeRange {
+            start: CMTime::new(start, 10),
+            duration: CMTime::new(duration, 10),
+        });
+        let range = Block::from(&range_block).into_owned().call((5, 20));
+        assert_eq!(range.start.seconds(), 0.5);
+        assert_eq!(range.duration.seconds(), 2.);
+    }
+
+    #[cfg(feature = "mock-runtime")]
+    #[test]
+    fn byref() {
+        use crate::base::mock_runtime::LeakCheck;
+        use std::sync::atomic::{AtomicI32, Ordering};
+        use std::sync::Arc;
+
+        let check = LeakCheck::start();
+        let counter = ByRef::new(AtomicI32::new(0));
+        let stack_block = StackBlock::new({
+            let counter = counter.clone();
+            move |x: i32| counter.fetch_add(x, Ordering::Relaxed) + x
+        });
+        assert_eq!(Block::from(&stack_block).call((1,)), 1);
+        // The heap copy of the block shares the same variable.
+        let heap_block: HeapBlock<_, (i32,), i32> = stack_block.into();
+        assert_eq!(Block::from(heap_block).call((2,)), 3);
+        assert_eq!(counter.load(Ordering::Relaxed), 3);
+
+        // What Objective-C code would do.
+        let header = counter.as_byref_ptr() as *const ByRefStorage<AtomicI32>;
+        unsafe {
+            assert_eq!((*header).forwarding, header as *mut _);
+            assert_eq!((*header).size as usize, std::mem::size_of_val(&*header));
+            *(*counter.as_ptr()).get_mut() = 10;
+        }
+        assert_eq!(counter.load(Ordering::Relaxed), 10);
+        drop(counter);
+        check.assert_balanced();
+
+        // The value is dropped with the last reference.
+        struct Tracked(std::cell::UnsafeCell<Arc<()>>);
+        unsafe impl ByRefValue for Tracked {}
+
+        let value = Arc::new(());
+        let byref = ByRef::new(Tracked(std::cell::UnsafeCell::new(value.clone())));
+        let byref2 = byref.clone();
+        assert_eq!(Arc::strong_count(&value), 2);
+        drop(byref);
+        assert_eq!(Arc::strong_count(&value), 2);
+        drop(byref2);
+        assert_eq!(Arc::strong_count(&value), 1);
+        check.assert_balanced();
+    }
+}
@@ -0,0 +1,630 @@
+//! Rust implementation of the blocks runtime.
+//!
+//! Enabled by the `blocks-runtime` feature (enabled by `mock-runtime`), it replaces the blocks runtime
+//! part of libSystem (or libobjc2): the `_NSConcreteStackBlock`, `_NSConcreteMallocBlock` and `_NSConcreteGlobalBlock`
+//! isa classes, `_Block_copy`/`_Block_release`, and `_Block_object_assign`/`_Block_object_dispose`
+//! used by the copy and dispose helpers of blocks for their captured objects, blocks and `__block` variables.
+//!
+//! It follows the behavior of `libclosure` and of LLVM's `compiler-rt` BlocksRuntime,
+//! including the reference count being kept (atomically) in the flags.
+//! See `base::block` for documentation about the blocks ABI.
+//!
+//! Everything is allocated with Rust's allocator, so blocks copied by this runtime must not be released by another one.
+//! With the `mock-runtime` feature, each allocation is also reported to the mock runtime so that `LeakCheck` sees it.
+
+// The `extern "C"` functions have the same requirements as the ones of the runtime they are replacing.
+#![allow(clippy::missing_safety_doc)]
+
+// The symbols are exported, and would clash with the ones of the real runtime's blocks runtime,
+// blocks then being copied by one and released by the other.
+#[cfg(not(feature = "mock-runtime"))]
+compile_error!("the `blocks-runtime` feature can only be used with `mock-runtime`, the Objective-C runtime having its own blocks runtime");
+
+use super::{objc_release, objc_retain, RawObjPtr};
+use std::alloc::Layout;
+use std::ffi::c_void;
+use std::os::raw::{c_int, c_ulong};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// The reference count of heap blocks and `__block` variables, in increments of 2.
+const BLOCK_REFCOUNT_MASK: c_int = 0xfffe;
+/// Set when the reference count got to zero.
+const BLOCK_DEALLOCATING: c_int = 0x0001;
+const BLOCK_NEEDS_FREE: c_int = 1 << 24;
+const BLOCK_HAS_COPY_DISPOSE: c_int = 1 << 25;
+const BLOCK_IS_GLOBAL: c_int = 1 << 28;
+
+/// The same values are used for `__block` variables.
+const BLOCK_BYREF_NEEDS_FREE: c_int = BLOCK_NEEDS_FREE;
+const BLOCK_BYREF_HAS_COPY_DISPOSE: c_int = BLOCK_HAS_COPY_DISPOSE;
+/// Indicates that the keep and destroy helpers are followed by a layout string.
+const BLOCK_BYREF_LAYOUT_EXTENDED: c_int = 1 << 28;
+
+// Values of the `flags` parameter of `_Block_object_assign` and `_Block_object_dispose`.
+/// The field is an Objective-C object.
+const BLOCK_FIELD_IS_OBJECT: c_int = 3;
+/// The field is another block.
+const BLOCK_FIELD_IS_BLOCK: c_int = 7;
+/// The field is a `__block` variable.
+const BLOCK_FIELD_IS_BYREF: c_int = 8;
+/// The field is declared `__weak`.
+const BLOCK_FIELD_IS_WEAK: c_int = 16;
+/// Called from the helpers of a `__block` variable instead of the ones of a block.
+const BLOCK_BYREF_CALLER: c_int = 128;
+
+#[repr(C)]
+struct BlockDescriptorLayout {
+    _reserved: c_ulong,
+    block_size: c_ulong,
+}
+
+/// Follows `BlockDescriptorLayout` if the block has `BLOCK_HAS_COPY_DISPOSE`.
+#[repr(C)]
+struct BlockHelpersLayout {
+    copy: unsafe extern "C" fn(*mut c_void, *const c_void),
+    dispose: unsafe extern "C" fn(*mut c_void),
+}
+
+#[repr(C)]
+struct BlockLayout {
+    isa: *const c_void,
+    flags: c_int,
+    _reserved: c_int,
+    invoke: *const c_void,
+    descriptor: *const BlockDescriptorLayout,
+}
+
+/// Header of a `__block` variable, the variable itself follows it (after the helpers if there are some).
+#[repr(C)]
+struct ByrefLayout {
+    isa: *const c_void,
+    /// Points to the variable itself until it gets copied to the heap, and then to the copy.
+    forwarding: *mut ByrefLayout,
+    flags: c_int,
+    /// Size of the whole structure, header included.
+    size: u32,
+}
+
+/// Follows `ByrefLayout` if the variable has `BLOCK_BYREF_HAS_COPY_DISPOSE`.
+#[repr(C)]
+struct ByrefHelpersLayout {
+    keep: unsafe extern "C" fn(*mut ByrefLayout, *mut ByrefLayout),
+    destroy: unsafe extern "C" fn(*mut ByrefLayout),
+}
+
+/// Follows `ByrefHelpersLayout` if the variable has `BLOCK_BYREF_LAYOUT_EXTENDED`.
+#[repr(C)]
+struct ByrefLayoutExtended {
+    layout: *const c_void,
+}
+
+// Only the address of the isa of blocks matters. Like in libclosure, they are made big enough to look like a class.
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+pub static _NSConcreteStackBlock: [usize; 32] = [0; 32];
+
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+pub static _NSConcreteMallocBlock: [usize; 32] = [0; 32];
+
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+pub static _NSConcreteGlobalBlock: [usize; 32] = [0; 32];
+
+/// Malloc gives 16 bytes aligned memory, which should be enough for any block or `__block` variable.
+const ALIGNMENT: usize = 16;
+
+/// Class name the mock runtime reports for leaked blocks.
+const MALLOC_BLOCK_CLASS_NAME: &str = "__NSMallocBlock__";
+/// Class name the mock runtime reports for leaked `__block` variables.
+const BLOCK_VARIABLE_CLASS_NAME: &str = "__NSBlockVariable__";
+
+#[cfg(feature = "mock-runtime")]
+use super::mock_runtime::{heap_allocated, heap_released, heap_retained};
+
+#[cfg(not(feature = "mock-runtime"))]
+fn heap_allocated(_addr: usize, _class_name: &'static str, _retain_count: usize) {}
+
+#[cfg(not(feature = "mock-runtime"))]
+fn heap_retained(_addr: usize) {}
+
+#[cfg(not(feature = "mock-runtime"))]
+fn heap_released(_addr: usize) -> bool {
+    true
+}
+
+/// # Safety
+/// `flags` must point to the flags of a live block or `__block` variable, and only be accessed atomically while shared.
+unsafe fn atomic_flags<'a>(flags: *mut c_int) -> &'a AtomicI32 {
+    AtomicI32::from_ptr(flags)
+}
+
+/// Increments the reference count, unless it is already at its maximum, in which case it stays there forever.
+fn latching_incr(flags: &AtomicI32) {
+    let mut old = flags.load(Ordering::Relaxed);
+    loop {
+        if old & BLOCK_REFCOUNT_MASK == BLOCK_REFCOUNT_MASK {
+            return;
+        }
+        match flags.compare_exchange_weak(old, old + 2, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => return,
+            Err(current) => old = current,
+        }
+    }
+}
+
+/// Decrements the reference count, returning true if it got to zero and the memory should be freed.
+///
+/// A reference count at its maximum never changes, and one already at zero stays there.
+fn latching_decr_should_deallocate(flags: &AtomicI32) -> bool {
+    let mut old = flags.load(Ordering::Relaxed);
+    loop {
+        let refcount = old & BLOCK_REFCOUNT_MASK;
+        if refcount == BLOCK_REFCOUNT_MASK || refcount == 0 {
+            return false;
+        }
+        let (new, deallocate) = if old & (BLOCK_REFCOUNT_MASK | BLOCK_DEALLOCATING) == 2 {
+            (old - 1, true)
+        } else {
+            (old - 2, false)
+        };
+        match flags.compare_exchange_weak(old, new, Ordering::Release, Ordering::Relaxed) {
+            Ok(_) => {
+                if deallocate {
+                    // Same as for `Arc`, makes sure all uses from other threads happened before deallocating.
+                    std::sync::atomic::fence(Ordering::Acquire);
+                }
+                return deallocate;
+            }
+            Err(current) => old = current,
+        }
+    }
+}
+
+unsafe fn allocate(size: usize) -> *mut u8 {
+    let layout = Layout::from_size_align(size, ALIGNMENT).unwrap();
+    let ptr = std::alloc::alloc(layout);
+    if ptr.is_null() {
+        std::alloc::handle_alloc_error(layout);
+    }
+    ptr
+}
+
+unsafe fn deallocate(ptr: *mut u8, size: usize) {
+    let layout = Layout::from_size_align(size, ALIGNMENT).unwrap();
+    std::alloc::dealloc(ptr, layout);
+}
+
+unsafe fn block_helpers<'a>(block: *const BlockLayout) -> &'a BlockHelpersLayout {
+    &*((*block).descriptor.add(1) as *const BlockHelpersLayout)
+}
+
+unsafe fn byref_helpers<'a>(byref: *const ByrefLayout) -> &'a ByrefHelpersLayout {
+    &*(byref.add(1) as *const ByrefHelpersLayout)
+}
+
+//-------------------------------------------------------------------
+// Blocks
+
+/// Copies a stack block to the heap, or increments the reference count of a block already on the heap.
+/// Global blocks are returned as is.
+#[no_mangle]
+pub unsafe extern "C" fn _Block_copy(block: *const c_void) -> *mut c_void {
+    if block.is_null() {
+        return std::ptr::null_mut();
+    }
+    let src = block as *mut BlockLayout;
+    let flags = atomic_flags(std::ptr::addr_of_mut!((*src).flags)).load(Ordering::Relaxed);
+    if flags & BLOCK_NEEDS_FREE != 0 {
+        heap_retained(src as usize);
+        latching_incr(atomic_flags(std::ptr::addr_of_mut!((*src).flags)));
+        return src as *mut c_void;
+    }
+    if flags & BLOCK_IS_GLOBAL != 0 {
+        return src as *mut c_void;
+    }
+
+    let size = (*(*src).descriptor).block_size as usize;
+    let dst = allocate(size) as *mut BlockLayout;
+    std::ptr::copy_nonoverlapping(src as *const u8, dst as *mut u8, size);
+    // Nobody else has access to the copy yet.
+    (*dst).flags &= !(BLOCK_REFCOUNT_MASK | BLOCK_DEALLOCATING);
+    (*dst).flags |= BLOCK_NEEDS_FREE | 2;
+    heap_allocated(dst as usize, MALLOC_BLOCK_CLASS_NAME, 1);
+    if flags & BLOCK_HAS_COPY_DISPOSE != 0 {
+        (block_helpers(src).copy)(dst as *mut c_void, src as *const c_void);
+    }
+    // Like libclosure, the isa is set last so that the copy only looks like a heap block once fully initialized.
+    (*dst).isa = _NSConcreteMallocBlock.as_ptr() as *const c_void;
+    dst as *mut c_void
+}
+
+/// Decrements the reference count of a heap block, disposing and freeing it when getting to zero.
+/// Does nothing for stack and global blocks.
+#[no_mangle]
+pub unsafe extern "C" fn _Block_release(block: *const c_void) {
+    if block.is_null() {
+        return;
+    }
+    let block = block as *mut BlockLayout;
+    // The mock runtime checks for over-releases before anything reads the (possibly freed) block.
+    if !heap_released(block as usize) {
+        return;
+    }
+    let flags = atomic_flags(std::ptr::addr_of_mut!((*block).flags));
+    if flags.load(Ordering::Relaxed) & BLOCK_NEEDS_FREE == 0 {
+        return;
+    }
+    if latching_decr_should_deallocate(flags) {
+        if flags.load(Ordering::Relaxed) & BLOCK_HAS_COPY_DISPOSE != 0 {
+            (block_helpers(block).dispose)(block as *mut c_void);
+        }
+        let size = (*(*block).descriptor).block_size as usize;
+        deallocate(block as *mut u8, size);
+    }
+}
+
+//-------------------------------------------------------------------
+// `__block` variables
+
+/// Moves a `__block` variable to the heap the first time a block using it is copied,
+/// or just increments the reference count of the heap copy.
+unsafe fn byref_copy(src: *mut ByrefLayout) -> *mut ByrefLayout {
+    let forwarding = (*src).forwarding;
+    let flags = atomic_flags(std::ptr::addr_of_mut!((*forwarding).flags));
+    let current_flags = flags.load(Ordering::Relaxed);
+    if current_flags & BLOCK_REFCOUNT_MASK == 0 {
+        // Still on the stack.
+        let size = (*src).size as usize;
+        let copy = allocate(size) as *mut ByrefLayout;
+        copy.write(ByrefLayout {
+            isa: std::ptr::null(),
+            forwarding: copy,
+            // One reference for the caller, and one for the stack variable,
+            // released by `_Block_object_dispose` at the end of its scope.
+            flags: (*src).flags | BLOCK_BYREF_NEEDS_FREE | 4,
+            size: (*src).size,
+        });
+        (*src).forwarding = copy;
+        heap_allocated(copy as usize, BLOCK_VARIABLE_CLASS_NAME, 2);
+        if (*src).flags & BLOCK_BYREF_HAS_COPY_DISPOSE != 0 {
+            let src_helpers = byref_helpers(src);
+            let copy_helpers = copy.add(1) as *mut ByrefHelpersLayout;
+            copy_helpers.write(ByrefHelpersLayout {
+                keep: src_helpers.keep,
+                destroy: src_helpers.destroy,
+            });
+            if (*src).flags & BLOCK_BYREF_LAYOUT_EXTENDED != 0 {
+                let src_extended =
+                    (src_helpers as *const ByrefHelpersLayout).add(1) as *const ByrefLayoutExtended;
+                let copy_extended = copy_helpers.add(1) as *mut ByrefLayoutExtended;
+                copy_extended.write(ByrefLayoutExtended {
+                    layout: (*src_extended).layout,
+                });
+            }
+            // The keep helper takes care of copying the variable itself.
+            (src_helpers.keep)(copy, src);
+        } else {
+            // Bitwise copy of the variable, the header is already set.
+            let header_size = std::mem::size_of::<ByrefLayout>();
+            std::ptr::copy_nonoverlapping(
+                (src as *const u8).add(header_size),
+                (copy as *mut u8).add(header_size),
+                size - header_size,
+            );
+        }
+    } else if current_flags & BLOCK_BYREF_NEEDS_FREE != 0 {
+        heap_retained(forwarding as usize);
+        latching_incr(flags);
+    }
+    (*src).forwarding
+}
+
+/// Decrements the reference count of the heap copy of a `__block` variable, destroying and freeing it when getting to zero.
+/// Does nothing if it was never copied to the heap.
+unsafe fn byref_release(byref: *mut ByrefLayout) {
+    let byref = (*byref).forwarding;
+    let flags = atomic_flags(std::ptr::addr_of_mut!((*byref).flags));
+    if flags.load(Ordering::Relaxed) & BLOCK_BYREF_NEEDS_FREE == 0 {
+        return;
+    }
+    if !heap_released(byref as usize) {
+        return;
+    }
+    if latching_decr_should_deallocate(flags) {
+        if flags.load(Ordering::Relaxed) & BLOCK_BYREF_HAS_COPY_DISPOSE != 0 {
+            (byref_helpers(byref).destroy)(byref);
+        }
+        deallocate(byref as *mut u8, (*byref).size as usize);
+    }
+}
+
+//-------------------------------------------------------------------
+// Helpers support
+
+/// Called by the copy helper of blocks (and keep helper of `__block` variables) for each object, block or `__block` variable captured,
+/// `dest` being the field of the copy and `object` the value of the field in the original.
+#[no_mangle]
+pub unsafe extern "C" fn _Block_object_assign(
+    dest: *mut c_void,
+    object: *const c_void,
+    flags: c_int,
+) {
+    const BYREF_WEAK: c_int = BLOCK_FIELD_IS_BYREF | BLOCK_FIELD_IS_WEAK;
+    const CALLER_OBJECT: c_int = BLOCK_BYREF_CALLER | BLOCK_FIELD_IS_OBJECT;
+    const CALLER_BLOCK: c_int = BLOCK_BYREF_CALLER | BLOCK_FIELD_IS_BLOCK;
+    const CALLER_WEAK_OBJECT: c_int = CALLER_OBJECT | BLOCK_FIELD_IS_WEAK;
+    const CALLER_WEAK_BLOCK: c_int = CALLER_BLOCK | BLOCK_FIELD_IS_WEAK;
+    let dest = dest as *mut *const c_void;
+    match flags {
+        BLOCK_FIELD_IS_OBJECT => {
+            if let Some(ptr) = NonNull::new(object as *mut _) {
+                objc_retain(RawObjPtr { ptr });
+            }
+            *dest = object;
+        }
+        BLOCK_FIELD_IS_BLOCK => *dest = _Block_copy(object),
+        BLOCK_FIELD_IS_BYREF | BYREF_WEAK => {
+            *dest = byref_copy(object as *mut ByrefLayout) as *const c_void
+        }
+        // The object is owned by the `__block` variable itself, the reference is just moved.
+        CALLER_OBJECT | CALLER_BLOCK | CALLER_WEAK_OBJECT | CALLER_WEAK_BLOCK => *dest = object,
+        _ => {}
+    }
+}
+
+/// Called by the dispose helper of blocks (and destroy helper of `__block` variables)
+/// for each object, block or `__block` variable captured.
+#[no_mangle]
+pub unsafe extern "C" fn _Block_object_dispose(object: *const c_void, flags: c_int) {
+    const BYREF_WEAK: c_int = BLOCK_FIELD_IS_BYREF | BLOCK_FIELD_IS_WEAK;
+    match flags {
+        BLOCK_FIELD_IS_OBJECT => {
+            if let Some(ptr) = NonNull::new(object as *mut _) {
+                objc_release(RawObjPtr { ptr });
+            }
+        }
+        BLOCK_FIELD_IS_BLOCK => _Block_release(object),
+        BLOCK_FIELD_IS_BYREF | BYREF_WEAK => byref_release(object as *mut ByrefLayout),
+        _ => {}
+    }
+}
+
+#[cfg(all(test, feature = "mock-runtime"))]
+mod blocks_runtime_tests {
+    use super::*;
+    use crate::base::block::HeapBlock;
+    use crate::base::mock_runtime::{create_object, register_class, retain_count, LeakCheck};
+    use crate::base::mock_runtime::{is_alive, Issue};
+    use std::cell::Cell;
+
+    #[test]
+    fn latching_refcount() {
+        let flags = AtomicI32::new(BLOCK_NEEDS_FREE | 2);
+        latching_incr(&flags);
+        assert_eq!(flags.load(Ordering::Relaxed), BLOCK_NEEDS_FREE | 4);
+        assert!(!latching_decr_should_deallocate(&flags));
+        assert!(latching_decr_should_deallocate(&flags));
+        assert_eq!(
+            flags.load(Ordering::Relaxed),
+            BLOCK_NEEDS_FREE | BLOCK_DEALLOCATING
+        );
+        // Already deallocating, nothing changes.
+        assert!(!latching_decr_should_deallocate(&flags));
+
+        // Once at its maximum, the reference count stays there forever.
+        let flags = AtomicI32::new(BLOCK_NEEDS_FREE | BLOCK_REFCOUNT_MASK);
+        latching_incr(&flags);
+        assert!(!latching_decr_should_deallocate(&flags));
+        assert_eq!(
+            flags.load(Ordering::Relaxed),
+            BLOCK_NEEDS_FREE | BLOCK_REFCOUNT_MASK
+        );
+    }
+
+    #[test]
+    fn global_block() {
+        static DESCRIPTOR: BlockDescriptorLayout = BlockDescriptorLayout {
+            _reserved: 0,
+            block_size: std::mem::size_of::<BlockLayout>() as c_ulong,
+        };
+        let mut block = BlockLayout {
+            isa: _NSConcreteGlobalBlock.as_ptr() as *const c_void,
+            flags: BLOCK_IS_GLOBAL,
+            _reserved: 0,
+            invoke: std::ptr::null(),
+            descriptor: &DESCRIPTOR,
+        };
+        // Like any block, its flags are accessed atomically and so must be in writable memory.
+        let ptr = std::ptr::addr_of_mut!(block) as *const c_void;
+        let check = LeakCheck::start();
+        unsafe {
+            assert_eq!(_Block_copy(ptr) as *const c_void, ptr);
+            _Block_release(ptr);
+        }
+        assert_eq!(block.flags, BLOCK_IS_GLOBAL);
+        check.assert_balanced();
+    }
+
+    #[test]
+    fn captured_objects_and_blocks() {
+        let check = LeakCheck::start();
+        let obj = create_object(register_class("MockObject"));
+        let mut field: *const c_void = std::ptr::null();
+        unsafe {
+            _Block_object_assign(
+                &mut field as *mut _ as *mut c_void,
+                obj.ptr.as_ptr() as *const c_void,
+                BLOCK_FIELD_IS_OBJECT,
+            );
+            assert_eq!(field, obj.ptr.as_ptr() as *const c_void);
+            assert_eq!(retain_count(obj), 2);
+            _Block_object_dispose(field, BLOCK_FIELD_IS_OBJECT);
+            assert_eq!(retain_count(obj), 1);
+            objc_release(obj);
+        }
+
+        let block = HeapBlock::new(|| 42);
+        let ptr = block.as_ptr() as *const c_void;
+        unsafe {
+            _Block_object_assign(
+                &mut field as *mut _ as *mut c_void,
+                ptr,
+                BLOCK_FIELD_IS_BLOCK,
+            );
+            // A heap block is not copied again.
+            assert_eq!(field, ptr);
+            _Block_object_dispose(field, BLOCK_FIELD_IS_BLOCK);
+        }
+        drop(block);
+        check.assert_balanced();
+    }
+
+    #[repr(C)]
+    struct Byref<T> {
+        header: ByrefLayout,
+        value: T,
+    }
+
+    #[test]
+    fn byref_without_helpers() {
+        let check = LeakCheck::start();
+        let mut variable = Byref {
+            header: ByrefLayout {
+                isa: std::ptr::null(),
+                forwarding: std::ptr::null_mut(),
+                flags: 0,
+                size: std::mem::size_of::<Byref<u64>>() as u32,
+            },
+            value: 42u64,
+        };
+        let stack = &mut variable as *mut Byref<u64>;
+        unsafe {
+            (*stack).header.forwarding = stack as *mut ByrefLayout;
+            // First block copied to the heap.
+            let mut field1: *const c_void = std::ptr::null();
+            _Block_object_assign(
+                &mut field1 as *mut _ as *mut c_void,
+                stack as *const c_void,
+                BLOCK_FIELD_IS_BYREF,
+            );
+            let heap = field1 as *mut Byref<u64>;
+            assert_ne!(heap, stack);
+            assert_eq!((*stack).header.forwarding, heap as *mut ByrefLayout);
+            assert_eq!((*heap).header.forwarding, heap as *mut ByrefLayout);
+            assert_eq!((*heap).value, 42);
+            (*(*stack).header.forwarding.cast::<Byref<u64>>()).value = 43;
+
+            // Second block copied to the heap, sharing the same variable.
+            let mut field2: *const c_void = std::ptr::null();
+            _Block_object_assign(
+                &mut field2 as *mut _ as *mut c_void,
+                stack as *const c_void,
+                BLOCK_FIELD_IS_BYREF,
+            );
+            assert_eq!(field2, field1);
+            assert_eq!((*heap).value, 43);
+
+            _Block_object_dispose(field1, BLOCK_FIELD_IS_BYREF);
+            _Block_object_dispose(field2, BLOCK_FIELD_IS_BYREF);
+            assert_eq!(
+                check.issues(),
+                vec![Issue::Leak {
+                    class_name: "__NSBlockVariable__",
+                    retain_count: 1
+                }]
+            );
+            // End of the scope of the variable.
+            _Block_object_dispose(stack as *const c_void, BLOCK_FIELD_IS_BYREF);
+        }
+        check.assert_balanced();
+    }
+
+    thread_local! {
+        static KEPT: Cell<usize> = const { Cell::new(0) };
+        static DESTROYED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    #[repr(C)]
+    struct ByrefWithHelpers {
+        header: ByrefLayout,
+        helpers: ByrefHelpersLayout,
+        value: RawObjPtr,
+    }
+
+    unsafe extern "C" fn keep(dst: *mut ByrefLayout, src: *mut ByrefLayout) {
+        KEPT.with(|kept| kept.set(kept.get() + 1));
+        let dst = dst as *mut ByrefWithHelpers;
+        let src = src as *mut ByrefWithHelpers;
+        _Block_object_assign(
+            std::ptr::addr_of_mut!((*dst).value) as *mut c_void,
+            (*src).value.ptr.as_ptr() as *const c_void,
+            BLOCK_FIELD_IS_OBJECT,
+        );
+    }
+
+    unsafe extern "C" fn destroy(byref: *mut ByrefLayout) {
+        DESTROYED.with(|destroyed| destroyed.set(destroyed.get() + 1));
+        let byref = byref as *mut ByrefWithHelpers;
+        _Block_object_dispose(
+            (*byref).value.ptr.as_ptr() as *const c_void,
+            BLOCK_FIELD_IS_OBJECT,
+        );
+    }
+
+    #[test]
+    fn byref_with_helpers() {
+        let check = LeakCheck::start();
+        let obj = create_object(register_class("MockObject"));
+        let mut variable = ByrefWithHelpers {
+            header: ByrefLayout {
+                isa: std::ptr::null(),
+                forwarding: std::ptr::null_mut(),
+                flags: BLOCK_BYREF_HAS_COPY_DISPOSE,
+                size: std::mem::size_of::<ByrefWithHelpers>() as u32,
+            },
+            helpers: ByrefHelpersLayout { keep, destroy },
+            value: obj,
+        };
+        let stack = &mut variable as *mut ByrefWithHelpers;
+        unsafe {
+            (*stack).header.forwarding = stack as *mut ByrefLayout;
+            let mut field: *const c_void = std::ptr::null();
+            _Block_object_assign(
+                &mut field as *mut _ as *mut c_void,
+                stack as *const c_void,
+                BLOCK_FIELD_IS_BYREF,
+            );
+            assert_eq!(KEPT.with(Cell::get), 1);
+            assert_eq!(retain_count(obj), 2);
+            let heap = field as *mut ByrefWithHelpers;
+            assert_eq!((*heap).value.ptr, obj.ptr);
+
+            // The stack version goes out of scope first.
+            _Block_object_dispose(stack as *const c_void, BLOCK_FIELD_IS_BYREF);
+            assert_eq!(DESTROYED.with(Cell::get), 0);
+            _Block_object_dispose(field, BLOCK_FIELD_IS_BYREF);
+            assert_eq!(DESTROYED.with(Cell::get), 1);
+            assert_eq!(retain_count(obj), 1);
+            objc_release(obj);
+        }
+        assert!(!is_alive(obj));
+        check.assert_balanced();
+    }
+
+    #[test]
+    fn detects_block_over_release() {
+        let block = HeapBlock::new(|| 42);
+        let ptr = block.as_ptr() as *const c_void;
+        drop(block);
+        let check = LeakCheck::start();
+        unsafe { _Block_release(ptr) };
+        assert_eq!(
+            check.issues(),
+            vec![Issue::OverRelease {
+                class_name: "__NSMallocBlock__"
+            }]
+        );
+    }
+}
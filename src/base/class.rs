@@ -0,0 +1,402 @@
+//-------------------------------------------------------------------
+// Runtime reflection
+//
+// Inspecting classes with the runtime functions, to check bindings against what the runtime has,
+// or to dump the API of a framework:
+//
+// ```ignore
+// let class = Class::get("NSString").unwrap();
+// for method in class.instance_methods() {
+//     println!("{:?} {:?}", method.sel(), method.type_encoding());
+// }
+// ```
+//
+// Only functions available in both Apple's runtime and GNUstep's libobjc2 are used.
+
+use super::{MessageReceiver, ObjCClass, OpaqueMethod, RawClassPtr, RawObjPtr, Sel, BOOL};
+use std::ffi::{c_char, c_uint, c_void, CStr, CString};
+use std::ptr::NonNull;
+
+#[repr(C)]
+pub struct OpaqueIvar {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct OpaqueProperty {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct OpaqueProtocol {
+    _private: [u8; 0],
+}
+
+/// Objective-C class, as known by the runtime.
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct Class {
+    raw: RawClassPtr,
+}
+
+/// Method of a class, as returned by `Class::instance_methods`.
+#[derive(Copy, Clone)]
+#[repr(transparent)]
+pub struct Method {
+    ptr: NonNull<OpaqueMethod>,
+}
+
+/// Instance variable of a class, as returned by `Class::ivars`.
+#[derive(Copy, Clone)]
+#[repr(transparent)]
+pub struct Ivar {
+    ptr: NonNull<OpaqueIvar>,
+}
+
+/// Declared property of a class, as returned by `Class::properties`.
+#[derive(Copy, Clone)]
+#[repr(transparent)]
+pub struct Property {
+    ptr: NonNull<OpaqueProperty>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct Protocol {
+    pub(super) ptr: NonNull<OpaqueProtocol>,
+}
+
+#[cfg_attr(not(feature = "mock-runtime"), link(name = "objc", kind = "dylib"))]
+extern "C" {
+    fn objc_getClass(name: *const c_char) -> Option<Class>;
+    fn object_getClass(obj: Option<RawObjPtr>) -> Option<RawClassPtr>;
+    fn class_getName(class: Class) -> *const c_char;
+    fn class_getSuperclass(class: Option<RawClassPtr>) -> Option<RawClassPtr>;
+    fn class_respondsToSelector(class: Class, sel: Sel) -> BOOL;
+    fn class_conformsToProtocol(class: Class, protocol: Protocol) -> BOOL;
+    fn class_copyMethodList(class: Class, count: *mut c_uint) -> *mut Method;
+    fn class_copyIvarList(class: Class, count: *mut c_uint) -> *mut Ivar;
+    fn class_copyPropertyList(class: Class, count: *mut c_uint) -> *mut Property;
+    fn class_copyProtocolList(class: Class, count: *mut c_uint) -> *mut Protocol;
+    fn method_getName(method: Method) -> Sel;
+    fn method_getTypeEncoding(method: Method) -> *const c_char;
+    fn ivar_getName(ivar: Ivar) -> *const c_char;
+    fn ivar_getTypeEncoding(ivar: Ivar) -> *const c_char;
+    fn ivar_getOffset(ivar: Ivar) -> isize;
+    fn property_getName(property: Property) -> *const c_char;
+    fn property_getAttributes(property: Property) -> *const c_char;
+    fn objc_getProtocol(name: *const c_char) -> Option<Protocol>;
+    fn protocol_getName(protocol: Protocol) -> *const c_char;
+}
+
+// The lists returned by the `class_copy...List` functions are allocated with `malloc`.
+extern "C" {
+    fn free(ptr: *mut c_void);
+}
+
+/// Strings returned by the runtime belong to the class or protocol, that are never unloaded.
+/// Some runtimes return null instead of an empty type encoding.
+unsafe fn runtime_str(ptr: *const c_char) -> &'static CStr {
+    if ptr.is_null() {
+        c""
+    } else {
+        CStr::from_ptr(ptr)
+    }
+}
+
+/// Takes the content of a list returned by one of the `class_copy...List` functions, freeing it.
+unsafe fn take_list<T: Copy>(list: *mut T, count: c_uint) -> Vec<T> {
+    if list.is_null() {
+        return Vec::new();
+    }
+    let items = std::slice::from_raw_parts(list, count as usize).to_vec();
+    free(list.cast());
+    items
+}
+
+impl Class {
+    /// Class registered with the runtime under `name`, if any.
+    pub fn get(name: &str) -> Option<Self> {
+        let name = CString::new(name).ok()?;
+        unsafe { objc_getClass(name.as_ptr()) }
+    }
+
+    pub fn of<T: ObjCClass>() -> Self {
+        Self::from_raw(T::class())
+    }
+
+    pub fn from_raw(raw: RawClassPtr) -> Self {
+        Self { raw }
+    }
+
+    pub fn as_raw(&self) -> RawClassPtr {
+        self.raw
+    }
+
+    pub fn name(&self) -> &'static CStr {
+        unsafe { runtime_str(class_getName(*self)) }
+    }
+
+    pub fn superclass(&self) -> Option<Class> {
+        unsafe { class_getSuperclass(Some(self.raw)) }.map(Self::from_raw)
+    }
+
+    /// Class of the class, whose instance methods are the class methods of this one.
+    pub fn metaclass(&self) -> Class {
+        let metaclass = unsafe { object_getClass(Some(self.raw.as_receiver())) };
+        Self::from_raw(metaclass.expect("expecting a class to have a metaclass"))
+    }
+
+    /// Methods implemented by the class itself, not including the ones of its superclasses.
+    pub fn instance_methods(&self) -> Vec<Method> {
+        let mut count = 0;
+        unsafe { take_list(class_copyMethodList(*self, &mut count), count) }
+    }
+
+    /// Instance variables declared by the class itself, not including the ones of its superclasses.
+    pub fn ivars(&self) -> Vec<Ivar> {
+        let mut count = 0;
+        unsafe { take_list(class_copyIvarList(*self, &mut count), count) }
+    }
+
+    /// Properties declared by the class itself, not including the ones of its superclasses.
+    pub fn properties(&self) -> Vec<Property> {
+        let mut count = 0;
+        unsafe { take_list(class_copyPropertyList(*self, &mut count), count) }
+    }
+
+    /// Protocols the class itself declares adopting, not including the ones of its superclasses.
+    pub fn adopted_protocols(&self) -> Vec<Protocol> {
+        let mut count = 0;
+        unsafe { take_list(class_copyProtocolList(*self, &mut count), count) }
+    }
+
+    /// Whether instances of the class respond to `sel`, looking at the superclasses too.
+    pub fn responds_to(&self, sel: Sel) -> bool {
+        unsafe { class_respondsToSelector(*self, sel) }.into()
+    }
+
+    /// Whether the class or one of its superclasses adopts `protocol`.
+    pub fn conforms_to(&self, protocol: Protocol) -> bool {
+        unsafe { class_conformsToProtocol(*self, protocol) }.into()
+    }
+}
+
+impl std::fmt::Debug for Class {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name().to_string_lossy())
+    }
+}
+
+// Classes are never deallocated, and the runtime functions can be used from any thread.
+unsafe impl Send for Class {}
+unsafe impl Sync for Class {}
+
+impl Method {
+    pub fn sel(&self) -> Sel {
+        unsafe { method_getName(*self) }
+    }
+
+    /// Types of the return value and parameters, for example `c"@24@0:8Q16"` for `objectAtIndex:`.
+    pub fn type_encoding(&self) -> &'static CStr {
+        unsafe { runtime_str(method_getTypeEncoding(*self)) }
+    }
+}
+
+impl std::fmt::Debug for Method {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} {}",
+            self.sel(),
+            self.type_encoding().to_string_lossy()
+        )
+    }
+}
+
+impl Ivar {
+    pub fn name(&self) -> &'static CStr {
+        unsafe { runtime_str(ivar_getName(*self)) }
+    }
+
+    pub fn type_encoding(&self) -> &'static CStr {
+        unsafe { runtime_str(ivar_getTypeEncoding(*self)) }
+    }
+
+    /// Offset of the variable from the start of the object, in bytes.
+    pub fn offset(&self) -> isize {
+        unsafe { ivar_getOffset(*self) }
+    }
+}
+
+impl std::fmt::Debug for Ivar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} at {}",
+            self.name().to_string_lossy(),
+            self.type_encoding().to_string_lossy(),
+            self.offset()
+        )
+    }
+}
+
+impl Property {
+    pub fn name(&self) -> &'static CStr {
+        unsafe { runtime_str(property_getName(*self)) }
+    }
+
+    /// Attribute string of the property, for example `c"T@\"NSString\",R,C,N"`
+    /// for a `readonly`, `copy` and `nonatomic` `NSString *`.
+    pub fn attributes(&self) -> &'static CStr {
+        unsafe { runtime_str(property_getAttributes(*self)) }
+    }
+}
+
+impl std::fmt::Debug for Property {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}",
+            self.name().to_string_lossy(),
+            self.attributes().to_string_lossy()
+        )
+    }
+}
+
+impl Protocol {
+    /// Protocol registered with the runtime under `name`, if any.
+    ///
+    /// Protocols are only registered if some code uses them, so a protocol declared in a framework can be missing.
+    pub fn get(name: &str) -> Option<Self> {
+        let name = CString::new(name).ok()?;
+        unsafe { objc_getProtocol(name.as_ptr()) }
+    }
+
+    pub fn name(&self) -> &'static CStr {
+        unsafe { runtime_str(protocol_getName(*self)) }
+    }
+}
+
+impl std::fmt::Debug for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name().to_string_lossy())
+    }
+}
+
+// Like classes, protocols are never deallocated.
+unsafe impl Send for Protocol {}
+unsafe impl Sync for Protocol {}
+
+#[cfg(all(test, feature = "mock-runtime"))]
+mod class_tests {
+    use super::*;
+    use crate::base::mock_runtime::{
+        add_property, class_addIvar, class_addMethod, class_addProtocol, register_class,
+        register_protocol, register_subclass,
+    };
+    use crate::base::{Imp, RawObjPtr};
+    use crate::sel;
+
+    unsafe extern "C-unwind" fn count(_this: RawObjPtr, _sel: Sel) -> u32 {
+        0
+    }
+
+    fn names<T>(items: &[T], name: impl Fn(&T) -> &'static CStr) -> Vec<&'static CStr> {
+        let mut names: Vec<_> = items.iter().map(name).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn classes() {
+        let base = register_class("MockReflectionBase");
+        let derived = register_subclass("MockReflectionDerived", base);
+        let class = Class::get("MockReflectionDerived").unwrap();
+        assert_eq!(class, Class::from_raw(derived));
+        assert_eq!(class.name(), c"MockReflectionDerived");
+        assert_eq!(format!("{:?}", class), "MockReflectionDerived");
+        assert_eq!(class.superclass(), Some(Class::from_raw(base)));
+        assert_eq!(Class::from_raw(base).superclass(), None);
+        assert_ne!(class.metaclass(), class);
+        assert_eq!(
+            class.metaclass().superclass(),
+            Some(Class::from_raw(base).metaclass())
+        );
+        assert_eq!(Class::get("MockReflectionUnknown"), None);
+        assert_eq!(Class::get("MockReflection\0Derived"), None);
+    }
+
+    #[test]
+    fn methods() {
+        let base = register_class("MockMethodsBase");
+        let derived = register_subclass("MockMethodsDerived", base);
+        let class = Class::from_raw(derived);
+        unsafe {
+            let count = std::mem::transmute::<unsafe extern "C-unwind" fn(_, _) -> u32, Imp>(count);
+            class_addMethod(base, sel!(count), count, c"I16@0:8".as_ptr());
+            class_addMethod(derived, sel!(size), count, c"I16@0:8".as_ptr());
+            class_addMethod(derived, sel!(length), count, c"I16@0:8".as_ptr());
+            class_addMethod(
+                class.metaclass().as_raw(),
+                sel!(new),
+                count,
+                c"I16@0:8".as_ptr(),
+            );
+        }
+
+        let methods = class.instance_methods();
+        // The mock runtime keeps the order in which the methods were added.
+        let method_names: Vec<_> = methods.iter().map(|method| method.sel().name()).collect();
+        assert_eq!(method_names, [c"size", c"length"]);
+        assert_eq!(methods[0].type_encoding(), c"I16@0:8");
+        let class_methods = class.metaclass().instance_methods();
+        assert_eq!(class_methods.len(), 1);
+        assert_eq!(format!("{:?}", class_methods[0]), "new I16@0:8");
+
+        assert!(class.responds_to(sel!(length)));
+        // Inherited.
+        assert!(class.responds_to(sel!(count)));
+        assert!(!class.responds_to(sel!(new)));
+        assert!(!Class::from_raw(base).responds_to(sel!(length)));
+    }
+
+    #[test]
+    fn ivars_and_properties() {
+        let class = Class::from_raw(register_class("MockIvars"));
+        assert!(class.ivars().is_empty());
+        unsafe {
+            class_addIvar(class.as_raw(), c"_flag".as_ptr(), 1, 0, c"c".as_ptr());
+            class_addIvar(class.as_raw(), c"_name".as_ptr(), 8, 3, c"@".as_ptr());
+        }
+        add_property(class.as_raw(), c"name", c"T@\"NSString\",R,C,N,V_name");
+
+        let ivars = class.ivars();
+        assert_eq!(names(&ivars, Ivar::name), [c"_flag", c"_name"]);
+        assert_eq!(ivars[1].type_encoding(), c"@");
+        // After the isa, aligned.
+        assert_eq!(ivars[0].offset(), 8);
+        assert_eq!(ivars[1].offset(), 16);
+
+        let properties = class.properties();
+        assert_eq!(properties.len(), 1);
+        assert_eq!(properties[0].name(), c"name");
+        assert_eq!(properties[0].attributes(), c"T@\"NSString\",R,C,N,V_name");
+    }
+
+    #[test]
+    fn protocols() {
+        let base = register_class("MockProtocolsBase");
+        let derived = register_subclass("MockProtocolsDerived", base);
+        let protocol = register_protocol(c"MockDelegate");
+        assert_eq!(Protocol::get("MockDelegate"), Some(protocol));
+        assert_eq!(Protocol::get("MockUnknownDelegate"), None);
+        assert_eq!(protocol.name(), c"MockDelegate");
+
+        unsafe { class_addProtocol(base, protocol) };
+        assert_eq!(Class::from_raw(base).adopted_protocols(), [protocol]);
+        assert!(Class::from_raw(derived).adopted_protocols().is_empty());
+        assert!(Class::from_raw(derived).conforms_to(protocol));
+        assert!(!Class::from_raw(derived).conforms_to(register_protocol(c"MockOtherDelegate")));
+    }
+}
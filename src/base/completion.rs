@@ -0,0 +1,316 @@
+//! Turning APIs taking a completion handler into futures.
+//!
+//! ```ignore
+//! let future = CompletionFuture::start(|completer| {
+//!     let handler = HeapBlock::new(move |finished: BOOL| completer.complete(bool::from(finished)));
+//!     unsafe { choco_SomeFramework_SomeClass_instance_doSomething_completionHandler(raw_self, handler.as_ptr()) }
+//! });
+//! let finished = future.await;
+//! ```
+//!
+//! For handlers getting a value and an error, the future's output can be a `Result`, made with
+//! `foundation::make_value_result_unchecked`:
+//!
+//! ```ignore
+//! let future = CompletionFuture::start(|completer| {
+//!     let handler = HeapBlock::new(move |status: NSInteger, error: Option<RawObjPtr>| {
+//!         completer.complete(unsafe { make_value_result_unchecked(status, error) })
+//!     });
+//!     unsafe { choco_SomeFramework_SomeClass_instance_loadWithCompletionHandler(raw_self, handler.as_ptr()) }
+//! });
+//! let status: Result<NSInteger, Ptr<NSError>> = future.await;
+//! ```
+//!
+//! Nothing is specific to an async runtime: the framework calling the handler from whatever thread it wants
+//! just wakes the task polling the future.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+
+enum State<T> {
+    /// The handler has not been called yet. Keeps the waker of the last poll.
+    Waiting(Option<Waker>),
+    Completed(T),
+    /// The value has been given to the task that awaited the future.
+    Taken,
+    /// The `CompletionFuture` was dropped before the handler got called.
+    Cancelled,
+    /// The `Completer` was dropped (with the block containing it) without the handler ever being called.
+    Abandoned,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+}
+
+impl<T> Shared<T> {
+    fn state(&self) -> MutexGuard<'_, State<T>> {
+        // The lock is never held while calling user code or panicking, so it can't be poisoned.
+        self.state.lock().unwrap()
+    }
+}
+
+/// Future of the value a completion handler receives.
+///
+/// Dropping the future before completion cancels it: the value the handler gets afterwards is just dropped,
+/// and the action given to `on_cancel` (if any) is run.
+pub struct CompletionFuture<T> {
+    shared: Arc<Shared<T>>,
+    on_cancel: Option<Box<dyn FnOnce() + Send>>,
+}
+
+/// Completes a `CompletionFuture`, to be moved into the completion handler.
+///
+/// If it is dropped without `complete` having been called, for example because the framework
+/// released the handler without calling it, awaiting the future panics.
+pub struct Completer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> CompletionFuture<T> {
+    pub fn new() -> (Self, Completer<T>) {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::Waiting(None)),
+        });
+        let future = Self {
+            shared: shared.clone(),
+            on_cancel: None,
+        };
+        (future, Completer { shared })
+    }
+
+    /// Creates the future, giving the completer to `start`, which usually creates the handler and calls the API with it.
+    pub fn start(start: impl FnOnce(Completer<T>)) -> Self {
+        let (future, completer) = Self::new();
+        start(completer);
+        future
+    }
+
+    /// Sets an action to run if the future is dropped before completion, for example to cancel the underlying operation.
+    pub fn on_cancel(mut self, on_cancel: impl FnOnce() + Send + 'static) -> Self {
+        self.on_cancel = Some(Box::new(on_cancel));
+        self
+    }
+}
+
+impl<T> Future for CompletionFuture<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.shared.state();
+        match std::mem::replace(&mut *state, State::Taken) {
+            State::Waiting(_) => {
+                *state = State::Waiting(Some(cx.waker().clone()));
+                Poll::Pending
+            }
+            State::Completed(value) => Poll::Ready(value),
+            State::Taken => {
+                drop(state);
+                panic!("CompletionFuture polled after completion")
+            }
+            State::Abandoned => {
+                *state = State::Abandoned;
+                // Not panicking with the lock held, that would poison it.
+                drop(state);
+                panic!("completion handler released without having been called")
+            }
+            State::Cancelled => unreachable!("only the future itself cancels"),
+        }
+    }
+}
+
+impl<T> Drop for CompletionFuture<T> {
+    fn drop(&mut self) {
+        let cancelled = {
+            let mut state = self.shared.state();
+            if let State::Waiting(_) = *state {
+                *state = State::Cancelled;
+                true
+            } else {
+                false
+            }
+        };
+        if cancelled {
+            if let Some(on_cancel) = self.on_cancel.take() {
+                on_cancel();
+            }
+        }
+    }
+}
+
+impl<T> Completer<T> {
+    /// Completes the future with `value`, waking the task awaiting it.
+    ///
+    /// Only the first call matters, the value given to later ones (or given after cancellation) is just dropped,
+    /// as some frameworks do not guarantee to call handlers only once.
+    pub fn complete(&self, value: T) {
+        let waker = {
+            let mut state = self.shared.state();
+            match &mut *state {
+                State::Waiting(waker) => {
+                    let waker = waker.take();
+                    *state = State::Completed(value);
+                    waker
+                }
+                _ => None,
+            }
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Whether the future has been dropped before completion.
+    pub fn is_cancelled(&self) -> bool {
+        matches!(*self.shared.state(), State::Cancelled)
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state = self.shared.state();
+            match &mut *state {
+                State::Waiting(waker) => {
+                    let waker = waker.take();
+                    *state = State::Abandoned;
+                    waker
+                }
+                _ => None,
+            }
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod completion_tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::Wake;
+    use std::thread::Thread;
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Minimal executor, enough to check the future works without any async runtime.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(value) => return value,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn completed_from_another_thread() {
+        let future = CompletionFuture::start(|completer| {
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                completer.complete(42);
+            });
+        });
+        assert_eq!(block_on(future), 42);
+    }
+
+    #[test]
+    fn completed_before_polling() {
+        let (future, completer) = CompletionFuture::new();
+        completer.complete("first");
+        // Later calls are ignored.
+        completer.complete("second");
+        drop(completer);
+        assert_eq!(block_on(future), "first");
+    }
+
+    #[test]
+    fn cancellation() {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let (future, completer) = CompletionFuture::<Arc<()>>::new();
+        let future = future.on_cancel({
+            let cancelled = cancelled.clone();
+            move || cancelled.store(true, Ordering::Relaxed)
+        });
+        assert!(!completer.is_cancelled());
+        drop(future);
+        assert!(completer.is_cancelled());
+        assert!(cancelled.load(Ordering::Relaxed));
+
+        // The value is just dropped.
+        let value = Arc::new(());
+        completer.complete(value.clone());
+        assert_eq!(Arc::strong_count(&value), 1);
+
+        // Nothing to cancel once completed.
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let (future, completer) = CompletionFuture::new();
+        let future = future.on_cancel({
+            let cancelled = cancelled.clone();
+            move || cancelled.store(true, Ordering::Relaxed)
+        });
+        completer.complete(());
+        drop(future);
+        assert!(!cancelled.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    #[should_panic(expected = "completion handler released without having been called")]
+    fn abandoned() {
+        let future = CompletionFuture::<()>::start(|completer| {
+            std::thread::spawn(move || drop(completer));
+        });
+        block_on(future);
+    }
+
+    /// What a framework would do with a completion handler: keep it and call it later from another thread.
+    #[cfg(feature = "mock-runtime")]
+    fn call_later(
+        handler: *const crate::base::block::BlockHeader,
+        value: u32,
+    ) -> std::thread::JoinHandle<()> {
+        use crate::base::block::Block;
+        use crate::base::BOOL;
+
+        struct SendBlock(Block<'static, (u32, BOOL), ()>);
+        unsafe impl Send for SendBlock {}
+
+        let handler = SendBlock(unsafe { Block::copy_from_raw(handler) }.unwrap());
+        std::thread::spawn(move || {
+            let handler = handler;
+            handler.0.call((value, true.into()));
+        })
+    }
+
+    #[cfg(feature = "mock-runtime")]
+    #[test]
+    fn completion_handler_block() {
+        use crate::base::block::HeapBlock;
+        use crate::base::mock_runtime::LeakCheck;
+        use crate::base::BOOL;
+
+        let check = LeakCheck::start();
+        let mut caller = None;
+        let future = CompletionFuture::start(|completer| {
+            let handler = HeapBlock::new(move |value: u32, finished: BOOL| {
+                completer.complete((value, bool::from(finished)))
+            });
+            caller = Some(call_later(handler.as_ptr(), 12));
+        });
+        assert_eq!(block_on(future), (12, true));
+        caller.unwrap().join().unwrap();
+        check.assert_balanced();
+    }
+}
@@ -30,15 +30,22 @@ pub(crate) struct OpaqueCFType {
 
 #[repr(transparent)]
 #[derive(Copy, Clone, Eq, PartialEq)]
-pub struct CFTypeID(usize);
+pub struct CFTypeID(pub(crate) usize);
 
-#[link(name = "CoreFoundation", kind = "framework")]
+#[cfg_attr(
+    all(target_vendor = "apple", not(feature = "mock-runtime")),
+    link(name = "CoreFoundation", kind = "framework")
+)]
+#[cfg_attr(
+    all(not(target_vendor = "apple"), not(feature = "mock-runtime")),
+    link(name = "gnustep-corebase")
+)]
 extern "C" {
     fn CFShow(cf: RawObjPtr);
     fn CFGetRetainCount(cf: RawObjPtr) -> CFIndex;
     fn CFHash(cf: RawObjPtr) -> CFHashCode;
     fn CFEqual(cf1: RawObjPtr, cf2: RawObjPtr) -> Boolean;
-    fn CFGetTypeID(cf: RawObjPtr) -> CFTypeID;
+    pub(super) fn CFGetTypeID(cf: RawObjPtr) -> CFTypeID;
 }
 
 pub trait CFTypeInterface
@@ -0,0 +1,533 @@
+//-------------------------------------------------------------------
+// Declaring classes
+//
+// Objective-C classes implemented in Rust, for example for delegates:
+//
+// ```ignore
+// pub struct ParserDelegate {
+//     _private: PhantomData<*const ()>,
+// }
+//
+// impl Type for ParserDelegate {
+//     const KIND: TypeKind = TypeKind::ObjC;
+// }
+// impl ObjCClass for ParserDelegate {
+//     fn class() -> RawClassPtr {
+//         declared_class::<Self>()
+//     }
+// }
+// unsafe impl IsKindOf<NSObject> for ParserDelegate {}
+//
+// unsafe impl DeclareClass for ParserDelegate {
+//     type Super = NSObject;
+//     type State = Mutex<Vec<String>>;
+//     const NAME: &'static str = "ChocoParserDelegate";
+//
+//     fn declare(decl: &mut ClassDecl<Self>) {
+//         extern "C-unwind" fn did_start_element(
+//             this: &ParserDelegate, _sel: Sel, _parser: RawObjPtr, name: RawObjPtr,
+//             _namespace: Option<RawObjPtr>, _qualified_name: Option<RawObjPtr>, _attributes: RawObjPtr,
+//         ) {
+//             let name: Ptr<NSString> = unsafe { name.retain() };
+//             this.state().lock().unwrap().push(name.to_string());
+//         }
+//
+//         let sel = sel!(parser:didStartElement:namespaceURI:qualifiedName:attributes:);
+//         unsafe { decl.add_method(sel, did_start_element as extern "C-unwind" fn(&_, _, _, _, _, _, _)) };
+//         decl.add_protocol(Protocol::get("NSXMLParserDelegate").unwrap());
+//     }
+// }
+//
+// let delegate: Ptr<ParserDelegate> = ParserDelegate::with_state(Mutex::new(Vec::new()));
+// ```
+//
+// The methods get the receiver as `&Self`, from which the state can be accessed.
+
+use super::{
+    check_method_types, function_signature, send_message, send_super_message, Encode,
+    EncodeArguments, Encoding, Imp, IsKindOf, MessageReceiver, ObjCClass, Ownership, Ptr,
+    RawClassPtr, RawObjPtr, Retained, Sel, BOOL,
+};
+use super::{OpaqueIvar, Protocol};
+use crate::sel;
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::ffi::{c_char, CStr, CString};
+use std::marker::PhantomData;
+use std::ptr::NonNull;
+use std::sync::{Mutex, OnceLock};
+
+#[cfg_attr(not(feature = "mock-runtime"), link(name = "objc", kind = "dylib"))]
+extern "C" {
+    fn objc_allocateClassPair(
+        superclass: Option<RawClassPtr>,
+        name: *const c_char,
+        extra_bytes: usize,
+    ) -> Option<RawClassPtr>;
+    fn objc_registerClassPair(class: RawClassPtr);
+    fn class_addIvar(
+        class: RawClassPtr,
+        name: *const c_char,
+        size: usize,
+        alignment: u8,
+        types: *const c_char,
+    ) -> BOOL;
+    fn class_addMethod(class: RawClassPtr, sel: Sel, imp: Imp, types: *const c_char) -> BOOL;
+    fn class_addProtocol(class: RawClassPtr, protocol: Protocol) -> BOOL;
+    fn class_getInstanceVariable(
+        class: Option<RawClassPtr>,
+        name: *const c_char,
+    ) -> Option<NonNull<OpaqueIvar>>;
+    fn ivar_getOffset(ivar: NonNull<OpaqueIvar>) -> isize;
+}
+
+/// Name of the instance variable keeping the state, a pointer to a boxed `DeclareClass::State`.
+const STATE_IVAR: &CStr = c"choco_state";
+
+/// Objective-C class implemented in Rust, registered with the runtime the first time `declared_class` is called.
+///
+/// # Safety
+/// `ObjCClass::class` must return `declared_class::<Self>()`, so that objects of type `Self`
+/// are always instances of the declared class.
+///
+/// `Self` must be zero-sized, references to it being made from the object pointers,
+/// and must never be constructed: any `&Self` is taken to be an instance of the class.
+/// A private field (`_private: PhantomData<*const ()>`) keeps other modules from constructing it.
+pub unsafe trait DeclareClass: ObjCClass + IsKindOf<Self::Super> + 'static {
+    type Super: ObjCClass;
+    /// Rust value kept by each instance, dropped when the instance is deallocated.
+    ///
+    /// Instances can be used and released from any thread, so the state is accessed and dropped
+    /// from whichever thread that happens on.
+    type State: Send + Sync + 'static;
+
+    /// Name the class is registered under, that must not be used by any other class of the process.
+    const NAME: &'static str;
+
+    /// Adds the methods and protocols of the class.
+    ///
+    /// `dealloc` is implemented by choco to drop the state, and can't be added.
+    fn declare(decl: &mut ClassDecl<Self>);
+
+    /// Creates an instance with `alloc` and `init`, `state` being set before `init` is called.
+    fn with_state(state: Self::State) -> Ptr<Self, Retained> {
+        let declared = declared::<Self>();
+        unsafe {
+            let obj: Option<RawObjPtr> = send_message(declared.class, sel!(alloc), ());
+            let obj = obj.expect("expecting alloc to return a non-null value");
+            *state_location(obj, declared.state_offset) = Box::into_raw(Box::new(state));
+            // If init fails, it releases the object, which drops the state.
+            let obj: Option<RawObjPtr> = send_message(obj, sel!(init), ());
+            Ptr::from_raw_unchecked(obj.expect("expecting init to return a non-null value"))
+        }
+    }
+
+    /// State of the instance, for example `this.state()` in a method.
+    ///
+    /// Panics if the instance was not created by `with_state`.
+    fn state(&self) -> &Self::State {
+        unsafe { state::<Self>(self.as_receiver()) }
+    }
+
+    /// Retains the instance, for example to keep the receiver of a method.
+    fn retain(&self) -> Ptr<Self, Retained> {
+        unsafe { self.as_receiver().retain() }
+    }
+}
+
+// The methods get the receiver as `&T`, so that messages can also be sent to it.
+impl<T: DeclareClass> MessageReceiver for &T {
+    fn as_receiver(&self) -> RawObjPtr {
+        const { assert!(std::mem::size_of::<T>() == 0) };
+        // `T` being zero-sized, the reference gives access to none of the object's memory:
+        // only its address is used, with the provenance the object was given by the runtime.
+        let addr = (*self as *const T).addr();
+        RawObjPtr {
+            ptr: NonNull::new(std::ptr::with_exposed_provenance_mut(addr)).unwrap(),
+        }
+    }
+}
+
+impl<T: DeclareClass, O: Ownership> Ptr<T, O> {
+    /// Same as `DeclareClass::state`.
+    pub fn state(&self) -> &T::State {
+        unsafe { state::<T>(self.as_raw()) }
+    }
+}
+
+/// # Safety
+/// `obj` must be an instance of `T`, that must outlive the reference returned.
+unsafe fn state<'a, T: DeclareClass>(obj: RawObjPtr) -> &'a T::State {
+    let state_offset = declared::<T>().state_offset;
+    let state = *state_location::<T::State>(obj, state_offset);
+    assert!(
+        !state.is_null(),
+        "instances of {} must be created with DeclareClass::with_state",
+        T::NAME
+    );
+    &*state
+}
+
+/// Location of the pointer to the state of `obj`.
+unsafe fn state_location<State>(obj: RawObjPtr, state_offset: isize) -> *mut *mut State {
+    obj.ptr
+        .as_ptr()
+        .cast::<u8>()
+        .offset(state_offset)
+        .cast::<*mut State>()
+}
+
+/// Function implementing a method of `T`: an `extern "C-unwind" fn` taking the receiver as `&T`,
+/// the selector, then the arguments. Function items have to be cast to a function pointer:
+///
+/// ```ignore
+/// decl.add_method(sel!(count), count as extern "C-unwind" fn(&_, _) -> _)
+/// ```
+pub trait MethodImplementation<T> {
+    type Args: EncodeArguments;
+    type Ret: Encode;
+
+    fn imp(self) -> Imp;
+}
+
+macro_rules! impl_method_implementation {
+    ($($arg:ident),*) => {
+        impl<T, Ret: Encode, $($arg: Encode),*> MethodImplementation<T>
+            for extern "C-unwind" fn(&T, Sel $(, $arg)*) -> Ret
+        {
+            type Args = ($($arg,)*);
+            type Ret = Ret;
+
+            fn imp(self) -> Imp {
+                unsafe { std::mem::transmute::<Self, Imp>(self) }
+            }
+        }
+    };
+}
+
+impl_method_implementation!();
+impl_method_implementation!(A1);
+impl_method_implementation!(A1, A2);
+impl_method_implementation!(A1, A2, A3);
+impl_method_implementation!(A1, A2, A3, A4);
+impl_method_implementation!(A1, A2, A3, A4, A5);
+impl_method_implementation!(A1, A2, A3, A4, A5, A6);
+impl_method_implementation!(A1, A2, A3, A4, A5, A6, A7);
+impl_method_implementation!(A1, A2, A3, A4, A5, A6, A7, A8);
+impl_method_implementation!(A1, A2, A3, A4, A5, A6, A7, A8, A9);
+impl_method_implementation!(A1, A2, A3, A4, A5, A6, A7, A8, A9, A10);
+impl_method_implementation!(A1, A2, A3, A4, A5, A6, A7, A8, A9, A10, A11);
+impl_method_implementation!(A1, A2, A3, A4, A5, A6, A7, A8, A9, A10, A11, A12);
+
+/// Class being declared, given to `DeclareClass::declare`.
+pub struct ClassDecl<T> {
+    class: RawClassPtr,
+    _marker: PhantomData<T>,
+}
+
+impl<T: DeclareClass> ClassDecl<T> {
+    /// Adds an instance method, its type encoding being derived from the signature of `imp`.
+    ///
+    /// In debug builds, panics if it overrides a method of the superclass with different types.
+    ///
+    /// # Safety
+    /// The method will be called with the types callers expect for that selector, for example the ones
+    /// declared by the protocol the method is part of, which must be the types of `imp`.
+    pub unsafe fn add_method<F: MethodImplementation<T>>(&mut self, sel: Sel, imp: F) {
+        if cfg!(debug_assertions) {
+            check_method_types::<F::Args, F::Ret>(Some(T::Super::class()), sel, "override");
+        }
+        let types = function_signature::<F::Args, F::Ret>(&[Encoding::Object, Encoding::Sel]);
+        let added = class_addMethod(self.class, sel, imp.imp(), types.as_ptr());
+        assert!(
+            bool::from(added),
+            "method {:?} added twice to {}",
+            sel,
+            T::NAME
+        );
+    }
+
+    pub fn add_protocol(&mut self, protocol: Protocol) {
+        unsafe { class_addProtocol(self.class, protocol) };
+    }
+}
+
+/// Implementation of `dealloc` of all declared classes, dropping the state.
+extern "C-unwind" fn dealloc<T: DeclareClass>(this: &T, sel: Sel) {
+    let state_offset = declared::<T>().state_offset;
+    unsafe {
+        let location = state_location::<T::State>(this.as_receiver(), state_offset);
+        let state = std::mem::replace(&mut *location, std::ptr::null_mut());
+        if !state.is_null() {
+            drop(Box::from_raw(state));
+        }
+        send_super_message::<_, (), ()>(this, T::Super::class(), sel, ())
+    }
+}
+
+#[derive(Copy, Clone)]
+struct DeclaredClass {
+    class: RawClassPtr,
+    /// Offset of the state instance variable.
+    state_offset: isize,
+}
+
+// Classes are never deallocated.
+unsafe impl Send for DeclaredClass {}
+unsafe impl Sync for DeclaredClass {}
+
+/// Class registered for `T`, registering it if needed.
+///
+/// Panics if another class with the same name is already registered.
+pub fn declared_class<T: DeclareClass>() -> RawClassPtr {
+    declared::<T>().class
+}
+
+fn declared<T: DeclareClass>() -> DeclaredClass {
+    static DECLARED: OnceLock<Mutex<HashMap<TypeId, &'static OnceLock<DeclaredClass>>>> =
+        OnceLock::new();
+
+    // The table is not kept locked while declaring, as `declare` can use other declared classes.
+    let once = *DECLARED
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .entry(TypeId::of::<T>())
+        .or_insert_with(|| Box::leak(Box::new(OnceLock::new())));
+    *once.get_or_init(declare::<T>)
+}
+
+fn declare<T: DeclareClass>() -> DeclaredClass {
+    const {
+        assert!(
+            std::mem::size_of::<T>() == 0,
+            "classes declared in Rust must be zero-sized"
+        )
+    };
+    let name = CString::new(T::NAME).expect("expecting the class name to not contain NUL");
+    let class = unsafe { objc_allocateClassPair(Some(T::Super::class()), name.as_ptr(), 0) }
+        .unwrap_or_else(|| panic!("a class named {} is already registered", T::NAME));
+
+    let pointer_size = std::mem::size_of::<*mut T::State>();
+    let alignment = pointer_size.trailing_zeros() as u8;
+    let types = CString::new(<*mut std::ffi::c_void>::encoding().to_string()).unwrap();
+    let added = unsafe {
+        class_addIvar(
+            class,
+            STATE_IVAR.as_ptr(),
+            pointer_size,
+            alignment,
+            types.as_ptr(),
+        )
+    };
+    assert!(bool::from(added), "expecting class_addIvar() to succeed");
+
+    let mut decl = ClassDecl {
+        class,
+        _marker: PhantomData,
+    };
+    unsafe { decl.add_method(sel!(dealloc), dealloc::<T> as extern "C-unwind" fn(&_, _)) };
+    T::declare(&mut decl);
+    unsafe { objc_registerClassPair(class) };
+
+    // The runtime can move instance variables when registering the class, to make room for the ones of the superclass.
+    let ivar = unsafe { class_getInstanceVariable(Some(class), STATE_IVAR.as_ptr()) }
+        .expect("expecting the state instance variable to be found");
+    DeclaredClass {
+        class,
+        state_offset: unsafe { ivar_getOffset(ivar) },
+    }
+}
+
+#[cfg(all(test, feature = "mock-runtime"))]
+mod declare_tests {
+    use super::*;
+    use crate::base::mock_runtime::{register_class, register_protocol, LeakCheck};
+    use crate::base::{Class, Type, TypeKind};
+    use crate::msg_send;
+    use std::sync::Arc;
+
+    struct MockRoot {}
+
+    impl Type for MockRoot {
+        const KIND: TypeKind = TypeKind::ObjC;
+    }
+
+    impl ObjCClass for MockRoot {
+        fn class() -> RawClassPtr {
+            register_class("MockDeclareRoot")
+        }
+    }
+
+    struct Counter {
+        _private: PhantomData<*const ()>,
+    }
+
+    impl Type for Counter {
+        const KIND: TypeKind = TypeKind::ObjC;
+    }
+
+    impl ObjCClass for Counter {
+        fn class() -> RawClassPtr {
+            declared_class::<Self>()
+        }
+    }
+
+    unsafe impl IsKindOf<MockRoot> for Counter {}
+
+    struct CounterState {
+        count: Mutex<u32>,
+        /// To check the state gets dropped.
+        alive: Arc<()>,
+    }
+
+    unsafe impl DeclareClass for Counter {
+        type Super = MockRoot;
+        type State = CounterState;
+        const NAME: &'static str = "MockCounter";
+
+        fn declare(decl: &mut ClassDecl<Self>) {
+            extern "C-unwind" fn increment(this: &Counter, _sel: Sel) {
+                *this.state().count.lock().unwrap() += 1;
+            }
+
+            extern "C-unwind" fn add(this: &Counter, _sel: Sel, value: u32) -> u32 {
+                let mut count = this.state().count.lock().unwrap();
+                *count += value;
+                *count
+            }
+
+            extern "C-unwind" fn count(this: &Counter, _sel: Sel) -> u32 {
+                *this.state().count.lock().unwrap()
+            }
+
+            // Sending a message to the receiver itself.
+            extern "C-unwind" fn add_twice(this: &Counter, _sel: Sel, value: u32) -> u32 {
+                unsafe {
+                    let _: u32 = msg_send![this, add: value];
+                    msg_send![this, add: value]
+                }
+            }
+
+            unsafe {
+                decl.add_method(sel!(increment), increment as extern "C-unwind" fn(&_, _));
+                decl.add_method(sel!(add:), add as extern "C-unwind" fn(&_, _, _) -> _);
+                decl.add_method(sel!(count), count as extern "C-unwind" fn(&_, _) -> _);
+                decl.add_method(
+                    sel!(addTwice:),
+                    add_twice as extern "C-unwind" fn(&_, _, _) -> _,
+                );
+            }
+            decl.add_protocol(register_protocol(c"MockCounting"));
+        }
+    }
+
+    fn new_counter(alive: &Arc<()>) -> Ptr<Counter> {
+        Counter::with_state(CounterState {
+            count: Mutex::new(0),
+            alive: alive.clone(),
+        })
+    }
+
+    #[test]
+    fn methods() {
+        let check = LeakCheck::start();
+        let alive = Arc::new(());
+        let counter = new_counter(&alive);
+        unsafe {
+            let _: () = msg_send![&counter, increment];
+            let count: u32 = msg_send![&counter, add: 2u32];
+            assert_eq!(count, 3);
+            let count: u32 = msg_send![&counter, addTwice: 10u32];
+            assert_eq!(count, 23);
+        }
+        assert_eq!(*counter.state().count.lock().unwrap(), 23);
+
+        let other = new_counter(&alive);
+        let count: u32 = unsafe { msg_send![&other, count] };
+        assert_eq!(count, 0);
+        assert_eq!(Arc::strong_count(&alive), 3);
+        drop(other);
+        // Dropped by dealloc.
+        assert_eq!(Arc::strong_count(&alive), 2);
+        drop(counter);
+        assert_eq!(Arc::strong_count(&alive), 1);
+        check.assert_balanced();
+    }
+
+    #[test]
+    fn class() {
+        let class = Class::of::<Counter>();
+        assert_eq!(class.name(), c"MockCounter");
+        assert_eq!(class.superclass(), Some(Class::of::<MockRoot>()));
+        assert!(class.responds_to(sel!(addTwice:)));
+        assert!(class.conforms_to(register_protocol(c"MockCounting")));
+        let add = class
+            .instance_methods()
+            .into_iter()
+            .find(|method| method.sel() == sel!(add:))
+            .unwrap();
+        assert_eq!(add.type_encoding(), c"I20@0:8I16");
+    }
+
+    #[test]
+    fn upcast() {
+        let alive = Arc::new(());
+        let root: Ptr<MockRoot> = new_counter(&alive).upcast();
+        let counter = root.downcast::<Counter>().ok().unwrap();
+        let retained = counter.retain();
+        drop(counter);
+        let count: u32 = unsafe { msg_send![&retained, add: 1u32] };
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "instances of MockCounter must be created with DeclareClass::with_state"
+    )]
+    fn without_state() {
+        let counter: Ptr<Counter> = unsafe {
+            let obj: RawObjPtr = msg_send![Counter::class(), alloc];
+            let obj: RawObjPtr = msg_send![obj, init];
+            Ptr::from_raw_unchecked(obj)
+        };
+        counter.state();
+    }
+
+    struct BadOverride {
+        _private: PhantomData<*const ()>,
+    }
+
+    impl Type for BadOverride {
+        const KIND: TypeKind = TypeKind::ObjC;
+    }
+
+    impl ObjCClass for BadOverride {
+        fn class() -> RawClassPtr {
+            declared_class::<Self>()
+        }
+    }
+
+    unsafe impl IsKindOf<MockRoot> for BadOverride {}
+
+    unsafe impl DeclareClass for BadOverride {
+        type Super = MockRoot;
+        type State = ();
+        const NAME: &'static str = "MockBadOverride";
+
+        fn declare(decl: &mut ClassDecl<Self>) {
+            extern "C-unwind" fn init(_this: &BadOverride, _sel: Sel) -> u32 {
+                0
+            }
+
+            unsafe { decl.add_method(sel!(init), init as extern "C-unwind" fn(&_, _) -> _) };
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "wrong types used to override init")]
+    fn wrong_override() {
+        BadOverride::class();
+    }
+}
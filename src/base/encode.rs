@@ -0,0 +1,777 @@
+//-------------------------------------------------------------------
+// Type encodings
+//
+// Same format as the strings generated by `@encode` and stored by the Objective-C runtime for methods and ivars.
+// Reference: <https://developer.apple.com/library/archive/documentation/Cocoa/Conceptual/ObjCRuntimeGuide/Articles/ocrtTypeEncodings.html>
+
+use super::{Boolean, RawClassPtr, RawObjPtr, BOOL};
+use std::collections::HashSet;
+use std::ffi::{c_int, c_void, CStr, CString};
+use std::fmt::Write;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+
+/// Parsed type encoding.
+///
+/// Information not affecting the layout (method qualifiers like `const`, class names of objects,
+/// field names of structures, signatures of blocks) is dropped when parsing.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Encoding {
+    /// `c` (also used for `BOOL` on x86_64 Apple platforms)
+    Char,
+    /// `s`
+    Short,
+    /// `i`
+    Int,
+    /// `l`, always 32-bit in type encodings
+    Long,
+    /// `q`
+    LongLong,
+    /// `t`
+    Int128,
+    /// `C`
+    UChar,
+    /// `S`
+    UShort,
+    /// `I`
+    UInt,
+    /// `L`, always 32-bit in type encodings
+    ULong,
+    /// `Q`
+    ULongLong,
+    /// `T`
+    UInt128,
+    /// `f`
+    Float,
+    /// `d`
+    Double,
+    /// `D`
+    LongDouble,
+    /// `B`, C++ `bool` or C99 `_Bool`
+    Bool,
+    /// `v`
+    Void,
+    /// `*`, `char *`
+    CString,
+    /// `@`, an Objective-C object
+    Object,
+    /// `@?`, a block
+    Block,
+    /// `#`
+    Class,
+    /// `:`
+    Sel,
+    /// `?`, unknown type, for example a function (`^?` being a function pointer)
+    Unknown,
+    /// `bN`, bit field of N bits
+    BitField(u8),
+    /// `^type`
+    Pointer(Box<Encoding>),
+    /// `[Ntype]`
+    Array(u64, Box<Encoding>),
+    /// `{name=fields}`, the fields being `None` when the structure is not detailed (`{name}`).
+    /// The name is `?` for anonymous structures (including `typedef struct { ... } Name;`).
+    Struct {
+        name: String,
+        fields: Option<Vec<Encoding>>,
+    },
+    /// `(name=fields)`, same as `Struct` for unions.
+    Union {
+        name: String,
+        fields: Option<Vec<Encoding>>,
+    },
+}
+
+impl Encoding {
+    /// Parses the encoding at the start of `s`, returning it with the rest of the string.
+    pub fn parse_prefix(s: &str) -> Result<(Self, &str), ParseEncodingError> {
+        let mut parser = Parser {
+            text: s,
+            position: 0,
+        };
+        let encoding = parser.parse()?;
+        Ok((encoding, &s[parser.position..]))
+    }
+
+    fn size_class(&self) -> Option<LayoutClass> {
+        use Encoding::*;
+        let class = match self {
+            Char | UChar | Bool => LayoutClass::Integer(1),
+            Short | UShort => LayoutClass::Integer(2),
+            Int | UInt | Long | ULong => LayoutClass::Integer(4),
+            LongLong | ULongLong => LayoutClass::Integer(8),
+            Int128 | UInt128 => LayoutClass::Integer(16),
+            CString | Object | Block | Class | Sel | Pointer(_) => LayoutClass::Pointer,
+            _ => return None,
+        };
+        Some(class)
+    }
+
+    /// Whether values of both types have the same memory layout.
+    ///
+    /// Integers of the same size (`BOOL` included) are compatible whatever their signedness,
+    /// pointers of any kind are compatible with each other, and names of structures and unions
+    /// are only compared when their fields are unknown.
+    pub fn is_layout_compatible(&self, other: &Self) -> bool {
+        use Encoding::*;
+        if let (Some(class), Some(other_class)) = (self.size_class(), other.size_class()) {
+            return class == other_class;
+        }
+        match (self, other) {
+            (Array(len, element), Array(other_len, other_element)) => {
+                len == other_len && element.is_layout_compatible(other_element)
+            }
+            (
+                Struct { name, fields },
+                Struct {
+                    name: other_name,
+                    fields: other_fields,
+                },
+            )
+            | (
+                Union { name, fields },
+                Union {
+                    name: other_name,
+                    fields: other_fields,
+                },
+            ) => match (fields, other_fields) {
+                (Some(fields), Some(other_fields)) => {
+                    fields.len() == other_fields.len()
+                        && fields
+                            .iter()
+                            .zip(other_fields)
+                            .all(|(field, other_field)| field.is_layout_compatible(other_field))
+                }
+                _ => name == other_name && name != "?",
+            },
+            _ => self == other,
+        }
+    }
+}
+
+/// Classes of types having the same layout.
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum LayoutClass {
+    Integer(usize),
+    Pointer,
+}
+
+impl std::fmt::Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Encoding::*;
+        let code = match self {
+            Char => "c",
+            Short => "s",
+            Int => "i",
+            Long => "l",
+            LongLong => "q",
+            Int128 => "t",
+            UChar => "C",
+            UShort => "S",
+            UInt => "I",
+            ULong => "L",
+            ULongLong => "Q",
+            UInt128 => "T",
+            Float => "f",
+            Double => "d",
+            LongDouble => "D",
+            Bool => "B",
+            Void => "v",
+            CString => "*",
+            Object => "@",
+            Block => "@?",
+            Class => "#",
+            Sel => ":",
+            Unknown => "?",
+            BitField(bits) => return write!(f, "b{}", bits),
+            Pointer(pointee) => return write!(f, "^{}", pointee),
+            Array(len, element) => return write!(f, "[{}{}]", len, element),
+            Struct { name, fields } => return write_aggregate(f, '{', '}', name, fields),
+            Union { name, fields } => return write_aggregate(f, '(', ')', name, fields),
+        };
+        f.write_str(code)
+    }
+}
+
+fn write_aggregate(
+    f: &mut std::fmt::Formatter<'_>,
+    open: char,
+    close: char,
+    name: &str,
+    fields: &Option<Vec<Encoding>>,
+) -> std::fmt::Result {
+    write!(f, "{}{}", open, name)?;
+    if let Some(fields) = fields {
+        f.write_str("=")?;
+        for field in fields {
+            write!(f, "{}", field)?;
+        }
+    }
+    write!(f, "{}", close)
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ParseEncodingError {
+    /// Position in bytes in the parsed string where the error was found.
+    pub position: usize,
+}
+
+impl std::fmt::Display for ParseEncodingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid type encoding at byte {}", self.position)
+    }
+}
+
+impl std::error::Error for ParseEncodingError {}
+
+/// Parses a string containing exactly one encoding.
+impl FromStr for Encoding {
+    type Err = ParseEncodingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (encoding, rest) = Self::parse_prefix(s)?;
+        if !rest.is_empty() {
+            return Err(ParseEncodingError {
+                position: s.len() - rest.len(),
+            });
+        }
+        Ok(encoding)
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self) -> ParseEncodingError {
+        ParseEncodingError {
+            position: self.position,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Result<u8, ParseEncodingError> {
+        let byte = self.peek().ok_or_else(|| self.error())?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<(), ParseEncodingError> {
+        if self.peek() != Some(expected) {
+            return Err(self.error());
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn number(&mut self) -> Result<u64, ParseEncodingError> {
+        let start = self.position;
+        while matches!(self.peek(), Some(b'0'..=b'9')) {
+            self.position += 1;
+        }
+        self.text[start..self.position]
+            .parse()
+            .map_err(|_| ParseEncodingError { position: start })
+    }
+
+    /// Skips everything up to the given delimiter (included).
+    fn skip_past(&mut self, delimiter: u8) -> Result<&str, ParseEncodingError> {
+        let start = self.position;
+        while self.next()? != delimiter {}
+        Ok(&self.text[start..self.position - 1])
+    }
+
+    fn parse(&mut self) -> Result<Encoding, ParseEncodingError> {
+        use Encoding::*;
+        // Method qualifiers: const, in, inout, out, bycopy, byref, oneway.
+        while matches!(
+            self.peek(),
+            Some(b'r' | b'n' | b'N' | b'o' | b'O' | b'R' | b'V')
+        ) {
+            self.position += 1;
+        }
+        let start = self.position;
+        let encoding = match self.next()? {
+            b'c' => Char,
+            b's' => Short,
+            b'i' => Int,
+            b'l' => Long,
+            b'q' => LongLong,
+            b't' => Int128,
+            b'C' => UChar,
+            b'S' => UShort,
+            b'I' => UInt,
+            b'L' => ULong,
+            b'Q' => ULongLong,
+            b'T' => UInt128,
+            b'f' => Float,
+            b'd' => Double,
+            b'D' => LongDouble,
+            b'B' => Bool,
+            b'v' => Void,
+            b'*' => CString,
+            b'#' => Class,
+            b':' => Sel,
+            b'?' => Unknown,
+            b'@' => match self.peek() {
+                Some(b'?') => {
+                    self.position += 1;
+                    // Extended block signature, for example `@?<v@?@>`.
+                    if self.peek() == Some(b'<') {
+                        self.position += 1;
+                        self.skip_block_signature()?;
+                    }
+                    Block
+                }
+                Some(b'"') => {
+                    // Class name (or protocols) of the object.
+                    self.position += 1;
+                    self.skip_past(b'"')?;
+                    Object
+                }
+                _ => Object,
+            },
+            b'b' => {
+                let bits = self.number()?;
+                BitField(u8::try_from(bits).map_err(|_| ParseEncodingError { position: start })?)
+            }
+            b'^' => Pointer(Box::new(self.parse()?)),
+            b'[' => {
+                let len = self.number()?;
+                let element = self.parse()?;
+                self.expect(b']')?;
+                Array(len, Box::new(element))
+            }
+            b'{' => {
+                let (name, fields) = self.parse_aggregate(b'}')?;
+                Struct { name, fields }
+            }
+            b'(' => {
+                let (name, fields) = self.parse_aggregate(b')')?;
+                Union { name, fields }
+            }
+            _ => return Err(ParseEncodingError { position: start }),
+        };
+        Ok(encoding)
+    }
+
+    fn skip_block_signature(&mut self) -> Result<(), ParseEncodingError> {
+        while self.peek() != Some(b'>') {
+            self.parse()?;
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn parse_aggregate(
+        &mut self,
+        close: u8,
+    ) -> Result<(String, Option<Vec<Encoding>>), ParseEncodingError> {
+        let start = self.position;
+        while !matches!(self.peek(), Some(b'=') | Some(b'}') | Some(b')')) {
+            self.next()?;
+        }
+        let name = self.text[start..self.position].to_owned();
+        if self.next()? == close {
+            return Ok((name, None));
+        }
+        if self.text.as_bytes()[self.position - 1] != b'=' {
+            return Err(ParseEncodingError {
+                position: self.position - 1,
+            });
+        }
+        let mut fields = Vec::new();
+        while self.peek() != Some(close) {
+            // Field names, as found in ivar encodings.
+            if self.peek() == Some(b'"') {
+                self.position += 1;
+                self.skip_past(b'"')?;
+            }
+            fields.push(self.parse()?);
+        }
+        self.position += 1;
+        Ok((name, Some(fields)))
+    }
+}
+
+/// Type with a known Objective-C type encoding.
+///
+/// Structures can implement it with `encode_struct!`.
+///
+/// # Safety
+/// The encoding must describe the memory layout of the type.
+pub unsafe trait Encode {
+    fn encoding() -> Encoding;
+}
+
+macro_rules! impl_encode {
+    ($($ty:ty => $encoding:expr,)*) => {
+        $(
+            unsafe impl Encode for $ty {
+                fn encoding() -> Encoding {
+                    $encoding
+                }
+            }
+        )*
+    };
+}
+
+impl_encode! {
+    i8 => Encoding::Char,
+    i16 => Encoding::Short,
+    i32 => Encoding::Int,
+    i64 => Encoding::LongLong,
+    i128 => Encoding::Int128,
+    u8 => Encoding::UChar,
+    u16 => Encoding::UShort,
+    u32 => Encoding::UInt,
+    u64 => Encoding::ULongLong,
+    u128 => Encoding::UInt128,
+    f32 => Encoding::Float,
+    f64 => Encoding::Double,
+    bool => Encoding::Bool,
+    () => Encoding::Void,
+    c_void => Encoding::Void,
+    RawObjPtr => Encoding::Object,
+    Option<RawObjPtr> => Encoding::Object,
+    RawClassPtr => Encoding::Class,
+    Option<RawClassPtr> => Encoding::Class,
+    Boolean => Encoding::UChar,
+}
+
+// `NSInteger` and `NSUInteger` are `long` on 64-bit platforms, encoded as 64-bit integers,
+// and `int` on 32-bit ones.
+#[cfg(target_pointer_width = "64")]
+impl_encode! {
+    isize => Encoding::LongLong,
+    usize => Encoding::ULongLong,
+}
+#[cfg(target_pointer_width = "32")]
+impl_encode! {
+    isize => Encoding::Int,
+    usize => Encoding::UInt,
+}
+
+// `BOOL` is a `bool` on Apple's ARM platforms, a `signed char` on x86_64 and an `unsigned char` with libobjc2.
+#[cfg(all(target_vendor = "apple", target_arch = "aarch64"))]
+impl_encode! { BOOL => Encoding::Bool, }
+#[cfg(all(target_vendor = "apple", not(target_arch = "aarch64")))]
+impl_encode! { BOOL => Encoding::Char, }
+#[cfg(not(target_vendor = "apple"))]
+impl_encode! { BOOL => Encoding::UChar, }
+
+unsafe impl<T: Encode> Encode for *const T {
+    fn encoding() -> Encoding {
+        Encoding::Pointer(Box::new(T::encoding()))
+    }
+}
+
+unsafe impl<T: Encode> Encode for *mut T {
+    fn encoding() -> Encoding {
+        Encoding::Pointer(Box::new(T::encoding()))
+    }
+}
+
+unsafe impl<T: Encode, const N: usize> Encode for [T; N] {
+    fn encoding() -> Encoding {
+        Encoding::Array(N as u64, Box::new(T::encoding()))
+    }
+}
+
+/// Arguments of a function or block, as a tuple of types having an encoding.
+pub trait EncodeArguments {
+    /// Encoding and size in bytes of each argument.
+    fn encodings() -> Vec<(Encoding, usize)>;
+}
+
+macro_rules! impl_encode_arguments {
+    ($($arg:ident),*) => {
+        impl<$($arg: Encode),*> EncodeArguments for ($($arg,)*) {
+            fn encodings() -> Vec<(Encoding, usize)> {
+                vec![$(($arg::encoding(), std::mem::size_of::<$arg>())),*]
+            }
+        }
+    };
+}
+
+impl_encode_arguments!();
+impl_encode_arguments!(A1);
+impl_encode_arguments!(A1, A2);
+impl_encode_arguments!(A1, A2, A3);
+impl_encode_arguments!(A1, A2, A3, A4);
+impl_encode_arguments!(A1, A2, A3, A4, A5);
+impl_encode_arguments!(A1, A2, A3, A4, A5, A6);
+impl_encode_arguments!(A1, A2, A3, A4, A5, A6, A7);
+impl_encode_arguments!(A1, A2, A3, A4, A5, A6, A7, A8);
+impl_encode_arguments!(A1, A2, A3, A4, A5, A6, A7, A8, A9);
+impl_encode_arguments!(A1, A2, A3, A4, A5, A6, A7, A8, A9, A10);
+impl_encode_arguments!(A1, A2, A3, A4, A5, A6, A7, A8, A9, A10, A11);
+impl_encode_arguments!(A1, A2, A3, A4, A5, A6, A7, A8, A9, A10, A11, A12);
+
+/// Type encoding of a function (block or method implementation) in the format clang uses:
+/// the return type, the size of the parameters, and each parameter followed by its offset,
+/// `implicit` being the pointer-sized parameters coming before the arguments,
+/// for example `v24@?0@8Q16` for a block `void (^)(id, NSUInteger)`, or `v24@0:8Q16` for a method.
+///
+/// The string is only built once for each signature.
+pub(super) fn function_signature<Args: EncodeArguments, Ret: Encode>(
+    implicit: &[Encoding],
+) -> &'static CStr {
+    static SIGNATURES: OnceLock<Mutex<HashSet<&'static CStr>>> = OnceLock::new();
+
+    let pointer_size = std::mem::size_of::<*const c_void>();
+    let arguments: Vec<(Encoding, usize)> = implicit
+        .iter()
+        .map(|encoding| (encoding.clone(), pointer_size))
+        .chain(Args::encodings().into_iter().map(|(encoding, size)| {
+            // Like in C, integers smaller than an `int` take the space of an `int`.
+            let promoted = matches!(
+                encoding,
+                Encoding::Char
+                    | Encoding::UChar
+                    | Encoding::Short
+                    | Encoding::UShort
+                    | Encoding::Bool
+            );
+            let size = if promoted {
+                size.max(std::mem::size_of::<c_int>())
+            } else {
+                size
+            };
+            (encoding, size)
+        }))
+        .collect();
+    let arguments_size: usize = arguments.iter().map(|(_, size)| size).sum();
+
+    let mut text = format!("{}{}", Ret::encoding(), arguments_size);
+    let mut offset = 0;
+    for (encoding, size) in arguments {
+        write!(text, "{}{}", encoding, offset).unwrap();
+        offset += size;
+    }
+    let text =
+        CString::new(text).expect("expecting type encodings to not contain any nul character");
+
+    let mut signatures = SIGNATURES.get_or_init(Default::default).lock().unwrap();
+    if let Some(signature) = signatures.get(text.as_c_str()) {
+        return signature;
+    }
+    let signature: &'static CStr = Box::leak(text.into_boxed_c_str());
+    signatures.insert(signature);
+    signature
+}
+
+/// Implements `Encode` for a `#[repr(C)]` structure, given the name of the structure in C
+/// (`?` for anonymous ones, including `typedef struct { ... } Name;`) and all its fields in order:
+///
+/// ```ignore
+/// encode_struct!(CGPoint = "CGPoint" { x: CGFloat, y: CGFloat });
+/// ```
+///
+/// The offsets of the fields and the size of the structure are checked at compile time
+/// against the layout C would use for those fields.
+#[macro_export]
+macro_rules! encode_struct {
+    ($ty:ident = $name:literal { $($field:ident: $field_ty:ty),* $(,)? }) => {
+        const _: () = {
+            let mut offset = 0usize;
+            let mut align = 1usize;
+            $(
+                let field_align = ::std::mem::align_of::<$field_ty>();
+                offset = offset.div_ceil(field_align) * field_align;
+                assert!(
+                    ::std::mem::offset_of!($ty, $field) == offset,
+                    concat!("unexpected offset for ", stringify!($ty), "::", stringify!($field))
+                );
+                offset += ::std::mem::size_of::<$field_ty>();
+                if field_align > align {
+                    align = field_align;
+                }
+            )*
+            assert!(
+                ::std::mem::size_of::<$ty>() == offset.div_ceil(align) * align,
+                concat!("unexpected size for ", stringify!($ty))
+            );
+        };
+
+        unsafe impl $crate::base::Encode for $ty {
+            fn encoding() -> $crate::base::Encoding {
+                // Makes sure all the fields are listed, with the right types.
+                let _check_fields = |value: &$ty| {
+                    let $ty { $($field: _),* } = value;
+                    $(let _: &$field_ty = &value.$field;)*
+                };
+                $crate::base::Encoding::Struct {
+                    name: ::std::string::String::from($name),
+                    fields: ::std::option::Option::Some(::std::vec![
+                        $(<$field_ty as $crate::base::Encode>::encoding()),*
+                    ]),
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod encode_tests {
+    use super::*;
+
+    fn parse(s: &str) -> Encoding {
+        s.parse()
+            .unwrap_or_else(|err| panic!("failed to parse {:?}: {}", s, err))
+    }
+
+    #[test]
+    fn primitives() {
+        assert_eq!(i32::encoding().to_string(), "i");
+        assert_eq!(u64::encoding().to_string(), "Q");
+        assert_eq!(f64::encoding().to_string(), "d");
+        assert_eq!(<()>::encoding().to_string(), "v");
+        assert_eq!(RawObjPtr::encoding().to_string(), "@");
+        assert_eq!(<Option<RawClassPtr>>::encoding().to_string(), "#");
+        assert_eq!(<*const c_void>::encoding().to_string(), "^v");
+        assert_eq!(<*mut *mut Option<RawObjPtr>>::encoding().to_string(), "^^@");
+        assert_eq!(<[usize; 5]>::encoding().to_string(), "[5Q]");
+        assert_eq!(Boolean::encoding().to_string(), "C");
+        assert!(BOOL::encoding().is_layout_compatible(&Encoding::Char));
+    }
+
+    #[derive(Copy, Clone)]
+    #[repr(C)]
+    struct Inner {
+        a: u8,
+        b: f64,
+    }
+    encode_struct!(Inner = "Inner" { a: u8, b: f64 });
+
+    #[repr(C)]
+    struct Outer {
+        inner: Inner,
+        pointer: *const Inner,
+        values: [i16; 3],
+    }
+    encode_struct!(Outer = "?" {
+        inner: Inner,
+        pointer: *const Inner,
+        values: [i16; 3],
+    });
+
+    #[test]
+    fn structs() {
+        assert_eq!(Inner::encoding().to_string(), "{Inner=Cd}");
+        assert_eq!(
+            Outer::encoding().to_string(),
+            "{?={Inner=Cd}^{Inner=Cd}[3s]}"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        for s in [
+            "v",
+            "@",
+            "@?",
+            "^?",
+            "^^v",
+            "[12^f]",
+            "{CGRect={CGPoint=dd}{CGSize=dd}}",
+            "{?=qiIq}",
+            "{?={?=qiIq}{?=qiIq}}",
+            "{_NSRange=QQ}",
+            "^{__CFString}",
+            "(?=iCd)",
+            "{bits=b1b7b24}",
+            "{?=Q^@^Q[5Q]}",
+            "{empty=}",
+        ] {
+            assert_eq!(parse(s).to_string(), s);
+        }
+    }
+
+    #[test]
+    fn parse_details() {
+        assert_eq!(parse("r*"), Encoding::CString);
+        assert_eq!(parse("Vv"), Encoding::Void);
+        assert_eq!(parse("@\"NSString\""), Encoding::Object);
+        assert_eq!(parse("@\"<NSCopying>\""), Encoding::Object);
+        assert_eq!(parse("@?<v@?@\"NSError\">"), Encoding::Block);
+        assert_eq!(
+            parse("{CGPoint=\"x\"d\"y\"d}"),
+            Encoding::Struct {
+                name: "CGPoint".to_owned(),
+                fields: Some(vec![Encoding::Double, Encoding::Double]),
+            }
+        );
+        assert_eq!(
+            parse("^{CGPoint}"),
+            Encoding::Pointer(Box::new(Encoding::Struct {
+                name: "CGPoint".to_owned(),
+                fields: None
+            }))
+        );
+        assert_eq!(
+            Encoding::parse_prefix("@16@0:8"),
+            Ok((Encoding::Object, "16@0:8"))
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        for (s, position) in [
+            ("", 0),
+            ("x", 0),
+            ("ii", 1),
+            ("^", 1),
+            ("[3", 2),
+            ("[i]", 1),
+            ("[3i", 3),
+            ("{CGPoint=dd", 11),
+            ("{CGPoint=dd)", 11),
+            ("{CGPoint)", 8),
+            ("@\"NSString", 10),
+            ("b999", 0),
+            ("@?<v", 4),
+        ] {
+            assert_eq!(
+                s.parse::<Encoding>(),
+                Err(ParseEncodingError { position }),
+                "{:?}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn layout_compatibility() {
+        let compatible = |a: &str, b: &str| parse(a).is_layout_compatible(&parse(b));
+        assert!(compatible("c", "B"));
+        assert!(compatible("i", "I"));
+        assert!(compatible("l", "i"));
+        assert!(!compatible("l", "q"));
+        assert!(!compatible("i", "f"));
+        assert!(!compatible("d", "q"));
+        assert!(compatible("@", "^v"));
+        assert!(compatible("*", "^c"));
+        assert!(compatible(":", "#"));
+        assert!(compatible("{CGPoint=dd}", "{?=dd}"));
+        assert!(!compatible("{CGPoint=dd}", "{CGPoint=ddd}"));
+        assert!(!compatible("{CGPoint=dd}", "{CGPoint=df}"));
+        assert!(compatible("{CGPoint}", "{CGPoint=dd}"));
+        assert!(!compatible("{CGPoint}", "{CGSize=dd}"));
+        assert!(!compatible("{?}", "{?=dd}"));
+        assert!(!compatible("{?=dd}", "(?=dd)"));
+        assert!(compatible("[5Q]", "[5q]"));
+        assert!(!compatible("[5Q]", "[4Q]"));
+        assert!(compatible("b3", "b3"));
+        assert!(!compatible("b3", "b4"));
+        assert!(compatible(
+            "{?=Q^@^Q[5Q]}",
+            "{?=\"state\"Q\"itemsPtr\"^@\"mutationsPtr\"^Q\"extra\"[5Q]}"
+        ));
+    }
+}
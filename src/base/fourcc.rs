@@ -0,0 +1,313 @@
+//-------------------------------------------------------------------
+// FourCC
+
+use std::str::FromStr;
+
+/// Four-character code, as used for media types, codec types, audio formats...
+///
+/// Displayed as `'avc1'` when all the characters are printable ASCII, and in hexadecimal otherwise.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[repr(transparent)]
+pub struct FourCC(pub u32);
+
+impl FourCC {
+    pub const fn new(bytes: [u8; 4]) -> Self {
+        Self(u32::from_be_bytes(bytes))
+    }
+
+    /// Code from a `&str` that must be made of exactly 4 ASCII characters.
+    /// Mostly useful in constants, where an invalid code fails the compilation.
+    ///
+    /// # Panics
+    /// Panics if the text is not made of 4 ASCII characters.
+    pub const fn from_text(text: &str) -> Self {
+        let bytes = text.as_bytes();
+
+        if bytes.len() != 4
+            || !bytes[0].is_ascii()
+            || !bytes[1].is_ascii()
+            || !bytes[2].is_ascii()
+            || !bytes[3].is_ascii()
+        {
+            panic!("invalid FOURCC code");
+        }
+
+        Self::new([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    pub const fn to_bytes(self) -> [u8; 4] {
+        self.0.to_be_bytes()
+    }
+
+    /// Whether all characters are printable ASCII (spaces included).
+    pub fn is_printable(self) -> bool {
+        self.to_bytes().iter().all(|b| (b' '..=b'~').contains(b))
+    }
+
+    /// Entries of `KNOWN_FOURCCS` for that code (the same code can be used for different kinds of codes).
+    pub fn known_entries(self) -> impl Iterator<Item = &'static KnownFourCC> {
+        KNOWN_FOURCCS.iter().filter(move |known| known.code == self)
+    }
+
+    /// Human readable name of the code, if it is a well-known code of that kind.
+    pub fn known_name(self, kind: FourCCKind) -> Option<&'static str> {
+        self.known_entries()
+            .find(|known| known.kind == kind)
+            .map(|known| known.name)
+    }
+}
+
+impl From<u32> for FourCC {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<FourCC> for u32 {
+    fn from(code: FourCC) -> Self {
+        code.0
+    }
+}
+
+impl std::fmt::Display for FourCC {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_printable() {
+            let bytes = self.to_bytes();
+            // All ASCII, so also valid UTF-8.
+            let text = std::str::from_utf8(&bytes).map_err(|_| std::fmt::Error)?;
+            write!(f, "'{}'", text)
+        } else {
+            write!(f, "{:#010x}", self.0)
+        }
+    }
+}
+
+impl std::fmt::Debug for FourCC {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ParseFourCCError;
+
+impl std::fmt::Display for ParseFourCCError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("expecting 4 printable ASCII characters (optionally quoted) or a 0x prefixed hexadecimal value")
+    }
+}
+
+impl std::error::Error for ParseFourCCError {}
+
+/// Parses the formats used by `Display`: `avc1`, `'avc1'` or `0x00000020`.
+impl FromStr for FourCC {
+    type Err = ParseFourCCError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            if hex.is_empty() || hex.len() > 8 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(ParseFourCCError);
+            }
+            return u32::from_str_radix(hex, 16)
+                .map(Self)
+                .map_err(|_| ParseFourCCError);
+        }
+        let text = s
+            .strip_prefix('\'')
+            .and_then(|s| s.strip_suffix('\''))
+            .unwrap_or(s);
+        let bytes: [u8; 4] = text.as_bytes().try_into().map_err(|_| ParseFourCCError)?;
+        let code = Self::new(bytes);
+        if !code.is_printable() {
+            return Err(ParseFourCCError);
+        }
+        Ok(code)
+    }
+}
+
+/// What a code in `KNOWN_FOURCCS` is used for.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum FourCCKind {
+    /// `CMMediaType`
+    MediaType,
+    /// `CMVideoCodecType`
+    VideoCodecType,
+    /// `AudioFormatID`
+    AudioFormatID,
+    /// `CMTimeCodeFormatType`
+    TimeCodeFormatType,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct KnownFourCC {
+    pub code: FourCC,
+    pub kind: FourCCKind,
+    pub name: &'static str,
+}
+
+const fn known(kind: FourCCKind, text: &str, name: &'static str) -> KnownFourCC {
+    KnownFourCC {
+        code: FourCC::from_text(text),
+        kind,
+        name,
+    }
+}
+
+/// Well-known codes, with a human readable name.
+pub const KNOWN_FOURCCS: &[KnownFourCC] = {
+    use FourCCKind::*;
+    &[
+        known(MediaType, "vide", "Video"),
+        known(MediaType, "soun", "Audio"),
+        known(MediaType, "muxx", "Muxed"),
+        known(MediaType, "text", "Text"),
+        known(MediaType, "clcp", "Closed caption"),
+        known(MediaType, "sbtl", "Subtitle"),
+        known(MediaType, "tmcd", "Time code"),
+        known(MediaType, "meta", "Metadata"),
+        known(MediaType, "auxv", "Auxiliary picture"),
+        known(VideoCodecType, "2vuy", "Component Y'CbCr 8-bit 4:2:2"),
+        known(VideoCodecType, "avc1", "H.264"),
+        known(VideoCodecType, "hvc1", "HEVC"),
+        known(VideoCodecType, "muha", "HEVC with alpha"),
+        known(VideoCodecType, "apcn", "Apple ProRes 422"),
+        known(VideoCodecType, "apch", "Apple ProRes 422 HQ"),
+        known(VideoCodecType, "apcs", "Apple ProRes 422 LT"),
+        known(VideoCodecType, "apco", "Apple ProRes 422 Proxy"),
+        known(VideoCodecType, "ap4h", "Apple ProRes 4444"),
+        known(VideoCodecType, "ap4x", "Apple ProRes 4444 XQ"),
+        known(VideoCodecType, "aprn", "Apple ProRes RAW"),
+        known(VideoCodecType, "aprh", "Apple ProRes RAW HQ"),
+        known(VideoCodecType, "jpeg", "Photo JPEG"),
+        known(VideoCodecType, "dmb1", "OpenDML JPEG"),
+        known(VideoCodecType, "mp4v", "MPEG-4 Part 2"),
+        known(VideoCodecType, "mp2v", "MPEG-2"),
+        known(VideoCodecType, "mp1v", "MPEG-1"),
+        known(VideoCodecType, "h263", "H.263"),
+        known(VideoCodecType, "cvid", "Cinepak"),
+        known(VideoCodecType, "dvc ", "DV NTSC"),
+        known(VideoCodecType, "dvcp", "DV PAL"),
+        known(VideoCodecType, "vp09", "VP9"),
+        known(VideoCodecType, "av01", "AV1"),
+        known(AudioFormatID, "lpcm", "Linear PCM"),
+        known(AudioFormatID, "aac ", "MPEG-4 AAC"),
+        known(AudioFormatID, "aach", "MPEG-4 HE-AAC"),
+        known(AudioFormatID, "aacp", "MPEG-4 HE-AAC v2"),
+        known(AudioFormatID, "aacl", "MPEG-4 AAC LD"),
+        known(AudioFormatID, "aace", "MPEG-4 AAC ELD"),
+        known(AudioFormatID, "ac-3", "AC-3"),
+        known(AudioFormatID, "ec-3", "Enhanced AC-3"),
+        known(AudioFormatID, "alac", "Apple Lossless"),
+        known(AudioFormatID, "flac", "FLAC"),
+        known(AudioFormatID, "opus", "Opus"),
+        known(AudioFormatID, ".mp1", "MPEG-1 Layer 1"),
+        known(AudioFormatID, ".mp2", "MPEG-1 Layer 2"),
+        known(AudioFormatID, ".mp3", "MPEG-1 Layer 3"),
+        known(AudioFormatID, "ima4", "IMA 4:1 ADPCM"),
+        known(AudioFormatID, "ulaw", "µ-law 2:1"),
+        known(AudioFormatID, "alaw", "A-law 2:1"),
+        known(AudioFormatID, "samr", "AMR Narrow Band"),
+        known(TimeCodeFormatType, "tmcd", "32-bit time code"),
+        known(TimeCodeFormatType, "tc64", "64-bit time code"),
+        known(TimeCodeFormatType, "cn32", "32-bit counter"),
+        known(TimeCodeFormatType, "cn64", "64-bit counter"),
+    ]
+};
+
+#[cfg(test)]
+mod fourcc_tests {
+    use super::*;
+
+    #[test]
+    fn construction() {
+        assert_eq!(FourCC::from_text("soun"), FourCC(0x736F756E));
+        assert_eq!(FourCC::from_text("text"), FourCC(0x74657874));
+        assert_eq!(FourCC::new(*b"avc1"), FourCC::from_text("avc1"));
+        assert_eq!(FourCC::from_text("avc1").to_bytes(), *b"avc1");
+        assert_eq!(u32::from(FourCC::from(32)), 32);
+    }
+
+    #[test]
+    #[should_panic]
+    fn from_text_too_short() {
+        FourCC::from_text("aac");
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(FourCC::from_text("avc1").to_string(), "'avc1'");
+        assert_eq!(FourCC::from_text("aac ").to_string(), "'aac '");
+        assert_eq!(FourCC(32).to_string(), "0x00000020");
+        assert_eq!(FourCC(0xFFFFFFFF).to_string(), "0xffffffff");
+        assert_eq!(FourCC::new(*b"ab\x7Fc").to_string(), "0x61627f63");
+        assert_eq!(format!("{:?}", FourCC::from_text("vide")), "'vide'");
+        assert_eq!(format!("{:?}", Some(FourCC(1))), "Some(0x00000001)");
+    }
+
+    #[test]
+    fn parse() {
+        let avc1 = FourCC::from_text("avc1");
+        assert_eq!("avc1".parse(), Ok(avc1));
+        assert_eq!("'avc1'".parse(), Ok(avc1));
+        assert_eq!("0x61766331".parse(), Ok(avc1));
+        assert_eq!("0X20".parse(), Ok(FourCC(32)));
+        assert_eq!("'aac '".parse(), Ok(FourCC::from_text("aac ")));
+        for invalid in [
+            "",
+            "aac",
+            "avc12",
+            "'avc1",
+            "0x",
+            "0x123456789",
+            "0xgg",
+            "ab\tc",
+            "été",
+        ] {
+            assert_eq!(
+                invalid.parse::<FourCC>(),
+                Err(ParseFourCCError),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for code in [
+            FourCC::from_text("hvc1"),
+            FourCC(0),
+            FourCC(0x7F000000),
+            FourCC(u32::MAX),
+        ] {
+            assert_eq!(code.to_string().parse(), Ok(code));
+        }
+        for known in KNOWN_FOURCCS {
+            assert_eq!(known.code.to_string().parse(), Ok(known.code));
+        }
+    }
+
+    #[test]
+    fn known_codes() {
+        let tmcd = FourCC::from_text("tmcd");
+        assert_eq!(tmcd.known_name(FourCCKind::MediaType), Some("Time code"));
+        assert_eq!(
+            tmcd.known_name(FourCCKind::TimeCodeFormatType),
+            Some("32-bit time code")
+        );
+        assert_eq!(tmcd.known_name(FourCCKind::VideoCodecType), None);
+        assert_eq!(tmcd.known_entries().count(), 2);
+        assert_eq!(
+            FourCC::from_text("avc1").known_name(FourCCKind::VideoCodecType),
+            Some("H.264")
+        );
+        assert_eq!(FourCC(32).known_entries().count(), 0);
+
+        // No duplicate for a given kind.
+        for (i, a) in KNOWN_FOURCCS.iter().enumerate() {
+            for b in &KNOWN_FOURCCS[i + 1..] {
+                assert!(a.code != b.code || a.kind != b.kind, "{:?}", a);
+            }
+        }
+    }
+}
@@ -0,0 +1,455 @@
+//-------------------------------------------------------------------
+// Message sending
+//
+// Calls Objective-C methods directly with `objc_msgSend`, so that bindings can be written without a shim in `choco.mm`:
+//
+// ```ignore
+// let length: NSUInteger = unsafe { msg_send![string, length] };
+// let raw: Option<RawObjPtr> = unsafe { msg_send![array, objectAtIndex: index] };
+// ```
+//
+// In debug builds the types of the arguments and return value are checked against the type encoding
+// the runtime has for the method (when it knows the method).
+
+use super::{Encode, EncodeArguments, Encoding, Ownership, Ptr, RawClassPtr, RawObjPtr, Type};
+use std::ffi::{c_char, CStr};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+#[repr(C)]
+pub struct OpaqueSelector {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct OpaqueMethod {
+    _private: [u8; 0],
+}
+
+/// Selector, the name of a method, registered with the runtime.
+#[derive(Copy, Clone, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct Sel {
+    pub(super) ptr: NonNull<OpaqueSelector>,
+}
+
+/// Implementation of a method, a function taking the receiver and selector as first two parameters.
+pub type Imp = unsafe extern "C-unwind" fn();
+
+#[cfg_attr(not(feature = "mock-runtime"), link(name = "objc", kind = "dylib"))]
+extern "C" {
+    fn sel_registerName(name: *const c_char) -> Option<Sel>;
+    fn sel_getName(sel: Sel) -> *const c_char;
+    fn object_getClass(obj: Option<RawObjPtr>) -> Option<RawClassPtr>;
+    fn class_getInstanceMethod(
+        class: Option<RawClassPtr>,
+        sel: Sel,
+    ) -> Option<NonNull<OpaqueMethod>>;
+    fn method_getTypeEncoding(method: NonNull<OpaqueMethod>) -> *const c_char;
+}
+
+// The functions sending messages are declared with `()` as parameters, and transmuted to the right type before calling.
+#[cfg(not(feature = "mock-runtime"))]
+#[link(name = "objc", kind = "dylib")]
+extern "C-unwind" {
+    fn objc_msgSend();
+    // arm64 does not have a `_stret` variant, the hidden pointer having its own register.
+    #[cfg(any(target_arch = "x86_64", target_arch = "x86", target_arch = "arm"))]
+    fn objc_msgSend_stret();
+    // Only needed for floating point values returned on the x87 stack.
+    #[cfg(target_arch = "x86")]
+    fn objc_msgSend_fpret();
+}
+
+// Also used with the mock runtime instead of `objc_msgSend`, that it can't provide.
+#[cfg_attr(not(feature = "mock-runtime"), link(name = "objc", kind = "dylib"))]
+extern "C" {
+    fn class_getMethodImplementation(class: Option<RawClassPtr>, sel: Sel) -> Option<Imp>;
+}
+
+impl Sel {
+    /// Registers the selector if needed, `name` being for example `c"objectAtIndex:"`.
+    pub fn register(name: &CStr) -> Self {
+        unsafe { sel_registerName(name.as_ptr()) }
+            .expect("expecting sel_registerName() to return a non-null value")
+    }
+
+    pub fn name(&self) -> &'static CStr {
+        unsafe { CStr::from_ptr(sel_getName(*self)) }
+    }
+}
+
+impl std::fmt::Debug for Sel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name().to_string_lossy())
+    }
+}
+
+unsafe impl Encode for Sel {
+    fn encoding() -> Encoding {
+        Encoding::Sel
+    }
+}
+
+/// Selector registered the first time it is needed, used by `sel!` and `msg_send!`.
+pub struct CachedSel {
+    /// Name with a terminating NUL.
+    name: &'static str,
+    sel: AtomicPtr<OpaqueSelector>,
+}
+
+impl CachedSel {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            sel: AtomicPtr::new(std::ptr::null_mut()),
+        }
+    }
+
+    pub fn get(&self) -> Sel {
+        // Acquire/Release, so that the runtime's writes registering the selector
+        // are visible to threads using it, for example with `sel_getName`.
+        if let Some(ptr) = NonNull::new(self.sel.load(Ordering::Acquire)) {
+            return Sel { ptr };
+        }
+        // Registering is idempotent, so it does not matter if two threads do it at the same time.
+        let name = CStr::from_bytes_with_nul(self.name.as_bytes())
+            .expect("expecting the selector name to have a single NUL, at the end");
+        let sel = Sel::register(name);
+        self.sel.store(sel.ptr.as_ptr(), Ordering::Release);
+        sel
+    }
+}
+
+/// Selector of the given method name, registered only once:
+///
+/// ```ignore
+/// let sel = sel!(objectAtIndex:);
+/// ```
+#[macro_export]
+macro_rules! sel {
+    ($name:ident) => {{
+        static SEL: $crate::base::CachedSel =
+            $crate::base::CachedSel::new(concat!(stringify!($name), "\0"));
+        SEL.get()
+    }};
+    ($($part:ident :)+) => {{
+        static SEL: $crate::base::CachedSel =
+            $crate::base::CachedSel::new(concat!($(stringify!($part), ":",)+ "\0"));
+        SEL.get()
+    }};
+}
+
+/// Sends a message, with the same syntax as Objective-C, the return type being inferred:
+///
+/// ```ignore
+/// let count: NSUInteger = unsafe { msg_send![array, count] };
+/// let _: () = unsafe { msg_send![dictionary, setObject: object forKey: key] };
+/// ```
+///
+/// The receiver is anything implementing `MessageReceiver`, the arguments and return value must implement `Encode`.
+/// It has to be used in an `unsafe` block, as the types can only be checked at runtime, in debug builds.
+#[macro_export]
+macro_rules! msg_send {
+    [$receiver:expr, $name:ident $(,)?] => {
+        $crate::base::send_message($receiver, $crate::sel!($name), ())
+    };
+    [$receiver:expr, $($part:ident : $arg:expr)+] => {
+        $crate::base::send_message($receiver, $crate::sel!($($part:)+), ($($arg,)+))
+    };
+}
+
+/// What messages can be sent to: objects and classes.
+pub trait MessageReceiver {
+    fn as_receiver(&self) -> RawObjPtr;
+}
+
+impl MessageReceiver for RawObjPtr {
+    fn as_receiver(&self) -> RawObjPtr {
+        *self
+    }
+}
+
+// Classes are objects too.
+impl MessageReceiver for RawClassPtr {
+    fn as_receiver(&self) -> RawObjPtr {
+        RawObjPtr {
+            ptr: self.ptr.cast(),
+        }
+    }
+}
+
+impl<T: Type, O: Ownership> MessageReceiver for &Ptr<T, O> {
+    fn as_receiver(&self) -> RawObjPtr {
+        self.as_raw()
+    }
+}
+
+/// Arguments of a message, as a tuple.
+///
+/// # Safety
+/// `call_imp` must call `imp` with the receiver, the selector, then all the arguments in order.
+pub unsafe trait MessageArguments: EncodeArguments {
+    /// # Safety
+    /// `imp` must take the receiver, the selector, then parameters of the types of the arguments, and return `Ret`.
+    unsafe fn call_imp<Ret>(imp: Imp, receiver: RawObjPtr, sel: Sel, args: Self) -> Ret;
+}
+
+macro_rules! impl_message_arguments {
+    ($($arg:ident $name:ident),*) => {
+        unsafe impl<$($arg: Encode),*> MessageArguments for ($($arg,)*) {
+            unsafe fn call_imp<Ret>(imp: Imp, receiver: RawObjPtr, sel: Sel, args: Self) -> Ret {
+                let ($($name,)*) = args;
+                let imp: unsafe extern "C-unwind" fn(RawObjPtr, Sel $(, $arg)*) -> Ret =
+                    std::mem::transmute(imp);
+                imp(receiver, sel $(, $name)*)
+            }
+        }
+    };
+}
+
+impl_message_arguments!();
+impl_message_arguments!(A1 a1);
+impl_message_arguments!(A1 a1, A2 a2);
+impl_message_arguments!(A1 a1, A2 a2, A3 a3);
+impl_message_arguments!(A1 a1, A2 a2, A3 a3, A4 a4);
+impl_message_arguments!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5);
+impl_message_arguments!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6);
+impl_message_arguments!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7);
+impl_message_arguments!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8);
+impl_message_arguments!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8, A9 a9);
+impl_message_arguments!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8, A9 a9, A10 a10);
+impl_message_arguments!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8, A9 a9, A10 a10, A11 a11);
+impl_message_arguments!(A1 a1, A2 a2, A3 a3, A4 a4, A5 a5, A6 a6, A7 a7, A8 a8, A9 a9, A10 a10, A11 a11, A12 a12);
+
+/// Sends the message `sel` to `receiver`, what `msg_send!` expands to.
+///
+/// # Safety
+/// The method must take arguments of the types of `args`, and return a `Ret`.
+pub unsafe fn send_message<R, Args, Ret>(receiver: R, sel: Sel, args: Args) -> Ret
+where
+    R: MessageReceiver,
+    Args: MessageArguments,
+    Ret: Encode,
+{
+    let receiver = receiver.as_receiver();
+    if cfg!(debug_assertions) {
+        check_method_types::<Args, Ret>(object_getClass(Some(receiver)), sel, "send");
+    }
+    Args::call_imp(msg_send_imp::<Ret>(receiver, sel), receiver, sel, args)
+}
+
+/// Sends the message `sel` to `receiver` using the implementation of `superclass`,
+/// what `[super ...]` does in Objective-C, for example to call the overridden method in a declared class.
+///
+/// # Safety
+/// Same as `send_message`, `superclass` also having to be a superclass of the class of `receiver`.
+pub unsafe fn send_super_message<R, Args, Ret>(
+    receiver: R,
+    superclass: RawClassPtr,
+    sel: Sel,
+    args: Args,
+) -> Ret
+where
+    R: MessageReceiver,
+    Args: MessageArguments,
+    Ret: Encode,
+{
+    if cfg!(debug_assertions) {
+        check_method_types::<Args, Ret>(Some(superclass), sel, "send");
+    }
+    // The real runtime returns its forwarding implementation for unknown methods.
+    let imp = class_getMethodImplementation(Some(superclass), sel)
+        .unwrap_or_else(|| panic!("unrecognized selector {:?}", sel));
+    Args::call_imp(imp, receiver.as_receiver(), sel, args)
+}
+
+#[cfg(not(feature = "mock-runtime"))]
+fn msg_send_imp<Ret: Encode>(_receiver: RawObjPtr, _sel: Sel) -> Imp {
+    #[cfg(any(target_arch = "x86_64", target_arch = "x86", target_arch = "arm"))]
+    if super::block::use_stret::<Ret>() {
+        return objc_msgSend_stret;
+    }
+    #[cfg(target_arch = "x86")]
+    if matches!(Ret::encoding(), Encoding::Float | Encoding::Double) {
+        return objc_msgSend_fpret;
+    }
+    objc_msgSend
+}
+
+// Calling the implementation directly, Rust takes care of structures returned in memory.
+#[cfg(feature = "mock-runtime")]
+#[allow(clippy::extra_unused_type_parameters)]
+fn msg_send_imp<Ret: Encode>(receiver: RawObjPtr, sel: Sel) -> Imp {
+    let class = unsafe { object_getClass(Some(receiver)) };
+    unsafe { class_getMethodImplementation(class, sel) }
+        .unwrap_or_else(|| panic!("unrecognized selector {:?}", sel))
+}
+
+/// Panics if the types do not match the encoding of the method of `class`, if the runtime knows it,
+/// `action` saying what the types are used for in the message.
+pub(super) fn check_method_types<Args, Ret>(class: Option<RawClassPtr>, sel: Sel, action: &str)
+where
+    Args: EncodeArguments,
+    Ret: Encode,
+{
+    let method = match unsafe { class_getInstanceMethod(class, sel) } {
+        Some(method) => method,
+        // Might be handled by forwarding.
+        None => return,
+    };
+    let types = unsafe { CStr::from_ptr(method_getTypeEncoding(method)) };
+    let types = match types.to_str() {
+        Ok(types) => types,
+        Err(_) => return,
+    };
+    let expected = match parse_method_types(types) {
+        Some(expected) => expected,
+        None => return,
+    };
+
+    let mut actual = vec![Ret::encoding(), Encoding::Object, Encoding::Sel];
+    actual.extend(Args::encodings().into_iter().map(|(encoding, _)| encoding));
+    let compatible = expected.len() == actual.len()
+        && expected
+            .iter()
+            .zip(&actual)
+            .all(|(expected, actual)| expected.is_layout_compatible(actual));
+    if !compatible {
+        let actual: String = actual.iter().map(|encoding| encoding.to_string()).collect();
+        panic!(
+            "wrong types used to {} {:?}: the method has the type encoding {}, but got {}",
+            action, sel, types, actual
+        );
+    }
+}
+
+/// Parses a method type encoding, the return type then the parameters, each followed by its offset.
+fn parse_method_types(mut types: &str) -> Option<Vec<Encoding>> {
+    let mut encodings = Vec::new();
+    while !types.is_empty() {
+        let (encoding, rest) = Encoding::parse_prefix(types).ok()?;
+        encodings.push(encoding);
+        types = rest.trim_start_matches(|c: char| c.is_ascii_digit() || c == '-' || c == '+');
+    }
+    Some(encodings)
+}
+
+#[cfg(test)]
+mod message_tests {
+    use super::*;
+
+    #[test]
+    fn method_types() {
+        assert_eq!(
+            parse_method_types("@24@0:8Q16"),
+            Some(vec![
+                Encoding::Object,
+                Encoding::Object,
+                Encoding::Sel,
+                Encoding::ULongLong
+            ])
+        );
+        assert_eq!(
+            parse_method_types("v32@0:8@16@24"),
+            Some(vec![
+                Encoding::Void,
+                Encoding::Object,
+                Encoding::Sel,
+                Encoding::Object,
+                Encoding::Object
+            ])
+        );
+        // Without offsets, as found in protocol descriptions.
+        assert_eq!(
+            parse_method_types("c@:"),
+            Some(vec![Encoding::Char, Encoding::Object, Encoding::Sel])
+        );
+        assert_eq!(parse_method_types("v@:!"), None);
+    }
+
+    #[cfg(feature = "mock-runtime")]
+    mod mock {
+        use super::*;
+        use crate::base::mock_runtime::{
+            class_addMethod, create_object, objc_release, object_getClass, register_class,
+        };
+        use crate::core_graphics::CGRect;
+
+        unsafe extern "C-unwind" fn value(_this: RawObjPtr, _sel: Sel) -> i64 {
+            42
+        }
+
+        unsafe extern "C-unwind" fn add_to(_this: RawObjPtr, _sel: Sel, a: i32, b: i32) -> i32 {
+            a + b
+        }
+
+        unsafe extern "C-unwind" fn frame(_this: RawObjPtr, _sel: Sel) -> CGRect {
+            CGRect::new(1.0, 2.0, 3.0, 4.0)
+        }
+
+        unsafe extern "C-unwind" fn class_value(_this: RawObjPtr, _sel: Sel) -> i64 {
+            12
+        }
+
+        type Getter<Ret> = unsafe extern "C-unwind" fn(RawObjPtr, Sel) -> Ret;
+        type AddTo = unsafe extern "C-unwind" fn(RawObjPtr, Sel, i32, i32) -> i32;
+
+        fn mock_class() -> RawClassPtr {
+            let class = register_class("MockMessages");
+            let metaclass = unsafe { object_getClass(Some(class.as_receiver())) }.unwrap();
+            unsafe {
+                let value = std::mem::transmute::<Getter<i64>, Imp>(value);
+                class_addMethod(class, sel!(value), value, c"q16@0:8".as_ptr());
+                let add_to = std::mem::transmute::<AddTo, Imp>(add_to);
+                class_addMethod(class, sel!(add:to:), add_to, c"i24@0:8i16i20".as_ptr());
+                let frame = std::mem::transmute::<Getter<CGRect>, Imp>(frame);
+                let types = c"{CGRect={CGPoint=dd}{CGSize=dd}}16@0:8";
+                class_addMethod(class, sel!(frame), frame, types.as_ptr());
+                let class_value = std::mem::transmute::<Getter<i64>, Imp>(class_value);
+                class_addMethod(metaclass, sel!(value), class_value, c"q16@0:8".as_ptr());
+            }
+            class
+        }
+
+        #[test]
+        fn selectors() {
+            assert_eq!(sel!(add:to:), Sel::register(c"add:to:"));
+            assert_ne!(sel!(add:to:), sel!(value));
+            assert_eq!(sel!(objectAtIndex:).name(), c"objectAtIndex:");
+        }
+
+        #[test]
+        fn sending() {
+            let class = mock_class();
+            let obj = create_object(class);
+            unsafe {
+                let value: i64 = msg_send![obj, value];
+                assert_eq!(value, 42);
+                let sum: i32 = msg_send![obj, add: 1i32 to: 2i32];
+                assert_eq!(sum, 3);
+                let frame: CGRect = msg_send![obj, frame];
+                assert_eq!(frame, CGRect::new(1.0, 2.0, 3.0, 4.0));
+                // Class methods.
+                let value: i64 = msg_send![class, value];
+                assert_eq!(value, 12);
+                objc_release(obj);
+            }
+        }
+
+        #[cfg(debug_assertions)]
+        #[test]
+        #[should_panic(expected = "wrong types used to send add:to:")]
+        fn wrong_types() {
+            let obj = create_object(mock_class());
+            let _: i32 = unsafe { msg_send![obj, add: 1.0f64 to: 2i32] };
+        }
+
+        #[test]
+        #[should_panic(expected = "unrecognized selector unknownMethod")]
+        fn unknown_method() {
+            let obj = create_object(mock_class());
+            let _: () = unsafe { msg_send![obj, unknownMethod] };
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//-------------------------------------------------------------------
+// Associated objects
+
+use super::{objc_autorelease, objc_release, objc_retain, runtime};
+use crate::base::{AssociationPolicy, RawObjPtr};
+use std::ffi::c_void;
+use std::ptr::NonNull;
+
+const OBJC_ASSOCIATION_ASSIGN: usize = 0;
+const OBJC_ASSOCIATION_ATOMIC: usize = 0o1400;
+
+unsafe fn release_associated(value: usize, policy: usize) {
+    if policy != OBJC_ASSOCIATION_ASSIGN {
+        objc_release(RawObjPtr {
+            ptr: NonNull::new(value as *mut _).unwrap(),
+        });
+    }
+}
+
+/// Removes the associations of an object being deallocated, releasing the objects associated with it.
+pub(super) unsafe fn remove_associated_objects(object: RawObjPtr) {
+    let addr = object.ptr.as_ptr() as usize;
+    let associated: Vec<_> = {
+        let mut runtime = runtime();
+        let keys: Vec<_> = runtime
+            .associations
+            .keys()
+            .filter(|(object, _)| *object == addr)
+            .copied()
+            .collect();
+        keys.into_iter()
+            .filter_map(|key| runtime.associations.remove(&key))
+            .collect()
+    };
+    for (value, policy) in associated {
+        release_associated(value, policy);
+    }
+}
+
+/// Objects associated with a copy policy are retained instead, as the mock runtime does not know how to copy them.
+#[no_mangle]
+pub unsafe extern "C" fn objc_setAssociatedObject(
+    object: RawObjPtr,
+    key: *const c_void,
+    value: Option<RawObjPtr>,
+    policy: AssociationPolicy,
+) {
+    let policy = policy as usize;
+    let key = (object.ptr.as_ptr() as usize, key as usize);
+    if let Some(value) = value {
+        if policy != OBJC_ASSOCIATION_ASSIGN {
+            objc_retain(value);
+        }
+    }
+    let previous = {
+        let mut runtime = runtime();
+        match value {
+            Some(value) => {
+                let value = value.ptr.as_ptr() as usize;
+                runtime.associations.insert(key, (value, policy))
+            }
+            None => runtime.associations.remove(&key),
+        }
+    };
+    // Released with the runtime unlocked, as it can deallocate the object.
+    if let Some((previous, policy)) = previous {
+        release_associated(previous, policy);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn objc_getAssociatedObject(
+    object: RawObjPtr,
+    key: *const c_void,
+) -> Option<RawObjPtr> {
+    let key = (object.ptr.as_ptr() as usize, key as usize);
+    let (value, policy) = *runtime().associations.get(&key)?;
+    let value = RawObjPtr {
+        ptr: NonNull::new(value as *mut _).unwrap(),
+    };
+    // Like the real runtime, so that the object stays alive if another thread replaces it.
+    if policy & OBJC_ASSOCIATION_ATOMIC != 0 {
+        objc_retain(value);
+        objc_autorelease(value);
+    }
+    Some(value)
+}
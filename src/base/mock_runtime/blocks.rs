@@ -0,0 +1,54 @@
+//-------------------------------------------------------------------
+// Blocks runtime
+//
+// The blocks runtime itself is `base::blocks_runtime`, which reports its heap allocations here
+// so that heap blocks and `__block` variables are checked the same way objects are.
+
+use super::{runtime, EntryKind, Issue};
+
+/// A heap block or `__block` variable was allocated with the given reference count.
+pub(crate) fn heap_allocated(addr: usize, class_name: &'static str, retain_count: usize) {
+    // Contrary to objects, blocks are really freed, but stay in the table so that an over-release can be detected.
+    // The table entry keeps a retain count of 0 so a new block at the same address will just replace it.
+    let mut runtime = runtime();
+    runtime.insert(addr, EntryKind::Block, class_name);
+    if let Some(entry) = runtime.entries.get_mut(&addr) {
+        entry.retain_count = retain_count;
+    }
+}
+
+pub(crate) fn heap_retained(addr: usize) {
+    let mut runtime = runtime();
+    let issue = match runtime.entries.get_mut(&addr) {
+        None => Some(Issue::UnknownPointer),
+        Some(entry) if entry.retain_count == 0 => Some(Issue::RetainAfterDealloc {
+            class_name: entry.class_name,
+        }),
+        Some(entry) => {
+            entry.retain_count += 1;
+            None
+        }
+    };
+    if let Some(issue) = issue {
+        runtime.record_issue(issue);
+    }
+}
+
+/// Returns false if the release must not go further because it is an over-release.
+///
+/// Unknown pointers are let through as they can be stack or global blocks.
+pub(crate) fn heap_released(addr: usize) -> bool {
+    let mut runtime = runtime();
+    match runtime.entries.get_mut(&addr) {
+        None => true,
+        Some(entry) if entry.retain_count == 0 => {
+            let class_name = entry.class_name;
+            runtime.record_issue(Issue::OverRelease { class_name });
+            false
+        }
+        Some(entry) => {
+            entry.retain_count -= 1;
+            true
+        }
+    }
+}
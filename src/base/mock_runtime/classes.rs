@@ -0,0 +1,577 @@
+//-------------------------------------------------------------------
+// Classes: class table, selectors, methods, instance variables, properties, protocols and reflection
+
+use super::{create_object, runtime, MockObject, Runtime};
+use crate::base::{
+    function_signature, Encoding, Imp, OpaqueClass, OpaqueIvar, OpaqueMethod, OpaqueProperty,
+    OpaqueProtocol, OpaqueSelector, Protocol, RawClassPtr, RawObjPtr, Sel, BOOL,
+};
+use std::ffi::{c_char, c_uint, c_void, CStr, CString};
+use std::ptr::NonNull;
+
+/// Classes are objects too, so like objects the first field is the class, the metaclass here.
+/// The `isa` of metaclasses points to themselves.
+#[repr(C)]
+pub(super) struct MockClass {
+    isa: *const MockClass,
+    name: &'static str,
+    c_name: CString,
+    superclass: Option<RawClassPtr>,
+}
+
+/// Implementation of a method added with `class_addMethod`.
+pub(super) struct MockMethod {
+    sel: Sel,
+    pub(super) imp: Imp,
+    types: CString,
+}
+
+struct MockIvar {
+    name: CString,
+    types: CString,
+    offset: usize,
+    size: usize,
+}
+
+struct MockProperty {
+    name: CString,
+    attributes: CString,
+}
+
+struct MockProtocol {
+    name: CString,
+}
+
+/// Registers a class in the class table, or returns the existing class with that name.
+pub fn register_class(name: &'static str) -> RawClassPtr {
+    register(name, None)
+}
+
+/// Same as `register_class`, for a subclass of `superclass`.
+pub fn register_subclass(name: &'static str, superclass: RawClassPtr) -> RawClassPtr {
+    register(name, Some(superclass))
+}
+
+fn register(name: &'static str, superclass: Option<RawClassPtr>) -> RawClassPtr {
+    // Registered before locking the runtime, as registering a selector locks it.
+    let root_methods = root_methods();
+    let mut runtime = runtime();
+    match runtime.classes.get(name) {
+        Some(addr) => RawClassPtr {
+            ptr: NonNull::new(*addr as *mut OpaqueClass).unwrap(),
+        },
+        None => runtime.allocate_class(name, superclass, &root_methods),
+    }
+}
+
+/// Methods root classes have, like `NSObject`: `+alloc`, `-init` and `-dealloc`,
+/// with for each if it is a class method.
+fn root_methods() -> [(bool, Sel, Imp, &'static CStr); 3] {
+    unsafe extern "C-unwind" fn alloc(class: RawObjPtr, _sel: Sel) -> RawObjPtr {
+        create_object(RawClassPtr {
+            ptr: class.ptr.cast(),
+        })
+    }
+
+    unsafe extern "C-unwind" fn init(this: RawObjPtr, _sel: Sel) -> RawObjPtr {
+        this
+    }
+
+    // Objects are never freed, they stay as zombies.
+    unsafe extern "C-unwind" fn dealloc(_this: RawObjPtr, _sel: Sel) {}
+
+    type Getter = unsafe extern "C-unwind" fn(RawObjPtr, Sel) -> RawObjPtr;
+    type Action = unsafe extern "C-unwind" fn(RawObjPtr, Sel);
+    let method = &[Encoding::Object, Encoding::Sel];
+    let register = |name: &CStr| unsafe { sel_registerName(name.as_ptr()) }.unwrap();
+    unsafe {
+        [
+            (
+                true,
+                register(c"alloc"),
+                std::mem::transmute::<Getter, Imp>(alloc),
+                function_signature::<(), RawObjPtr>(method),
+            ),
+            (
+                false,
+                register(c"init"),
+                std::mem::transmute::<Getter, Imp>(init),
+                function_signature::<(), RawObjPtr>(method),
+            ),
+            (
+                false,
+                register(c"dealloc"),
+                std::mem::transmute::<Action, Imp>(dealloc),
+                function_signature::<(), ()>(method),
+            ),
+        ]
+    }
+}
+
+impl Runtime {
+    /// Creates a new class, giving it `root_methods` if it does not have a superclass.
+    fn allocate_class(
+        &mut self,
+        name: &'static str,
+        superclass: Option<RawClassPtr>,
+        root_methods: &[(bool, Sel, Imp, &'static CStr)],
+    ) -> RawClassPtr {
+        // Never freed, like real classes.
+        let c_name = CString::new(name).expect("expecting the class name to not contain NUL");
+        let meta = Box::into_raw(Box::new(MockClass {
+            isa: std::ptr::null(),
+            name,
+            c_name: c_name.clone(),
+            superclass: superclass.map(metaclass),
+        }));
+        unsafe { (*meta).isa = meta };
+        let class = Box::new(MockClass {
+            isa: meta,
+            name,
+            c_name,
+            superclass,
+        });
+        let addr = Box::into_raw(class) as usize;
+        self.classes.insert(name, addr);
+        let class = RawClassPtr {
+            ptr: NonNull::new(addr as *mut OpaqueClass).unwrap(),
+        };
+        if superclass.is_none() {
+            for (class_method, sel, imp, types) in root_methods {
+                let target = if *class_method {
+                    metaclass(class)
+                } else {
+                    class
+                };
+                self.add_method(target, *sel, *imp, types);
+            }
+        }
+        class
+    }
+}
+
+fn metaclass(class: RawClassPtr) -> RawClassPtr {
+    let class = class.ptr.as_ptr() as *const MockClass;
+    RawClassPtr {
+        ptr: NonNull::new(unsafe { (*class).isa } as *mut OpaqueClass).unwrap(),
+    }
+}
+
+/// Name of a class created by `register_class`.
+pub fn class_name(class: RawClassPtr) -> &'static str {
+    let class = class.ptr.as_ptr() as *const MockClass;
+    unsafe { (*class).name }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn class_getSuperclass(class: Option<RawClassPtr>) -> Option<RawClassPtr> {
+    let class = class?.ptr.as_ptr() as *const MockClass;
+    (*class).superclass
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sel_registerName(name: *const c_char) -> Option<Sel> {
+    let name = CStr::from_ptr(name);
+    let mut runtime = runtime();
+    let addr = match runtime.selectors.get(name) {
+        Some(addr) => *addr,
+        None => {
+            // Never freed, like real selectors.
+            let leaked: &'static CStr = Box::leak(Box::from(name));
+            let addr = leaked.as_ptr() as usize;
+            runtime.selectors.insert(name.to_owned(), addr);
+            addr
+        }
+    };
+    Some(Sel {
+        ptr: NonNull::new(addr as *mut OpaqueSelector).unwrap(),
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sel_getName(sel: Sel) -> *const c_char {
+    sel.ptr.as_ptr() as *const c_char
+}
+
+/// Adds a method to a class, returning `NO` if the class already has one for that selector.
+#[no_mangle]
+pub unsafe extern "C" fn class_addMethod(
+    class: RawClassPtr,
+    sel: Sel,
+    imp: Imp,
+    types: *const c_char,
+) -> BOOL {
+    runtime()
+        .add_method(class, sel, imp, CStr::from_ptr(types))
+        .into()
+}
+
+impl Runtime {
+    fn add_method(&mut self, class: RawClassPtr, sel: Sel, imp: Imp, types: &CStr) -> bool {
+        if self.own_method(class, sel).is_some() {
+            return false;
+        }
+        let method = Box::new(MockMethod {
+            sel,
+            imp,
+            types: types.to_owned(),
+        });
+        // Never freed, methods can't be removed.
+        let method = Box::into_raw(method) as usize;
+        let class = class.ptr.as_ptr() as usize;
+        self.methods.entry(class).or_default().push(method);
+        true
+    }
+
+    /// Method for `sel` added to the class itself.
+    fn own_method(&self, class: RawClassPtr, sel: Sel) -> Option<&'static MockMethod> {
+        let methods = self.methods.get(&(class.ptr.as_ptr() as usize))?;
+        methods
+            .iter()
+            .map(|method| unsafe { &*(*method as *const MockMethod) })
+            .find(|method| method.sel == sel)
+    }
+}
+
+/// Method for `sel` of the class or its superclasses.
+pub(super) fn find_method(class: Option<RawClassPtr>, sel: Sel) -> Option<&'static MockMethod> {
+    let mut class = class;
+    while let Some(current) = class {
+        if let Some(method) = runtime().own_method(current, sel) {
+            return Some(method);
+        }
+        class = unsafe { class_getSuperclass(Some(current)) };
+    }
+    None
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn class_getInstanceMethod(
+    class: Option<RawClassPtr>,
+    sel: Sel,
+) -> Option<NonNull<OpaqueMethod>> {
+    find_method(class, sel).map(|method| NonNull::from(method).cast())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn method_getTypeEncoding(method: NonNull<OpaqueMethod>) -> *const c_char {
+    method.cast::<MockMethod>().as_ref().types.as_ptr()
+}
+
+/// Contrary to the real one, returns null instead of the forwarding implementation for unknown methods.
+#[no_mangle]
+pub unsafe extern "C" fn class_getMethodImplementation(
+    class: Option<RawClassPtr>,
+    sel: Sel,
+) -> Option<Imp> {
+    find_method(class, sel).map(|method| method.imp)
+}
+
+/// Registers a protocol, or returns the existing protocol with that name.
+pub fn register_protocol(name: &CStr) -> Protocol {
+    let mut runtime = runtime();
+    let addr = *runtime.protocols.entry(name.to_owned()).or_insert_with(|| {
+        let protocol = Box::new(MockProtocol {
+            name: name.to_owned(),
+        });
+        // Never freed, like real protocols.
+        Box::into_raw(protocol) as usize
+    });
+    Protocol {
+        ptr: NonNull::new(addr as *mut OpaqueProtocol).unwrap(),
+    }
+}
+
+/// Adds a property with the given attribute string to a class.
+///
+/// The real runtime has `class_addProperty`, taking the attributes one by one.
+pub fn add_property(class: RawClassPtr, name: &CStr, attributes: &CStr) {
+    let property = Box::new(MockProperty {
+        name: name.to_owned(),
+        attributes: attributes.to_owned(),
+    });
+    let class = class.ptr.as_ptr() as usize;
+    // Never freed, properties can't be removed.
+    let property = Box::into_raw(property) as usize;
+    runtime()
+        .properties
+        .entry(class)
+        .or_default()
+        .push(property);
+}
+
+/// Copies `items` into a list allocated with `malloc`, as returned by the `class_copy...List` functions.
+unsafe fn copy_list<T: Copy>(items: &[T], count: *mut c_uint) -> *mut T {
+    extern "C" {
+        fn malloc(size: usize) -> *mut c_void;
+    }
+
+    if !count.is_null() {
+        *count = items.len() as c_uint;
+    }
+    if items.is_empty() {
+        return std::ptr::null_mut();
+    }
+    let list = malloc(std::mem::size_of_val(items)) as *mut T;
+    std::ptr::copy_nonoverlapping(items.as_ptr(), list, items.len());
+    list
+}
+
+impl Runtime {
+    /// Size of the instances of a class, where the next instance variable would go.
+    pub(super) fn instance_size(&self, class: RawClassPtr) -> usize {
+        let ivars = self.ivars.get(&(class.ptr.as_ptr() as usize));
+        match ivars.and_then(|ivars| ivars.last()) {
+            Some(ivar) => {
+                let ivar = unsafe { &*(*ivar as *const MockIvar) };
+                ivar.offset + ivar.size
+            }
+            None => match unsafe { class_getSuperclass(Some(class)) } {
+                Some(superclass) => self.instance_size(superclass),
+                None => std::mem::size_of::<MockObject>(),
+            },
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn objc_getClass(name: *const c_char) -> Option<RawClassPtr> {
+    let name = CStr::from_ptr(name).to_str().ok()?;
+    let addr = *runtime().classes.get(name)?;
+    Some(RawClassPtr {
+        ptr: NonNull::new(addr as *mut OpaqueClass).unwrap(),
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn class_getName(class: RawClassPtr) -> *const c_char {
+    let class = class.ptr.as_ptr() as *const MockClass;
+    (*class).c_name.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn class_respondsToSelector(class: RawClassPtr, sel: Sel) -> BOOL {
+    find_method(Some(class), sel).is_some().into()
+}
+
+/// Adds an instance variable to a class, returning `NO` if it already has one with that name.
+///
+/// Contrary to the real runtime, works even once instances have been created,
+/// as they do not have any storage for instance variables.
+#[no_mangle]
+pub unsafe extern "C" fn class_addIvar(
+    class: RawClassPtr,
+    name: *const c_char,
+    size: usize,
+    alignment: u8,
+    types: *const c_char,
+) -> BOOL {
+    let name = CStr::from_ptr(name);
+    let mut runtime = runtime();
+    let addr = class.ptr.as_ptr() as usize;
+    let ivars = runtime
+        .ivars
+        .get(&addr)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    if ivars
+        .iter()
+        .any(|ivar| (*(*ivar as *const MockIvar)).name.as_c_str() == name)
+    {
+        return false.into();
+    }
+    let offset = runtime
+        .instance_size(class)
+        .next_multiple_of(1 << alignment);
+    let ivar = Box::new(MockIvar {
+        name: name.to_owned(),
+        types: CStr::from_ptr(types).to_owned(),
+        offset,
+        size,
+    });
+    // Never freed, instance variables can't be removed.
+    let ivar = Box::into_raw(ivar) as usize;
+    runtime.ivars.entry(addr).or_default().push(ivar);
+    true.into()
+}
+
+/// Instance variable of the class or its superclasses.
+#[no_mangle]
+pub unsafe extern "C" fn class_getInstanceVariable(
+    class: Option<RawClassPtr>,
+    name: *const c_char,
+) -> Option<NonNull<OpaqueIvar>> {
+    let name = CStr::from_ptr(name);
+    let mut class = class;
+    while let Some(current) = class {
+        let ivars = runtime()
+            .ivars
+            .get(&(current.ptr.as_ptr() as usize))
+            .cloned();
+        for ivar in ivars.unwrap_or_default() {
+            if (*(ivar as *const MockIvar)).name.as_c_str() == name {
+                return NonNull::new(ivar as *mut OpaqueIvar);
+            }
+        }
+        class = class_getSuperclass(Some(current));
+    }
+    None
+}
+
+/// Creates a class, returning nil if there is already one with that name.
+#[no_mangle]
+pub unsafe extern "C" fn objc_allocateClassPair(
+    superclass: Option<RawClassPtr>,
+    name: *const c_char,
+    _extra_bytes: usize,
+) -> Option<RawClassPtr> {
+    let name = CStr::from_ptr(name).to_str().ok()?;
+    let root_methods = root_methods();
+    let mut runtime = runtime();
+    if runtime.classes.contains_key(name) {
+        return None;
+    }
+    // Never freed, like the class.
+    let name = Box::leak(name.to_owned().into_boxed_str());
+    Some(runtime.allocate_class(name, superclass, &root_methods))
+}
+
+/// Nothing to do, classes being usable as soon as they are created.
+#[no_mangle]
+pub unsafe extern "C" fn objc_registerClassPair(_class: RawClassPtr) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn class_addProtocol(class: RawClassPtr, protocol: Protocol) -> BOOL {
+    let mut runtime = runtime();
+    let adopted = runtime
+        .adopted_protocols
+        .entry(class.ptr.as_ptr() as usize)
+        .or_default();
+    let protocol = protocol.ptr.as_ptr() as usize;
+    if adopted.contains(&protocol) {
+        return false.into();
+    }
+    adopted.push(protocol);
+    true.into()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn class_conformsToProtocol(class: RawClassPtr, protocol: Protocol) -> BOOL {
+    let protocol = protocol.ptr.as_ptr() as usize;
+    let mut class = Some(class);
+    while let Some(current) = class {
+        let addr = current.ptr.as_ptr() as usize;
+        if let Some(adopted) = runtime().adopted_protocols.get(&addr) {
+            if adopted.contains(&protocol) {
+                return true.into();
+            }
+        }
+        class = class_getSuperclass(Some(current));
+    }
+    false.into()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn class_copyMethodList(
+    class: RawClassPtr,
+    count: *mut c_uint,
+) -> *mut NonNull<OpaqueMethod> {
+    let class = class.ptr.as_ptr() as usize;
+    let methods: Vec<_> = (runtime().methods.get(&class).cloned().unwrap_or_default())
+        .into_iter()
+        .map(|method| NonNull::new(method as *mut OpaqueMethod).unwrap())
+        .collect();
+    copy_list(&methods, count)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn class_copyIvarList(
+    class: RawClassPtr,
+    count: *mut c_uint,
+) -> *mut NonNull<OpaqueIvar> {
+    let class = class.ptr.as_ptr() as usize;
+    let ivars: Vec<_> = (runtime().ivars.get(&class).cloned().unwrap_or_default())
+        .into_iter()
+        .map(|ivar| NonNull::new(ivar as *mut OpaqueIvar).unwrap())
+        .collect();
+    copy_list(&ivars, count)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn class_copyPropertyList(
+    class: RawClassPtr,
+    count: *mut c_uint,
+) -> *mut NonNull<OpaqueProperty> {
+    let class = class.ptr.as_ptr() as usize;
+    let properties: Vec<_> = (runtime()
+        .properties
+        .get(&class)
+        .cloned()
+        .unwrap_or_default())
+    .into_iter()
+    .map(|property| NonNull::new(property as *mut OpaqueProperty).unwrap())
+    .collect();
+    copy_list(&properties, count)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn class_copyProtocolList(
+    class: RawClassPtr,
+    count: *mut c_uint,
+) -> *mut NonNull<OpaqueProtocol> {
+    let class = class.ptr.as_ptr() as usize;
+    let protocols: Vec<_> = (runtime()
+        .adopted_protocols
+        .get(&class)
+        .cloned()
+        .unwrap_or_default())
+    .into_iter()
+    .map(|protocol| NonNull::new(protocol as *mut OpaqueProtocol).unwrap())
+    .collect();
+    copy_list(&protocols, count)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn method_getName(method: NonNull<OpaqueMethod>) -> Sel {
+    method.cast::<MockMethod>().as_ref().sel
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ivar_getName(ivar: NonNull<OpaqueIvar>) -> *const c_char {
+    ivar.cast::<MockIvar>().as_ref().name.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ivar_getTypeEncoding(ivar: NonNull<OpaqueIvar>) -> *const c_char {
+    ivar.cast::<MockIvar>().as_ref().types.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn ivar_getOffset(ivar: NonNull<OpaqueIvar>) -> isize {
+    ivar.cast::<MockIvar>().as_ref().offset as isize
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn property_getName(property: NonNull<OpaqueProperty>) -> *const c_char {
+    property.cast::<MockProperty>().as_ref().name.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn property_getAttributes(
+    property: NonNull<OpaqueProperty>,
+) -> *const c_char {
+    property.cast::<MockProperty>().as_ref().attributes.as_ptr()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn objc_getProtocol(name: *const c_char) -> Option<Protocol> {
+    let addr = *runtime().protocols.get(CStr::from_ptr(name))?;
+    Some(Protocol {
+        ptr: NonNull::new(addr as *mut OpaqueProtocol).unwrap(),
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn protocol_getName(protocol: Protocol) -> *const c_char {
+    protocol.ptr.cast::<MockProtocol>().as_ref().name.as_ptr()
+}
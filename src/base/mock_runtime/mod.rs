@@ -0,0 +1,381 @@
+//! In-process Rust implementation of the runtime entry points choco's ownership layer relies on.
+//!
+//! Enabled by the `mock-runtime` feature, it provides `objc_retain`/`objc_release`, `CFRetain`/`CFRelease`,
+//! `objc_autoreleasePoolPush`/`objc_autoreleasePoolPop`, `objc_retainAutoreleasedReturnValue`, the weak reference functions, `object_getClass`/`class_getSuperclass`,
+//! selectors and methods (`sel_registerName`, `class_addMethod`...), the reflection functions used by `base::Class`,
+//! the class creation functions used by `base::DeclareClass`, associated objects,
+//! `CFGetTypeID`, and enables the blocks runtime of `base::blocks_runtime`,
+//! so the ownership layer can be tested without any Objective-C runtime, for example on Linux CI.
+//!
+//! Objects are created from classes registered in a small class table, and each object keeps its retain count
+//! in a global table. Deallocated objects are never freed but kept as zombies (the same way `NSZombieEnabled` does),
+//! so that an over-release or a use after deallocation can be detected and reported instead of crashing.
+//!
+//! Any misuse is recorded instead of panicking, as a panic can't unwind through the `extern "C"` functions.
+//! Use [`LeakCheck`] to check that a piece of code leaves everything balanced.
+//!
+//! The entry points are split by area: `objects` (retain counts, autorelease pools, weak references and CoreFoundation),
+//! `classes` (classes, selectors, methods and reflection), `associations` and `blocks` (heap blocks reported by the blocks runtime).
+
+// The `extern "C"` functions have the same requirements as the ones of the runtime they are replacing.
+#![allow(clippy::missing_safety_doc)]
+
+mod associations;
+mod blocks;
+mod classes;
+mod objects;
+
+pub use associations::*;
+pub(crate) use blocks::{heap_allocated, heap_released, heap_retained};
+pub use classes::*;
+pub use objects::*;
+
+use objects::Entry;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::thread::ThreadId;
+
+/// A problem detected by the mock runtime.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Issue {
+    /// An object or block still alive.
+    Leak {
+        class_name: &'static str,
+        retain_count: usize,
+    },
+    /// An object released more times than it was retained.
+    OverRelease { class_name: &'static str },
+    /// An object retained after having been deallocated.
+    RetainAfterDealloc { class_name: &'static str },
+    /// `objc_retain` used on a CF object or `CFRetain` on an Objective-C object (same for release).
+    KindMismatch { class_name: &'static str },
+    /// An object autoreleased while no autorelease pool was in place.
+    AutoreleaseWithoutPool { class_name: &'static str },
+    /// A pointer that was not created by the mock runtime was given to it.
+    UnknownPointer,
+}
+
+struct Runtime {
+    classes: HashMap<&'static str, usize>,
+    /// Name of the selectors by name, the address of the name being used as the selector.
+    selectors: HashMap<CString, usize>,
+    /// Methods (leaked `MockMethod`s) of each class, in the order they were added.
+    methods: HashMap<usize, Vec<usize>>,
+    /// Instance variables (leaked `MockIvar`s) of each class, in order.
+    ivars: HashMap<usize, Vec<usize>>,
+    /// Properties (leaked `MockProperty`s) of each class.
+    properties: HashMap<usize, Vec<usize>>,
+    /// Protocols (leaked `MockProtocol`s) by name.
+    protocols: HashMap<CString, usize>,
+    /// Protocols adopted by each class.
+    adopted_protocols: HashMap<usize, Vec<usize>>,
+    /// Associated objects with their policy, by object and key.
+    associations: HashMap<(usize, usize), (usize, usize)>,
+    entries: HashMap<usize, Entry>,
+    /// Issues detected, with the thread and serial at which they happened.
+    issues: Vec<(ThreadId, u64, Issue)>,
+    next_serial: u64,
+}
+
+static RUNTIME: OnceLock<Mutex<Runtime>> = OnceLock::new();
+
+fn runtime() -> MutexGuard<'static, Runtime> {
+    let runtime = RUNTIME.get_or_init(|| {
+        Mutex::new(Runtime {
+            classes: HashMap::new(),
+            selectors: HashMap::new(),
+            methods: HashMap::new(),
+            ivars: HashMap::new(),
+            properties: HashMap::new(),
+            protocols: HashMap::new(),
+            adopted_protocols: HashMap::new(),
+            associations: HashMap::new(),
+            entries: HashMap::new(),
+            issues: Vec::new(),
+            next_serial: 0,
+        })
+    });
+    // The lock is never held while calling user code,
+    // but a test failing while holding it should not prevent the other tests from running.
+    runtime
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Runtime {
+    fn next_serial(&mut self) -> u64 {
+        self.next_serial += 1;
+        self.next_serial
+    }
+
+    fn record_issue(&mut self, issue: Issue) {
+        let serial = self.next_serial();
+        self.issues
+            .push((std::thread::current().id(), serial, issue));
+    }
+}
+
+/// Checks that the objects and blocks created on the current thread since the check was started have all been deallocated,
+/// and that no other problem occurred on the current thread in the meantime.
+///
+/// Only looking at the current thread makes it possible to use it from tests running in parallel.
+pub struct LeakCheck {
+    thread: ThreadId,
+    start_serial: u64,
+}
+
+impl LeakCheck {
+    pub fn start() -> Self {
+        let mut runtime = runtime();
+        Self {
+            thread: std::thread::current().id(),
+            start_serial: runtime.next_serial(),
+        }
+    }
+
+    pub fn issues(&self) -> Vec<Issue> {
+        let runtime = runtime();
+        let mut issues: Vec<(u64, Issue)> = runtime
+            .entries
+            .values()
+            .filter(|entry| {
+                entry.thread == self.thread
+                    && entry.serial > self.start_serial
+                    && entry.retain_count > 0
+            })
+            .map(|entry| {
+                (
+                    entry.serial,
+                    Issue::Leak {
+                        class_name: entry.class_name,
+                        retain_count: entry.retain_count,
+                    },
+                )
+            })
+            .collect();
+        issues.extend(
+            runtime
+                .issues
+                .iter()
+                .filter(|(thread, serial, _)| *thread == self.thread && *serial > self.start_serial)
+                .map(|(_, serial, issue)| (*serial, issue.clone())),
+        );
+        issues.sort_by_key(|(serial, _)| *serial);
+        issues.into_iter().map(|(_, issue)| issue).collect()
+    }
+
+    pub fn assert_balanced(&self) {
+        let issues = self.issues();
+        assert!(issues.is_empty(), "mock runtime issues: {:?}", issues);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base::block::{HeapBlock, StackBlock};
+    use crate::base::objc::autorelease_pool;
+    use crate::base::{Ptr, RawObjPtr, Retained, Static, Type, TypeKind};
+
+    struct MockObjCType {}
+
+    impl Type for MockObjCType {
+        const KIND: TypeKind = TypeKind::ObjC;
+    }
+
+    struct MockCFType {}
+
+    impl Type for MockCFType {
+        const KIND: TypeKind = TypeKind::CF;
+    }
+
+    fn new_object() -> Ptr<MockObjCType, Retained> {
+        let class = register_class("MockObject");
+        unsafe { Ptr::from_raw_unchecked(create_object(class)) }
+    }
+
+    #[test]
+    fn class_table() {
+        let class1 = register_class("MockClass1");
+        let class2 = register_class("MockClass2");
+        assert_eq!(class_name(class1), "MockClass1");
+        assert_eq!(class_name(class2), "MockClass2");
+        assert_eq!(
+            class1.ptr,
+            register_class("MockClass1").ptr,
+            "registering twice should return the same class"
+        );
+        let obj = create_object(class2);
+        assert_eq!(class_name(object_class(obj)), "MockClass2");
+        unsafe { objc_release(obj) };
+    }
+
+    #[test]
+    fn drop_releases() {
+        let check = LeakCheck::start();
+        let obj = new_object();
+        let raw = obj.as_raw();
+        assert_eq!(retain_count(raw), 1);
+        drop(obj);
+        assert!(!is_alive(raw));
+        check.assert_balanced();
+    }
+
+    #[test]
+    fn retain() {
+        let check = LeakCheck::start();
+        let obj1 = new_object();
+        let obj2 = obj1.retain();
+        let raw = obj1.as_raw();
+        assert_eq!(retain_count(raw), 2);
+        drop(obj1);
+        assert_eq!(retain_count(raw), 1);
+        drop(obj2);
+        assert!(!is_alive(raw));
+        check.assert_balanced();
+    }
+
+    #[test]
+    fn static_is_never_released() {
+        let check = LeakCheck::start();
+        let raw = create_object(register_class("MockObject"));
+        let obj: Ptr<MockObjCType, Static> = unsafe { Ptr::from_raw_unchecked(raw) };
+        let retained = obj.retain();
+        drop(obj);
+        assert_eq!(retain_count(raw), 2);
+        drop(retained);
+        assert_eq!(retain_count(raw), 1);
+        unsafe { objc_release(raw) };
+        check.assert_balanced();
+    }
+
+    #[test]
+    fn cf_types_use_cf_retain() {
+        let check = LeakCheck::start();
+        let raw = create_cf_object(register_class("MockCFObject"));
+        let obj: Ptr<MockCFType, Retained> = unsafe { Ptr::from_raw_unchecked(raw) };
+        let retained = obj.retain();
+        drop(obj);
+        drop(retained);
+        assert!(!is_alive(raw));
+        check.assert_balanced();
+    }
+
+    #[test]
+    fn detects_issues() {
+        let check = LeakCheck::start();
+        let leaked = new_object();
+        let raw = leaked.as_raw();
+        std::mem::forget(leaked);
+        assert_eq!(
+            check.issues(),
+            vec![Issue::Leak {
+                class_name: "MockObject",
+                retain_count: 1
+            }]
+        );
+
+        let check = LeakCheck::start();
+        unsafe {
+            objc_release(raw);
+            objc_release(raw);
+            CFRetain(raw);
+        }
+        assert_eq!(
+            check.issues(),
+            vec![
+                Issue::OverRelease {
+                    class_name: "MockObject"
+                },
+                Issue::RetainAfterDealloc {
+                    class_name: "MockObject"
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn autorelease_pool_drains() {
+        let check = LeakCheck::start();
+        let raw = autorelease_pool(|_| {
+            let obj = new_object();
+            let retained = obj.retain();
+            // That reference now belongs to the autorelease pool.
+            unsafe { objc_autorelease(retained.as_raw()) };
+            std::mem::forget(retained);
+            let nested = autorelease_pool(|_| {
+                let obj = new_object();
+                let raw = obj.as_raw();
+                std::mem::forget(obj);
+                unsafe { objc_autorelease(raw) }
+            });
+            assert!(!is_alive(nested));
+            assert_eq!(retain_count(obj.as_raw()), 2);
+            obj.as_raw()
+        });
+        assert!(!is_alive(raw));
+        check.assert_balanced();
+    }
+
+    #[test]
+    fn autorelease_without_pool() {
+        let check = LeakCheck::start();
+        let obj = new_object();
+        unsafe { objc_autorelease(obj.as_raw()) };
+        std::mem::forget(obj);
+        assert_eq!(check.issues().len(), 2);
+        assert_eq!(
+            check.issues()[1],
+            Issue::AutoreleaseWithoutPool {
+                class_name: "MockObject"
+            }
+        );
+    }
+
+    /// `Ptr` is not `Send`, but blocks need their content to be.
+    struct SendPtr(Ptr<MockObjCType, Retained>);
+    unsafe impl Send for SendPtr {}
+    unsafe impl Sync for SendPtr {}
+
+    impl SendPtr {
+        fn as_raw(&self) -> RawObjPtr {
+            self.0.as_raw()
+        }
+    }
+
+    impl Clone for SendPtr {
+        fn clone(&self) -> Self {
+            Self(self.0.retain())
+        }
+    }
+
+    #[test]
+    fn heap_block() {
+        let check = LeakCheck::start();
+        let obj = SendPtr(new_object());
+        let raw = obj.0.as_raw();
+        let block = HeapBlock::new(move || obj.as_raw());
+        assert_eq!(retain_count(raw), 1);
+        let block2 = block.clone();
+        drop(block);
+        assert!(is_alive(raw));
+        drop(block2);
+        assert!(!is_alive(raw));
+        check.assert_balanced();
+    }
+
+    #[test]
+    fn stack_block_to_heap() {
+        let check = LeakCheck::start();
+        let obj = SendPtr(new_object());
+        let raw = obj.0.as_raw();
+        let stack_block = StackBlock::new(move || obj.as_raw());
+        let heap_block: HeapBlock<_, (), RawObjPtr> = stack_block.into();
+        // The captured pointer was cloned when copying to the heap, and the stack version dropped.
+        assert_eq!(retain_count(raw), 1);
+        drop(heap_block);
+        assert!(!is_alive(raw));
+        check.assert_balanced();
+    }
+}
@@ -0,0 +1,348 @@
+//-------------------------------------------------------------------
+// Objects: retain counts, autorelease pools, weak references and CoreFoundation
+
+use super::{
+    class_name, find_method, remove_associated_objects, runtime, sel_registerName, Issue,
+    MockClass, Runtime,
+};
+use crate::base::{CFTypeID, Imp, OpaqueClass, RawClassPtr, RawObjPtr, Sel};
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::ptr::NonNull;
+use std::thread::ThreadId;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(super) enum ObjectKind {
+    ObjC,
+    CF,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(super) enum EntryKind {
+    Object(ObjectKind),
+    Block,
+}
+
+/// What the object pointer points to: like for a real Objective-C object, the first field is the class.
+#[repr(C)]
+pub(super) struct MockObject {
+    isa: *const MockClass,
+}
+
+pub(super) struct Entry {
+    pub(super) kind: EntryKind,
+    pub(super) class_name: &'static str,
+    pub(super) retain_count: usize,
+    /// Thread that created the object, used by `LeakCheck` to only look at objects it is interested in.
+    pub(super) thread: ThreadId,
+    pub(super) serial: u64,
+    /// Locations of the weak references to the object, set to nil when it gets deallocated.
+    pub(super) weak_locations: Vec<usize>,
+}
+
+impl Runtime {
+    pub(super) fn insert(&mut self, addr: usize, kind: EntryKind, class_name: &'static str) {
+        let serial = self.next_serial();
+        self.entries.insert(
+            addr,
+            Entry {
+                kind,
+                class_name,
+                retain_count: 1,
+                thread: std::thread::current().id(),
+                serial,
+                weak_locations: Vec::new(),
+            },
+        );
+    }
+
+    pub(super) fn retain(&mut self, addr: usize, kind: ObjectKind) {
+        let issue = match self.entries.get_mut(&addr) {
+            None => Some(Issue::UnknownPointer),
+            Some(entry) => {
+                let class_name = entry.class_name;
+                if entry.retain_count == 0 {
+                    Some(Issue::RetainAfterDealloc { class_name })
+                } else {
+                    entry.retain_count += 1;
+                    if entry.kind != EntryKind::Object(kind) {
+                        Some(Issue::KindMismatch { class_name })
+                    } else {
+                        None
+                    }
+                }
+            }
+        };
+        if let Some(issue) = issue {
+            self.record_issue(issue);
+        }
+    }
+
+    /// Returns true if the object just got deallocated.
+    pub(super) fn release(&mut self, addr: usize, kind: ObjectKind) -> bool {
+        let mut deallocated = false;
+        let issue = match self.entries.get_mut(&addr) {
+            None => Some(Issue::UnknownPointer),
+            Some(entry) => {
+                let class_name = entry.class_name;
+                if entry.retain_count == 0 {
+                    Some(Issue::OverRelease { class_name })
+                } else {
+                    // When getting to 0 the object is considered deallocated, but stays in the table as a zombie.
+                    entry.retain_count -= 1;
+                    if entry.retain_count == 0 {
+                        deallocated = true;
+                        for location in entry.weak_locations.drain(..) {
+                            unsafe { *(location as *mut Option<RawObjPtr>) = None };
+                        }
+                    }
+                    if entry.kind != EntryKind::Object(kind) {
+                        Some(Issue::KindMismatch { class_name })
+                    } else {
+                        None
+                    }
+                }
+            }
+        };
+        if let Some(issue) = issue {
+            self.record_issue(issue);
+        }
+        deallocated
+    }
+}
+
+fn create(class: RawClassPtr, kind: ObjectKind) -> RawObjPtr {
+    let name = class_name(class);
+    // Room for the instance variables, zeroed like the real runtime does.
+    let words = runtime()
+        .instance_size(class)
+        .div_ceil(std::mem::size_of::<usize>());
+    let storage = vec![0usize; words].into_boxed_slice();
+    // Never freed: once deallocated the object stays around as a zombie.
+    let object = Box::into_raw(storage) as *mut MockObject;
+    unsafe {
+        object.write(MockObject {
+            isa: class.ptr.as_ptr() as *const MockClass,
+        })
+    };
+    let addr = object as usize;
+    runtime().insert(addr, EntryKind::Object(kind), name);
+    RawObjPtr {
+        ptr: NonNull::new(addr as *mut _).unwrap(),
+    }
+}
+
+/// Creates a new Objective-C object with a retain count of 1.
+pub fn create_object(class: RawClassPtr) -> RawObjPtr {
+    create(class, ObjectKind::ObjC)
+}
+
+/// Creates a new CoreFoundation object with a retain count of 1.
+pub fn create_cf_object(class: RawClassPtr) -> RawObjPtr {
+    create(class, ObjectKind::CF)
+}
+
+/// Class of an object created by the mock runtime.
+pub fn object_class(obj: RawObjPtr) -> RawClassPtr {
+    let object = obj.ptr.as_ptr() as *const MockObject;
+    let isa = unsafe { (*object).isa };
+    RawClassPtr {
+        ptr: NonNull::new(isa as *mut OpaqueClass).unwrap(),
+    }
+}
+
+/// Current retain count of an object, 0 if it has been deallocated.
+pub fn retain_count(obj: RawObjPtr) -> usize {
+    let addr = obj.ptr.as_ptr() as usize;
+    runtime()
+        .entries
+        .get(&addr)
+        .map_or(0, |entry| entry.retain_count)
+}
+
+pub fn is_alive(obj: RawObjPtr) -> bool {
+    retain_count(obj) > 0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn objc_retain(value: RawObjPtr) -> Option<RawObjPtr> {
+    runtime().retain(value.ptr.as_ptr() as usize, ObjectKind::ObjC);
+    Some(value)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn objc_release(value: RawObjPtr) {
+    let deallocated = runtime().release(value.ptr.as_ptr() as usize, ObjectKind::ObjC);
+    // Called with the runtime unlocked, as deallocating an object can release others.
+    if deallocated {
+        let sel = sel_registerName(c"dealloc".as_ptr()).unwrap();
+        if let Some(method) = find_method(Some(object_class(value)), sel) {
+            let dealloc =
+                std::mem::transmute::<Imp, unsafe extern "C-unwind" fn(RawObjPtr, Sel)>(method.imp);
+            dealloc(value, sel);
+        }
+        // Like the real runtime, the associated objects are released after `dealloc`.
+        remove_associated_objects(value);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn object_getClass(obj: Option<RawObjPtr>) -> Option<RawClassPtr> {
+    obj.map(object_class)
+}
+
+/// Objects are never returned with `objc_autoreleaseReturnValue` in the mock runtime, so it is just a retain.
+#[no_mangle]
+pub unsafe extern "C" fn objc_retainAutoreleasedReturnValue(
+    value: Option<RawObjPtr>,
+) -> Option<RawObjPtr> {
+    objc_retain(value?)
+}
+
+thread_local! {
+    /// Objects autoreleased in each of the autorelease pools of the current thread.
+    static AUTORELEASE_POOLS: RefCell<Vec<Vec<RawObjPtr>>> = const { RefCell::new(Vec::new()) };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn objc_autorelease(value: RawObjPtr) -> RawObjPtr {
+    let added = AUTORELEASE_POOLS.with(|pools| match pools.borrow_mut().last_mut() {
+        Some(pool) => {
+            pool.push(value);
+            true
+        }
+        None => false,
+    });
+    if !added {
+        // Like the real runtime, the object just leaks.
+        let class_name = class_name(object_class(value));
+        runtime().record_issue(Issue::AutoreleaseWithoutPool { class_name });
+    }
+    value
+}
+
+/// The pool "pointer" is just its 1-based depth in the current thread's pool stack.
+#[no_mangle]
+pub unsafe extern "C" fn objc_autoreleasePoolPush() -> *mut c_void {
+    let depth = AUTORELEASE_POOLS.with(|pools| {
+        let mut pools = pools.borrow_mut();
+        pools.push(Vec::new());
+        pools.len()
+    });
+    depth as *mut c_void
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn objc_autoreleasePoolPop(pool: *mut c_void) {
+    let depth = pool as usize;
+    // Pools pushed after the one popped are also popped.
+    // The objects are released outside of the borrow as releasing can end up autoreleasing.
+    while let Some(objects) = AUTORELEASE_POOLS.with(|pools| {
+        let mut pools = pools.borrow_mut();
+        if pools.len() >= depth {
+            pools.pop()
+        } else {
+            None
+        }
+    }) {
+        for object in objects {
+            objc_release(object);
+        }
+    }
+}
+
+// All the weak reference functions run with the runtime locked, so that an object can't be deallocated
+// while a weak reference to it is being loaded.
+
+impl Runtime {
+    /// Registers `location` as a weak reference to `obj`, storing nil instead if `obj` is deallocated.
+    unsafe fn store_weak(&mut self, location: *mut Option<RawObjPtr>, obj: Option<RawObjPtr>) {
+        let obj = obj.filter(|obj| {
+            let addr = obj.ptr.as_ptr() as usize;
+            match self.entries.get_mut(&addr) {
+                Some(entry) if entry.retain_count > 0 => {
+                    entry.weak_locations.push(location as usize);
+                    true
+                }
+                _ => false,
+            }
+        });
+        *location = obj;
+    }
+
+    unsafe fn unregister_weak(&mut self, location: *mut Option<RawObjPtr>) {
+        if let Some(obj) = *location {
+            let addr = obj.ptr.as_ptr() as usize;
+            if let Some(entry) = self.entries.get_mut(&addr) {
+                entry
+                    .weak_locations
+                    .retain(|weak_location| *weak_location != location as usize);
+            }
+        }
+        *location = None;
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn objc_initWeak(
+    location: *mut Option<RawObjPtr>,
+    obj: Option<RawObjPtr>,
+) -> Option<RawObjPtr> {
+    runtime().store_weak(location, obj);
+    obj
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn objc_storeWeak(
+    location: *mut Option<RawObjPtr>,
+    obj: Option<RawObjPtr>,
+) -> Option<RawObjPtr> {
+    let mut runtime = runtime();
+    runtime.unregister_weak(location);
+    runtime.store_weak(location, obj);
+    obj
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn objc_loadWeakRetained(
+    location: *mut Option<RawObjPtr>,
+) -> Option<RawObjPtr> {
+    let mut runtime = runtime();
+    let obj = (*location)?;
+    runtime.retain(obj.ptr.as_ptr() as usize, ObjectKind::ObjC);
+    Some(obj)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn objc_copyWeak(to: *mut Option<RawObjPtr>, from: *mut Option<RawObjPtr>) {
+    let mut runtime = runtime();
+    let obj = *from;
+    runtime.store_weak(to, obj);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn objc_destroyWeak(location: *mut Option<RawObjPtr>) {
+    runtime().unregister_weak(location);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn CFRetain(cf: RawObjPtr) -> Option<RawObjPtr> {
+    runtime().retain(cf.ptr.as_ptr() as usize, ObjectKind::CF);
+    Some(cf)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn CFRelease(cf: RawObjPtr) {
+    runtime().release(cf.ptr.as_ptr() as usize, ObjectKind::CF);
+}
+
+/// The type ID of a CoreFoundation object is the address of its class.
+#[no_mangle]
+pub unsafe extern "C" fn CFGetTypeID(cf: RawObjPtr) -> CFTypeID {
+    CFTypeID(object_class(cf).ptr.as_ptr() as usize)
+}
+
+/// Type ID of the objects created by `create_cf_object` with that class.
+pub fn cf_type_id(class: RawClassPtr) -> CFTypeID {
+    CFTypeID(class.ptr.as_ptr() as usize)
+}
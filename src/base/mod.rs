@@ -1,16 +1,47 @@
 use std::marker::PhantomData;
 use std::ptr::NonNull;
 
-pub(crate) mod block;
+mod associated;
+pub mod block;
+#[cfg(feature = "blocks-runtime")]
+pub mod blocks_runtime;
+mod class;
+mod completion;
 pub(crate) mod core_foundation;
-
-#[link(name = "objc", kind = "dylib")]
+mod declare;
+mod encode;
+mod fourcc;
+mod message;
+#[cfg(feature = "mock-runtime")]
+pub mod mock_runtime;
+pub mod objc;
+
+pub use associated::*;
+pub use class::*;
+pub use completion::*;
+pub use core_foundation::CFTypeID;
+pub use declare::*;
+pub use encode::*;
+pub use fourcc::*;
+pub use message::*;
+
+// With the `mock-runtime` feature, the functions below are provided by `mock_runtime`.
+#[cfg_attr(not(feature = "mock-runtime"), link(name = "objc", kind = "dylib"))]
 extern "C" {
     fn objc_release(value: RawObjPtr);
     fn objc_retain(value: RawObjPtr) -> Option<RawObjPtr>;
+    fn objc_autorelease(value: RawObjPtr) -> RawObjPtr;
+    fn objc_retainAutoreleasedReturnValue(value: Option<RawObjPtr>) -> Option<RawObjPtr>;
 }
 
-#[link(name = "CoreFoundation", kind = "framework")]
+#[cfg_attr(
+    all(target_vendor = "apple", not(feature = "mock-runtime")),
+    link(name = "CoreFoundation", kind = "framework")
+)]
+#[cfg_attr(
+    all(not(target_vendor = "apple"), not(feature = "mock-runtime")),
+    link(name = "gnustep-corebase")
+)]
 extern "C" {
     fn CFRelease(cf: RawObjPtr);
     fn CFRetain(cf: RawObjPtr) -> Option<RawObjPtr>;
@@ -39,7 +70,15 @@ pub trait ObjCClass: Type {
     fn class() -> RawClassPtr;
 }
 
+/// What `ObjCClass` is for Objective-C types, for CoreFoundation types.
+pub trait CFClass: Type {
+    fn type_id() -> CFTypeID;
+}
+
 /// Marker trait used for handling of type parameters in NSArray and NSDictionary.
+///
+/// # Safety
+/// Only implement it if an instance of `Self` can always be used where a `T` is expected.
 pub unsafe trait IsKindOf<T: Type>: Type {}
 unsafe impl<T: Type> IsKindOf<T> for T {}
 
@@ -50,6 +89,8 @@ pub struct RawObjPtr {
 }
 
 impl RawObjPtr {
+    /// # Safety
+    /// The object pointed to must be of type `T`, and must still be alive.
     pub unsafe fn retain<T: Type>(&self) -> Ptr<T, Retained> {
         let retained = match T::KIND {
             TypeKind::ObjC => objc_retain(*self),
@@ -63,7 +104,7 @@ impl RawObjPtr {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Eq, PartialEq)]
 #[repr(transparent)]
 pub struct RawClassPtr {
     ptr: NonNull<OpaqueClass>,
@@ -104,6 +145,49 @@ where
     pub fn as_raw(&self) -> RawObjPtr {
         self.raw
     }
+
+    /// Converts to a pointer to a more general type, for example from `Ptr<NSString>` to `Ptr<NSObject>`.
+    pub fn upcast<U: Type>(self) -> Ptr<U, O>
+    where
+        T: IsKindOf<U>,
+    {
+        let raw = self.raw;
+        // The reference (if any) now belongs to the new pointer.
+        std::mem::forget(self);
+        unsafe { Ptr::from_raw_unchecked(raw) }
+    }
+}
+
+impl<T: ObjCClass, O: Ownership> Ptr<T, O> {
+    /// Converts to a pointer to `U` if the object is an instance of `U` (or of one of its subclasses),
+    /// gives back the original pointer otherwise.
+    ///
+    /// The class of the object and its superclasses are checked directly with the runtime,
+    /// as the default implementation of `-[NSObject isKindOfClass:]` does, but overrides of `isKindOfClass:`
+    /// are not used: an `NSProxy` is only considered an instance of its own class, not of the class of the object
+    /// it stands for. That way the object really has the layout of `U` (the instance variables of a `DeclareClass`...).
+    pub fn downcast<U: ObjCClass>(self) -> Result<Ptr<U, O>, Self> {
+        if objc::is_kind_of_class(self.raw, U::class()) {
+            let raw = self.raw;
+            std::mem::forget(self);
+            Ok(unsafe { Ptr::from_raw_unchecked(raw) })
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl<T: CFClass, O: Ownership> Ptr<T, O> {
+    /// Same as `downcast`, for CoreFoundation types, checked with the type ID of the object.
+    pub fn downcast_cf<U: CFClass>(self) -> Result<Ptr<U, O>, Self> {
+        if unsafe { core_foundation::CFGetTypeID(self.raw) } == U::type_id() {
+            let raw = self.raw;
+            std::mem::forget(self);
+            Ok(unsafe { Ptr::from_raw_unchecked(raw) })
+        } else {
+            Err(self)
+        }
+    }
 }
 
 pub trait AsRaw {
@@ -154,6 +238,91 @@ impl Ownership for Static {
 unsafe impl<T: Type> Send for Ptr<T, Static> {}
 unsafe impl<T: Type> Sync for Ptr<T, Static> {}
 
+/// Ownership of objects autoreleased in the autorelease pool `'pool`, that keeps them alive until it is popped.
+///
+/// Use `retain` to get a pointer that can outlive the pool:
+///
+/// ```compile_fail
+/// use choco::base::objc::{autorelease_pool, AutoreleasePool};
+/// use choco::base::{Autoreleased, Ptr, Type};
+///
+/// fn escape<T: Type>(get: impl Fn(&AutoreleasePool) -> Ptr<T, Autoreleased<'_>>) {
+///     // Does not compile: the pointer can't outlive the pool.
+///     let ptr = autorelease_pool(|pool| get(pool));
+/// }
+/// ```
+pub struct Autoreleased<'pool> {
+    _marker: PhantomData<&'pool objc::AutoreleasePool>,
+}
+
+impl Ownership for Autoreleased<'_> {
+    // The pool does it.
+    fn release<T: Type>(_raw: RawObjPtr) {}
+}
+
+impl<'pool, T: Type> Ptr<T, Autoreleased<'pool>> {
+    /// # Safety
+    /// `raw` must point to an object of type `T`, autoreleased in the innermost pool, that `pool` must be.
+    pub unsafe fn from_autoreleased_unchecked(
+        raw: RawObjPtr,
+        pool: &'pool objc::AutoreleasePool,
+    ) -> Self {
+        pool.assert_innermost();
+        Self::from_raw_unchecked(raw)
+    }
+}
+
+impl<T: ObjCClass> Ptr<T, Retained> {
+    /// Hands the reference over to `pool`, which avoids a release when the pointer is used only in the pool.
+    ///
+    /// Panics if a pool was pushed by `autorelease_pool` inside `pool`.
+    ///
+    /// # Safety
+    /// `pool` must be the innermost autorelease pool of the thread. Pools pushed by Objective-C code,
+    /// for example by a framework calling a block inside `@autoreleasepool`, are not seen by the check,
+    /// and the object would be released when that pool gets popped, before the end of `pool`.
+    pub unsafe fn autorelease(self, pool: &objc::AutoreleasePool) -> Ptr<T, Autoreleased<'_>> {
+        pool.assert_innermost();
+        let raw = objc_autorelease(self.raw);
+        std::mem::forget(self);
+        Ptr::from_raw_unchecked(raw)
+    }
+
+    /// Retains an object returned autoreleased by the function that was just called,
+    /// for example an Objective-C shim not marked `NS_RETURNS_RETAINED`.
+    ///
+    /// If that function returned with `objc_autoreleaseReturnValue`, as ARC does,
+    /// the runtime can skip both the autorelease and the retain when it recognizes the caller:
+    /// - libobjc2 keeps the returned object aside for the next `objc_retainAutoreleasedReturnValue` of the thread.
+    /// - Apple's runtime on arm looks for a marker instruction right after the call, emitted here,
+    ///   so this must be called immediately after the function (it is always inlined).
+    /// - Apple's runtime on x86_64 looks for the call to `objc_retainAutoreleasedReturnValue`
+    ///   right after the call, which depends on the code generated, so it is not guaranteed.
+    ///
+    /// Returns `None` if the function returned nil.
+    ///
+    /// # Safety
+    /// `raw` must point to an object of type `T` (or be nil), and be the value returned by the function called just before.
+    #[inline(always)]
+    pub unsafe fn from_autoreleased_return_value(raw: Option<RawObjPtr>) -> Option<Self> {
+        // Same markers as the ones clang emits with ARC.
+        #[cfg(all(
+            target_vendor = "apple",
+            target_arch = "aarch64",
+            not(feature = "mock-runtime")
+        ))]
+        std::arch::asm!("mov x29, x29", options(nomem, nostack, preserves_flags));
+        #[cfg(all(
+            target_vendor = "apple",
+            target_arch = "arm",
+            not(feature = "mock-runtime")
+        ))]
+        std::arch::asm!("mov r7, r7", options(nomem, nostack, preserves_flags));
+        let retained = objc_retainAutoreleasedReturnValue(raw)?;
+        Some(Self::from_raw_unchecked(retained))
+    }
+}
+
 pub type NSInteger = isize;
 pub type NSUInteger = usize;
 
@@ -192,30 +361,145 @@ impl From<bool> for Boolean {
     }
 }
 
-/// Computes the FourCC for the string passed.
-///
-/// Expecting the &str passed to be all ASCII of length 4.
-/// No explicit check is done (though a shorter length will end up with a panic).
-const fn fourcc(text: &str) -> u32 {
-    let bytes = text.as_bytes();
-
-    if bytes.len() != 4
-        || !bytes[0].is_ascii()
-        || !bytes[1].is_ascii()
-        || !bytes[2].is_ascii()
-        || !bytes[3].is_ascii()
-    {
-        panic!("invalid FOURCC code");
+#[cfg(all(test, feature = "mock-runtime"))]
+mod ptr_tests {
+    use super::mock_runtime::{
+        cf_type_id, create_cf_object, create_object, is_alive, register_class, register_subclass,
+        retain_count, LeakCheck,
+    };
+    use super::*;
+
+    struct MockBase {}
+
+    impl Type for MockBase {
+        const KIND: TypeKind = TypeKind::ObjC;
     }
 
-    (bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | (bytes[3] as u32)
-}
+    impl ObjCClass for MockBase {
+        fn class() -> RawClassPtr {
+            register_class("MockBase")
+        }
+    }
+
+    struct MockDerived {}
+
+    impl Type for MockDerived {
+        const KIND: TypeKind = TypeKind::ObjC;
+    }
+
+    impl ObjCClass for MockDerived {
+        fn class() -> RawClassPtr {
+            register_subclass("MockDerived", MockBase::class())
+        }
+    }
+
+    unsafe impl IsKindOf<MockBase> for MockDerived {}
+
+    struct MockCFString {}
+
+    impl Type for MockCFString {
+        const KIND: TypeKind = TypeKind::CF;
+    }
+
+    impl CFClass for MockCFString {
+        fn type_id() -> CFTypeID {
+            cf_type_id(register_class("MockCFString"))
+        }
+    }
+
+    struct MockCFNumber {}
+
+    impl Type for MockCFNumber {
+        const KIND: TypeKind = TypeKind::CF;
+    }
+
+    impl CFClass for MockCFNumber {
+        fn type_id() -> CFTypeID {
+            cf_type_id(register_class("MockCFNumber"))
+        }
+    }
+
+    #[test]
+    fn upcast_and_downcast() {
+        let check = LeakCheck::start();
+        let derived: Ptr<MockDerived> =
+            unsafe { Ptr::from_raw_unchecked(create_object(MockDerived::class())) };
+        let raw = derived.as_raw();
+        let base: Ptr<MockBase> = derived.upcast();
+        assert_eq!(retain_count(raw), 1);
+
+        let derived = base.downcast::<MockDerived>().ok().unwrap();
+        assert_eq!(retain_count(raw), 1);
+        // Any object is also an instance of its superclasses.
+        let base = derived.downcast::<MockBase>().ok().unwrap();
+        drop(base);
+        assert!(!is_alive(raw));
+
+        let base: Ptr<MockBase> =
+            unsafe { Ptr::from_raw_unchecked(create_object(MockBase::class())) };
+        let raw = base.as_raw();
+        let base = base.downcast::<MockDerived>().err().unwrap();
+        assert_eq!(retain_count(raw), 1);
+
+        // Does not change the reference count, even for static pointers.
+        let static_base: Ptr<MockBase, Static> = unsafe { Ptr::from_raw_unchecked(raw) };
+        assert!(static_base.downcast::<MockDerived>().is_err());
+        drop(base);
+        check.assert_balanced();
+    }
+
+    #[test]
+    fn downcast_cf() {
+        let check = LeakCheck::start();
+        let raw = create_cf_object(register_class("MockCFString"));
+        let string: Ptr<MockCFString> = unsafe { Ptr::from_raw_unchecked(raw) };
+        let string = string.downcast_cf::<MockCFNumber>().err().unwrap();
+        let string = string.downcast_cf::<MockCFString>().ok().unwrap();
+        assert_eq!(retain_count(raw), 1);
+        drop(string);
+        check.assert_balanced();
+    }
+
+    #[test]
+    fn autoreleased() {
+        let check = LeakCheck::start();
+        let raw = objc::autorelease_pool(|pool| {
+            let obj: Ptr<MockDerived> =
+                unsafe { Ptr::from_raw_unchecked(create_object(MockDerived::class())) };
+            let raw = obj.as_raw();
+            let autoreleased = unsafe { obj.autorelease(pool) };
+            // Dropping an autoreleased pointer does nothing, the pool releases the object.
+            let upcast: Ptr<MockBase, Autoreleased<'_>> = autoreleased.upcast();
+            drop(upcast);
+            assert_eq!(retain_count(raw), 1);
+
+            let autoreleased: Ptr<MockDerived, Autoreleased<'_>> =
+                unsafe { Ptr::from_autoreleased_unchecked(raw, pool) };
+            let escaping = autoreleased.retain();
+            assert_eq!(retain_count(raw), 2);
+
+            let from_return_value: Ptr<MockDerived> =
+                unsafe { Ptr::from_autoreleased_return_value(Some(raw)) }.unwrap();
+            assert_eq!(retain_count(raw), 3);
+            drop(from_return_value);
+            assert!(unsafe { Ptr::<MockDerived>::from_autoreleased_return_value(None) }.is_none());
+            escaping
+        });
+        assert_eq!(retain_count(raw.as_raw()), 1);
+        drop(raw);
+        check.assert_balanced();
+    }
 
-#[cfg(test)]
-mod tests {
     #[test]
-    fn fourcc() {
-        assert_eq!(super::fourcc("soun"), 0x736F756Eu32);
-        assert_eq!(super::fourcc("text"), 0x74657874u32);
+    #[should_panic(expected = "autoreleasing in a pool that is not the innermost one")]
+    fn autoreleasing_in_outer_pool() {
+        objc::autorelease_pool(|outer| {
+            objc::autorelease_pool(|_inner| {
+                let obj: Ptr<MockBase> =
+                    unsafe { Ptr::from_raw_unchecked(create_object(MockBase::class())) };
+                // The object would be released when the inner pool is popped.
+                let _ = unsafe { obj.autorelease(outer) };
+            })
+        })
     }
 }
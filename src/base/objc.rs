@@ -1,4 +1,6 @@
-use crate::base::ptr;
+use super::{ObjCClass, Ownership, Ptr, RawClassPtr, RawObjPtr, Retained};
+use std::cell::{Cell, UnsafeCell};
+use std::marker::PhantomData;
 use std::ptr::NonNull;
 
 #[repr(C)]
@@ -6,12 +8,17 @@ struct OpaqueAutoreleasePool {
     _private: [u8; 0],
 }
 
-#[link(name = "objc", kind = "dylib")]
+#[cfg_attr(not(feature = "mock-runtime"), link(name = "objc", kind = "dylib"))]
 extern "C" {
     fn objc_autoreleasePoolPush() -> Option<NonNull<OpaqueAutoreleasePool>>;
     fn objc_autoreleasePoolPop(pool: NonNull<OpaqueAutoreleasePool>);
 }
 
+thread_local! {
+    /// Number of pools pushed by `autorelease_pool` on the current thread.
+    static POOL_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
 struct AutoreleasePoolGuard {
     pool: NonNull<OpaqueAutoreleasePool>,
 }
@@ -20,6 +27,7 @@ impl AutoreleasePoolGuard {
     fn push() -> AutoreleasePoolGuard {
         let pool = unsafe { objc_autoreleasePoolPush() }
             .expect("expecting objc_autoreleasePoolPush() to return a non-null value");
+        POOL_DEPTH.with(|depth| depth.set(depth.get() + 1));
         AutoreleasePoolGuard { pool }
     }
 }
@@ -27,10 +35,242 @@ impl AutoreleasePoolGuard {
 impl Drop for AutoreleasePoolGuard {
     fn drop(&mut self) {
         unsafe { objc_autoreleasePoolPop(self.pool) }
+        POOL_DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+/// Token of an autorelease pool given by `autorelease_pool`.
+///
+/// Pointers to objects autoreleased in the pool (`Ptr<T, Autoreleased<'pool>>`) borrow it,
+/// so that they can't be used after the pool has been popped.
+pub struct AutoreleasePool {
+    depth: usize,
+    // Autorelease pools are per thread.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl AutoreleasePool {
+    /// Panics if the pool is not the innermost one, as objects autoreleased would go to the innermost one,
+    /// and be released before the end of the lifetime of the pool.
+    ///
+    /// Only the pools pushed by `autorelease_pool` are known, not the ones pushed by Objective-C code.
+    pub(crate) fn assert_innermost(&self) {
+        let current_depth = POOL_DEPTH.with(|depth| depth.get());
+        assert_eq!(
+            self.depth, current_depth,
+            "autoreleasing in a pool that is not the innermost one"
+        );
+    }
+}
+
+pub fn autorelease_pool<Ret, F: FnOnce(&AutoreleasePool) -> Ret>(f: F) -> Ret {
+    let _guard = AutoreleasePoolGuard::push();
+    let pool = AutoreleasePool {
+        depth: POOL_DEPTH.with(|depth| depth.get()),
+        _not_send: PhantomData,
+    };
+    f(&pool)
+}
+
+//-------------------------------------------------------------------
+// Classes
+
+#[cfg_attr(not(feature = "mock-runtime"), link(name = "objc", kind = "dylib"))]
+extern "C" {
+    fn object_getClass(obj: Option<RawObjPtr>) -> Option<RawClassPtr>;
+    fn class_getSuperclass(class: Option<RawClassPtr>) -> Option<RawClassPtr>;
+}
+
+/// Whether `obj` is an instance of `class` or of one of its subclasses.
+///
+/// Does the same as the default implementation of `-[NSObject isKindOfClass:]`,
+/// but directly with the runtime so that it does not depend on Foundation.
+pub(crate) fn is_kind_of_class(obj: RawObjPtr, class: RawClassPtr) -> bool {
+    let mut current = unsafe { object_getClass(Some(obj)) };
+    while let Some(current_class) = current {
+        if current_class.ptr == class.ptr {
+            return true;
+        }
+        current = unsafe { class_getSuperclass(Some(current_class)) };
+    }
+    false
+}
+
+//-------------------------------------------------------------------
+// Weak references
+
+#[cfg_attr(not(feature = "mock-runtime"), link(name = "objc", kind = "dylib"))]
+extern "C" {
+    fn objc_initWeak(location: *mut Option<RawObjPtr>, obj: Option<RawObjPtr>)
+        -> Option<RawObjPtr>;
+    fn objc_storeWeak(
+        location: *mut Option<RawObjPtr>,
+        obj: Option<RawObjPtr>,
+    ) -> Option<RawObjPtr>;
+    fn objc_loadWeakRetained(location: *mut Option<RawObjPtr>) -> Option<RawObjPtr>;
+    fn objc_copyWeak(to: *mut Option<RawObjPtr>, from: *mut Option<RawObjPtr>);
+    fn objc_destroyWeak(location: *mut Option<RawObjPtr>);
+}
+
+/// Weak reference to an Objective-C object, that does not keep it alive.
+///
+/// Useful for delegates and observers, that would otherwise create retain cycles.
+/// Only Objective-C objects can be referenced weakly, not CoreFoundation ones.
+pub struct Weak<T: ObjCClass> {
+    // The runtime keeps track of the location of weak references, to set them to nil on deallocation,
+    // so it must not move. Not kept as a `Box`, as moving one would assert it is the only pointer to the location.
+    location: NonNull<UnsafeCell<Option<RawObjPtr>>>,
+    _marker: PhantomData<*const T>,
+}
+
+impl<T: ObjCClass> Weak<T> {
+    fn uninitialized() -> Self {
+        let location = Box::into_raw(Box::new(UnsafeCell::new(None)));
+        Self {
+            location: unsafe { NonNull::new_unchecked(location) },
+            _marker: PhantomData,
+        }
+    }
+
+    fn location(&self) -> *mut Option<RawObjPtr> {
+        UnsafeCell::raw_get(self.location.as_ptr())
+    }
+
+    pub fn new<O: Ownership>(obj: &Ptr<T, O>) -> Self {
+        let weak = Self::uninitialized();
+        unsafe { objc_initWeak(weak.location(), Some(obj.as_raw())) };
+        weak
+    }
+
+    /// Weak reference that does not reference anything, the same as one to an object that has been deallocated.
+    pub fn empty() -> Self {
+        let weak = Self::uninitialized();
+        unsafe { objc_initWeak(weak.location(), None) };
+        weak
+    }
+
+    /// Gets a strong reference to the object, or `None` if it has been deallocated.
+    pub fn upgrade(&self) -> Option<Ptr<T, Retained>> {
+        unsafe { objc_loadWeakRetained(self.location()).map(|raw| Ptr::from_raw_unchecked(raw)) }
+    }
+
+    /// Makes the weak reference point to another object (or none).
+    pub fn set<O: Ownership>(&mut self, obj: Option<&Ptr<T, O>>) {
+        unsafe { objc_storeWeak(self.location(), obj.map(|obj| obj.as_raw())) };
+    }
+}
+
+impl<T: ObjCClass> Clone for Weak<T> {
+    fn clone(&self) -> Self {
+        let weak = Self::uninitialized();
+        unsafe { objc_copyWeak(weak.location(), self.location()) };
+        weak
     }
 }
 
-pub fn autorelease_pool<Ret, F: FnOnce() -> Ret>(f: F) -> Ret {
-    let _pool = AutoreleasePoolGuard::push();
-    f()
+impl<T: ObjCClass> Drop for Weak<T> {
+    fn drop(&mut self) {
+        unsafe {
+            objc_destroyWeak(self.location());
+            drop(Box::from_raw(self.location.as_ptr()));
+        }
+    }
+}
+
+// The runtime synchronizes the accesses to weak references, so `Weak` can go to any thread the objects can go to.
+unsafe impl<T: ObjCClass> Send for Weak<T> where Ptr<T, Retained>: Send + Sync {}
+unsafe impl<T: ObjCClass> Sync for Weak<T> where Ptr<T, Retained>: Send + Sync {}
+
+#[cfg(all(test, feature = "mock-runtime"))]
+mod weak_tests {
+    use super::*;
+    use crate::base::mock_runtime::{
+        create_object, is_alive, register_class, retain_count, LeakCheck,
+    };
+    use crate::base::{RawClassPtr, Static, Type, TypeKind};
+
+    struct MockObjCType {}
+
+    impl Type for MockObjCType {
+        const KIND: TypeKind = TypeKind::ObjC;
+    }
+
+    impl ObjCClass for MockObjCType {
+        fn class() -> RawClassPtr {
+            register_class("MockObject")
+        }
+    }
+
+    // Like for immutable Foundation objects.
+    unsafe impl Send for Ptr<MockObjCType, Retained> {}
+    unsafe impl Sync for Ptr<MockObjCType, Retained> {}
+
+    fn new_object() -> Ptr<MockObjCType, Retained> {
+        unsafe { Ptr::from_raw_unchecked(create_object(MockObjCType::class())) }
+    }
+
+    #[test]
+    fn upgrade() {
+        let check = LeakCheck::start();
+        let obj = new_object();
+        let raw = obj.as_raw();
+        let weak = Weak::new(&obj);
+        // The weak reference does not keep the object alive.
+        assert_eq!(retain_count(raw), 1);
+        let upgraded = weak.upgrade().unwrap();
+        assert_eq!(upgraded.as_raw().ptr, raw.ptr);
+        assert_eq!(retain_count(raw), 2);
+        drop(upgraded);
+        drop(obj);
+        assert!(!is_alive(raw));
+        assert!(weak.upgrade().is_none());
+        drop(weak);
+        check.assert_balanced();
+    }
+
+    #[test]
+    fn clone_and_set() {
+        let check = LeakCheck::start();
+        let obj1 = new_object();
+        let obj2 = new_object();
+        let static_obj: Ptr<MockObjCType, Static> =
+            unsafe { Ptr::from_raw_unchecked(obj2.as_raw()) };
+
+        let mut weak = Weak::empty();
+        assert!(weak.upgrade().is_none());
+        weak.set(Some(&obj1));
+        let clone = weak.clone();
+        weak.set(Some(&static_obj));
+        assert_eq!(weak.upgrade().unwrap().as_raw().ptr, obj2.as_raw().ptr);
+        assert_eq!(clone.upgrade().unwrap().as_raw().ptr, obj1.as_raw().ptr);
+
+        // Only the references to the deallocated object are cleared.
+        drop(obj1);
+        assert!(clone.upgrade().is_none());
+        assert!(weak.upgrade().is_some());
+        weak.set::<Retained>(None);
+        assert!(weak.upgrade().is_none());
+        drop(obj2);
+        check.assert_balanced();
+    }
+
+    #[test]
+    fn other_threads() {
+        let obj = new_object();
+        let raw = obj.as_raw();
+        let weak = Weak::new(&obj);
+        let upgraded = std::thread::spawn(move || weak.upgrade().is_some())
+            .join()
+            .unwrap();
+        assert!(upgraded);
+
+        let weak = Weak::new(&obj);
+        let released = std::thread::spawn(move || drop(obj));
+        released.join().unwrap();
+        assert!(!is_alive(raw));
+        let upgraded = std::thread::spawn(move || weak.upgrade().is_some())
+            .join()
+            .unwrap();
+        assert!(!upgraded);
+    }
 }
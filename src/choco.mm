@@ -1,5 +1,7 @@
 #import <Foundation/Foundation.h>
+#ifdef __APPLE__
 #import <AVFoundation/AVFoundation.h>
+#endif
 #include <type_traits>
 #include <cstdint>
 
@@ -7,7 +9,12 @@
 #error This file must be compiled with ARC turned on (-fobjc-arc)
 #endif
 
+#ifdef __APPLE__
 static_assert(std::is_same<BOOL, signed char>::value && sizeof(BOOL) == 1, "expecting BOOL to be a signed char");
+#else
+// libobjc2 defines BOOL as an unsigned char, but it only ever holds YES (1) or NO (0) so it is fine for us.
+static_assert(sizeof(BOOL) == 1, "expecting BOOL to be a single byte");
+#endif
 static_assert(std::is_same<NSInteger, std::intptr_t>::value, "expecting NSInteger to be similar to isize");
 static_assert(std::is_same<NSUInteger, std::size_t>::value, "expecting NSInteger to be similar to usize");
 
@@ -21,7 +28,9 @@ static void abort_due_to_exception(__unsafe_unretained NSException *exception) {
 // We want the C++ features, but not its name mangling.
 extern "C" {
 
-// We should not need that anymore once we can use the "C unwind" ABI.
+// Used by default, as exceptions are in most cases programming errors that can't be recovered from.
+// Shims that let exceptions through instead have a _throwing suffix, are declared "C-unwind" on the Rust side,
+// and are used by the try_ methods, inside choco_Foundation_catchException.
 #define ABORT_ON_EXCEPTION(expr) \
     @try { \
         expr \
@@ -43,8 +52,11 @@ extern "C" {
 // Some explanation of the attributes used:
 // - All Objective-C pointer return values must be marked NS_RETURNS_RETAINED.
 //   That makes sure we always return an object with a +1 retain count.
+//   Getters can instead return autoreleased objects if the Rust side uses Ptr::from_autoreleased_return_value,
+//   which avoids the retain/release pair when the runtime's fast path is taken.
 // - All Objective-C pointer parameters must be marked __unsafe_unretained.
 //   That makes sure that if the method called needs to keep a reference it will always increment the retain count before.
+// - ARC is not exception safe by default, so a shim that can throw should not have any strong local variable.
 // 
 // We end up with something similar to how Swift handles Objective-C pointers without having to think too much.
 
@@ -221,6 +233,10 @@ NS_RETURNS_RETAINED id choco_Foundation_NSArrayInterface_instance_objectAtIndex(
     )
 }
 
+NS_RETURNS_RETAINED id choco_Foundation_NSArrayInterface_instance_objectAtIndex_throwing(__unsafe_unretained NSArray *self_, NSUInteger index) {
+    return [self_ objectAtIndex:index];
+}
+
 NS_RETURNS_RETAINED NSArray *choco_Foundation_NSArrayInterface_instance_arrayByAddingObject(__unsafe_unretained NSArray *self_, __unsafe_unretained id object) {
     ABORT_ON_EXCEPTION(
         return [self_ arrayByAddingObject:object];
@@ -266,6 +282,10 @@ void choco_Foundation_NSMutableDictionaryInterface_instance_setObject_forKey(__u
     )
 }
 
+void choco_Foundation_NSMutableDictionaryInterface_instance_setObject_forKey_throwing(__unsafe_unretained NSMutableDictionary *self_, __unsafe_unretained id object, __unsafe_unretained id key) {
+    [self_ setObject:object forKey:key];
+}
+
 void choco_Foundation_NSMutableDictionaryInterface_instance_removeObjectForKey(__unsafe_unretained NSMutableDictionary *self_, __unsafe_unretained id key) {
     ABORT_ON_EXCEPTION(
         return [self_ removeObjectForKey:key];
@@ -366,12 +386,56 @@ NSInteger choco_Foundation_NSErrorInterface_instance_code(__unsafe_unretained NS
     )
 }
 
-NS_RETURNS_RETAINED NSString *choco_Foundation_NSErrorInterface_instance_domain(__unsafe_unretained NSError *self_) {
+NSString *choco_Foundation_NSErrorInterface_instance_domain(__unsafe_unretained NSError *self_) {
     ABORT_ON_EXCEPTION(
         return self_.domain;
     )
 }
 
+NSString *choco_Foundation_NSErrorInterface_instance_localizedDescription(__unsafe_unretained NSError *self_) {
+    ABORT_ON_EXCEPTION(
+        return self_.localizedDescription;
+    )
+}
+
+//-------------------------------------------------------------------
+// NSException
+
+CLASS_FUNCTION_DEFINITION(Foundation, NSException)
+
+NS_RETURNS_RETAINED NSString *choco_Foundation_NSExceptionInterface_instance_name(__unsafe_unretained NSException *self_) {
+    ABORT_ON_EXCEPTION(
+        return self_.name;
+    )
+}
+
+NS_RETURNS_RETAINED NSString *choco_Foundation_NSExceptionInterface_instance_reason(__unsafe_unretained NSException *self_) {
+    ABORT_ON_EXCEPTION(
+        return self_.reason;
+    )
+}
+
+NS_RETURNS_RETAINED NSDictionary *choco_Foundation_NSExceptionInterface_instance_userInfo(__unsafe_unretained NSException *self_) {
+    ABORT_ON_EXCEPTION(
+        return self_.userInfo;
+    )
+}
+
+// Calls f(context), returning the NSException it raised if any.
+// Exceptions that are not NSExceptions, or Rust panics, just go through.
+NS_RETURNS_RETAINED NSException *choco_Foundation_catchException(void (*f)(void *), void *context) {
+    @try {
+        f(context);
+        return nil;
+    }
+    @catch (NSException *exception) {
+        return exception;
+    }
+}
+
+// AVFoundation is only available on Apple platforms.
+#ifdef __APPLE__
+
 //-------------------------------------------------------------------
 // AVAsynchronousKeyValueLoading
 
@@ -563,4 +627,6 @@ float choco_AVFoundation_AVAudioPlayerInterface_instance_rate(__unsafe_unretaine
     )
 }
 
+#endif // __APPLE__
+
 } // extern "C"
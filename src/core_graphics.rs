@@ -1,40 +1,417 @@
+use std::ops::{Add, Mul, Neg, Sub};
+
 #[cfg(target_pointer_width = "64")]
 pub type CGFloat = f64;
+#[cfg(target_pointer_width = "32")]
+pub type CGFloat = f32;
 
-#[derive(Copy, Clone, Default, PartialEq)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
 #[repr(C)]
 pub struct CGPoint {
     pub x: CGFloat,
     pub y: CGFloat,
 }
 
+crate::encode_struct!(CGPoint = "CGPoint" { x: CGFloat, y: CGFloat });
+
 impl CGPoint {
+    pub const ZERO: Self = Self { x: 0., y: 0. };
+
     pub fn new(x: CGFloat, y: CGFloat) -> Self {
         Self { x, y }
     }
+
+    /// Whether both coordinates are at most `tolerance` away from the ones of `other`.
+    pub fn approx_eq(self, other: Self, tolerance: CGFloat) -> bool {
+        (self.x - other.x).abs() <= tolerance && (self.y - other.y).abs() <= tolerance
+    }
+}
+
+impl Add<CGVector> for CGPoint {
+    type Output = Self;
+
+    fn add(self, rhs: CGVector) -> Self {
+        Self::new(self.x + rhs.dx, self.y + rhs.dy)
+    }
+}
+
+impl Sub<CGVector> for CGPoint {
+    type Output = Self;
+
+    fn sub(self, rhs: CGVector) -> Self {
+        Self::new(self.x - rhs.dx, self.y - rhs.dy)
+    }
+}
+
+/// Vector going from `rhs` to `self`.
+impl Sub for CGPoint {
+    type Output = CGVector;
+
+    fn sub(self, rhs: Self) -> CGVector {
+        CGVector::new(self.x - rhs.x, self.y - rhs.y)
+    }
 }
 
-#[derive(Copy, Clone, Default, PartialEq)]
+impl From<(CGFloat, CGFloat)> for CGPoint {
+    fn from((x, y): (CGFloat, CGFloat)) -> Self {
+        Self::new(x, y)
+    }
+}
+
+impl From<CGPoint> for (CGFloat, CGFloat) {
+    fn from(point: CGPoint) -> Self {
+        (point.x, point.y)
+    }
+}
+
+/// Same format as `NSStringFromCGPoint`, for example `{1, 2.5}`.
+impl std::fmt::Display for CGPoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{{{}, {}}}", self.x, self.y)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
 #[repr(C)]
 pub struct CGSize {
     pub width: CGFloat,
     pub height: CGFloat,
 }
 
+crate::encode_struct!(CGSize = "CGSize" { width: CGFloat, height: CGFloat });
+
 impl CGSize {
+    pub const ZERO: Self = Self {
+        width: 0.,
+        height: 0.,
+    };
+
     pub fn new(width: CGFloat, height: CGFloat) -> Self {
         Self { width, height }
     }
+
+    /// Whether the width and height are at most `tolerance` away from the ones of `other`.
+    pub fn approx_eq(self, other: Self, tolerance: CGFloat) -> bool {
+        (self.width - other.width).abs() <= tolerance
+            && (self.height - other.height).abs() <= tolerance
+    }
+
+    /// Largest rectangle with the aspect ratio of the size fitting in `bounding_rect`, centered in it
+    /// (same as `AVMakeRectWithAspectRatioInsideRect`).
+    pub fn aspect_fit(self, bounding_rect: CGRect) -> CGRect {
+        self.scaled_to(bounding_rect, CGFloat::min)
+    }
+
+    /// Smallest rectangle with the aspect ratio of the size covering `bounding_rect`, centered on it.
+    pub fn aspect_fill(self, bounding_rect: CGRect) -> CGRect {
+        self.scaled_to(bounding_rect, CGFloat::max)
+    }
+
+    /// Size scaled by the factor picked by `choose_scale` between the horizontal and vertical scales
+    /// needed to match the rectangle, and centered on it. Empty sizes give an empty rectangle at the center.
+    fn scaled_to(
+        self,
+        bounding_rect: CGRect,
+        choose_scale: fn(CGFloat, CGFloat) -> CGFloat,
+    ) -> CGRect {
+        let (width, height) = (self.width.abs(), self.height.abs());
+        let (bounding_width, bounding_height) = (bounding_rect.width(), bounding_rect.height());
+        if width == 0. || height == 0. {
+            return CGRect::new(bounding_rect.mid_x(), bounding_rect.mid_y(), 0., 0.);
+        }
+        let horizontal_scale = bounding_width / width;
+        let vertical_scale = bounding_height / height;
+        // Use the dimension of the rectangle as is, to not get any rounding error on it.
+        let size = if choose_scale(horizontal_scale, vertical_scale) == horizontal_scale {
+            Self::new(bounding_width, height * bounding_width / width)
+        } else {
+            Self::new(width * bounding_height / height, bounding_height)
+        };
+        CGRect::new(
+            bounding_rect.min_x() + (bounding_width - size.width) / 2.,
+            bounding_rect.min_y() + (bounding_height - size.height) / 2.,
+            size.width,
+            size.height,
+        )
+    }
+}
+
+impl Add for CGSize {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.width + rhs.width, self.height + rhs.height)
+    }
+}
+
+impl Sub for CGSize {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.width - rhs.width, self.height - rhs.height)
+    }
+}
+
+impl Mul<CGFloat> for CGSize {
+    type Output = Self;
+
+    fn mul(self, rhs: CGFloat) -> Self {
+        Self::new(self.width * rhs, self.height * rhs)
+    }
+}
+
+impl From<(CGFloat, CGFloat)> for CGSize {
+    fn from((width, height): (CGFloat, CGFloat)) -> Self {
+        Self::new(width, height)
+    }
+}
+
+impl From<CGSize> for (CGFloat, CGFloat) {
+    fn from(size: CGSize) -> Self {
+        (size.width, size.height)
+    }
+}
+
+/// Same format as `NSStringFromCGSize`, for example `{1920, 1080}`.
+impl std::fmt::Display for CGSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{{{}, {}}}", self.width, self.height)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[repr(C)]
+pub struct CGVector {
+    pub dx: CGFloat,
+    pub dy: CGFloat,
+}
+
+crate::encode_struct!(CGVector = "CGVector" { dx: CGFloat, dy: CGFloat });
+
+impl CGVector {
+    pub const ZERO: Self = Self { dx: 0., dy: 0. };
+
+    pub fn new(dx: CGFloat, dy: CGFloat) -> Self {
+        Self { dx, dy }
+    }
+
+    /// Whether both components are at most `tolerance` away from the ones of `other`.
+    pub fn approx_eq(self, other: Self, tolerance: CGFloat) -> bool {
+        (self.dx - other.dx).abs() <= tolerance && (self.dy - other.dy).abs() <= tolerance
+    }
 }
 
-#[derive(Copy, Clone, Default, PartialEq)]
+impl Add for CGVector {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.dx + rhs.dx, self.dy + rhs.dy)
+    }
+}
+
+impl Sub for CGVector {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.dx - rhs.dx, self.dy - rhs.dy)
+    }
+}
+
+impl Mul<CGFloat> for CGVector {
+    type Output = Self;
+
+    fn mul(self, rhs: CGFloat) -> Self {
+        Self::new(self.dx * rhs, self.dy * rhs)
+    }
+}
+
+impl Neg for CGVector {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::new(-self.dx, -self.dy)
+    }
+}
+
+impl From<(CGFloat, CGFloat)> for CGVector {
+    fn from((dx, dy): (CGFloat, CGFloat)) -> Self {
+        Self::new(dx, dy)
+    }
+}
+
+impl From<CGVector> for (CGFloat, CGFloat) {
+    fn from(vector: CGVector) -> Self {
+        (vector.dx, vector.dy)
+    }
+}
+
+/// Same format as `NSStringFromCGVector`, for example `{0, -1}`.
+impl std::fmt::Display for CGVector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{{{}, {}}}", self.dx, self.dy)
+    }
+}
+
+#[cfg(test)]
+mod cgpoint_cgsize_tests {
+    use super::*;
+
+    #[test]
+    fn operators() {
+        let point = CGPoint::new(1., 2.);
+        let vector = CGVector::new(0.5, -1.);
+        assert_eq!(point + vector, CGPoint::new(1.5, 1.));
+        assert_eq!(point - vector, CGPoint::new(0.5, 3.));
+        assert_eq!((point + vector) - point, vector);
+        assert_eq!(vector + vector, vector * 2.);
+        assert_eq!(vector - vector, CGVector::ZERO);
+        assert_eq!(-vector, CGVector::new(-0.5, 1.));
+
+        let size = CGSize::new(3., 4.);
+        assert_eq!(size + size, size * 2.);
+        assert_eq!(size - CGSize::new(1., 1.), CGSize::new(2., 3.));
+    }
+
+    #[test]
+    fn encoding() {
+        use crate::base::Encode;
+        let float = CGFloat::encoding();
+        assert_eq!(
+            CGRect::encoding().to_string(),
+            format!("{{CGRect={{CGPoint={0}{0}}}{{CGSize={0}{0}}}}}", float)
+        );
+        assert_eq!(
+            CGVector::encoding().to_string(),
+            format!("{{CGVector={0}{0}}}", float)
+        );
+        assert_eq!(
+            CGAffineTransform::encoding().to_string(),
+            format!("{{CGAffineTransform={0}{0}{0}{0}{0}{0}}}", float)
+        );
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(CGPoint::from((1., 2.)), CGPoint::new(1., 2.));
+        assert_eq!(<(CGFloat, CGFloat)>::from(CGPoint::new(1., 2.)), (1., 2.));
+        assert_eq!(CGSize::from((3., 4.)), CGSize::new(3., 4.));
+        assert_eq!(<(CGFloat, CGFloat)>::from(CGSize::new(3., 4.)), (3., 4.));
+        assert_eq!(CGVector::from((5., 6.)), CGVector::new(5., 6.));
+        assert_eq!(<(CGFloat, CGFloat)>::from(CGVector::new(5., 6.)), (5., 6.));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(CGPoint::new(1., 2.5).to_string(), "{1, 2.5}");
+        assert_eq!(CGSize::new(1920., -1080.).to_string(), "{1920, -1080}");
+        assert_eq!(CGVector::new(0., -1.).to_string(), "{0, -1}");
+        assert_eq!(
+            CGRect::new(0., 0.5, 10., 20.).to_string(),
+            "{{0, 0.5}, {10, 20}}"
+        );
+    }
+
+    #[test]
+    fn approx_eq() {
+        let point = CGPoint::new(0.1 + 0.2, 1.);
+        assert_ne!(point, CGPoint::new(0.3, 1.));
+        assert!(point.approx_eq(CGPoint::new(0.3, 1.), 1e-6));
+        assert!(!point.approx_eq(CGPoint::new(0.3, 1.1), 1e-6));
+        assert!(CGSize::new(1., 2.).approx_eq(CGSize::new(1.05, 1.95), 0.1));
+        assert!(!CGSize::new(1., 2.).approx_eq(CGSize::new(1., 2.2), 0.1));
+        assert!(CGVector::new(1., 2.).approx_eq(CGVector::new(1., 2.), 0.));
+        assert!(!CGVector::new(1., 2.).approx_eq(CGVector::new(-1., 2.), 1.));
+        let rect = CGRect::new(0., 0., 10., 10.);
+        assert!(rect.approx_eq(CGRect::new(0.01, 0., 10., 9.99), 0.1));
+        assert!(!rect.approx_eq(CGRect::new(0., 0., 10., -10.), 0.1));
+    }
+
+    #[test]
+    fn aspect_fit_and_fill() {
+        let bounds = CGRect::new(0., 0., 1000., 1000.);
+        let landscape = CGSize::new(1920., 1080.);
+        assert_eq!(
+            landscape.aspect_fit(bounds),
+            CGRect::new(0., 218.75, 1000., 562.5)
+        );
+        assert_eq!(
+            landscape.aspect_fill(CGRect::new(0., 0., 900., 900.)),
+            CGRect::new(-350., 0., 1600., 900.)
+        );
+        let portrait = CGSize::new(9., 16.);
+        assert_eq!(
+            portrait.aspect_fit(CGRect::new(10., 10., 160., 90.)),
+            CGRect::new(64.6875, 10., 50.625, 90.)
+        );
+        // Same aspect ratio.
+        assert_eq!(
+            CGSize::new(16., 9.).aspect_fit(CGRect::new(-10., 5., 160., 90.)),
+            CGRect::new(-10., 5., 160., 90.)
+        );
+        // Negative sizes are not a problem.
+        assert_eq!(
+            CGSize::new(-2., 1.).aspect_fit(CGRect::new(10., 10., -4., 4.)),
+            CGRect::new(6., 11., 4., 2.)
+        );
+        assert_eq!(
+            CGSize::ZERO.aspect_fit(bounds),
+            CGRect::new(500., 500., 0., 0.)
+        );
+        assert_eq!(
+            CGSize::new(0., 1.).aspect_fill(bounds),
+            CGRect::new(500., 500., 0., 0.)
+        );
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
 #[repr(C)]
 pub struct CGRect {
     pub origin: CGPoint,
     pub size: CGSize,
 }
 
+crate::encode_struct!(CGRect = "CGRect" { origin: CGPoint, size: CGSize });
+
+/// Edge of a rectangle, used by `CGRect::divide`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct CGRectEdge(pub u32);
+
+impl CGRectEdge {
+    pub const MIN_X_EDGE: Self = Self(0);
+    pub const MIN_Y_EDGE: Self = Self(1);
+    pub const MAX_X_EDGE: Self = Self(2);
+    pub const MAX_Y_EDGE: Self = Self(3);
+}
+
 impl CGRect {
+    pub const ZERO: Self = Self {
+        origin: CGPoint::ZERO,
+        size: CGSize::ZERO,
+    };
+
+    /// Same as `CGRectNull`, returned for example by the intersection of two disjoint rectangles.
+    pub const NULL: Self = Self {
+        origin: CGPoint {
+            x: CGFloat::INFINITY,
+            y: CGFloat::INFINITY,
+        },
+        size: CGSize::ZERO,
+    };
+
+    /// Same as `CGRectInfinite`.
+    pub const INFINITE: Self = Self {
+        origin: CGPoint {
+            x: -CGFloat::MAX / 2.,
+            y: -CGFloat::MAX / 2.,
+        },
+        size: CGSize {
+            width: CGFloat::MAX,
+            height: CGFloat::MAX,
+        },
+    };
+
     pub fn new(x: CGFloat, y: CGFloat, width: CGFloat, height: CGFloat) -> Self {
         Self {
             origin: CGPoint::new(x, y),
@@ -75,15 +452,774 @@ impl CGRect {
     pub fn height(self) -> CGFloat {
         self.size.height.abs()
     }
+
+    /// Same rectangle with a positive width and height.
+    pub fn standardized(self) -> Self {
+        Self::new(self.min_x(), self.min_y(), self.width(), self.height())
+    }
+
+    pub fn is_null(self) -> bool {
+        self.origin.x == CGFloat::INFINITY || self.origin.y == CGFloat::INFINITY
+    }
+
+    /// Whether the rectangle is null or has no area.
+    pub fn is_empty(self) -> bool {
+        self.is_null() || self.size.width == 0. || self.size.height == 0.
+    }
+
+    pub fn is_infinite(self) -> bool {
+        self == Self::INFINITE
+    }
+
+    /// Smallest rectangle containing both rectangles, a null rectangle being ignored.
+    pub fn union(self, other: Self) -> Self {
+        if self.is_null() {
+            return other.standardized();
+        }
+        if other.is_null() {
+            return self.standardized();
+        }
+        if self.is_infinite() || other.is_infinite() {
+            return Self::INFINITE;
+        }
+        let min_x = self.min_x().min(other.min_x());
+        let min_y = self.min_y().min(other.min_y());
+        let max_x = self.max_x().max(other.max_x());
+        let max_y = self.max_y().max(other.max_y());
+        Self::new(min_x, min_y, max_x - min_x, max_y - min_y)
+    }
+
+    /// Part common to both rectangles, null if they do not overlap.
+    ///
+    /// Rectangles only sharing an edge have an empty (but not null) intersection.
+    pub fn intersection(self, other: Self) -> Self {
+        if self.is_null() || other.is_null() {
+            return Self::NULL;
+        }
+        if self.is_infinite() {
+            return other.standardized();
+        }
+        if other.is_infinite() {
+            return self.standardized();
+        }
+        let min_x = self.min_x().max(other.min_x());
+        let min_y = self.min_y().max(other.min_y());
+        let max_x = self.max_x().min(other.max_x());
+        let max_y = self.max_y().min(other.max_y());
+        if max_x < min_x || max_y < min_y {
+            return Self::NULL;
+        }
+        Self::new(min_x, min_y, max_x - min_x, max_y - min_y)
+    }
+
+    /// Standardized rectangle moved inwards by `dx` on the left and right, and by `dy` on the top and bottom
+    /// (outwards for negative values). Null if nothing is left.
+    pub fn inset_by(self, dx: CGFloat, dy: CGFloat) -> Self {
+        if self.is_null() || self.is_infinite() {
+            return self;
+        }
+        let width = self.width() - 2. * dx;
+        let height = self.height() - 2. * dy;
+        if width < 0. || height < 0. {
+            return Self::NULL;
+        }
+        Self::new(self.min_x() + dx, self.min_y() + dy, width, height)
+    }
+
+    /// Rectangle moved by `(dx, dy)`, null and infinite rectangles staying the same.
+    pub fn offset_by(self, dx: CGFloat, dy: CGFloat) -> Self {
+        if self.is_null() || self.is_infinite() {
+            return self;
+        }
+        Self {
+            origin: CGPoint::new(self.origin.x + dx, self.origin.y + dy),
+            size: self.size,
+        }
+    }
+
+    /// Smallest rectangle with integral coordinates containing the rectangle.
+    pub fn integral(self) -> Self {
+        if self.is_null() || self.is_infinite() {
+            return self;
+        }
+        let min_x = self.min_x().floor();
+        let min_y = self.min_y().floor();
+        let max_x = self.max_x().ceil();
+        let max_y = self.max_y().ceil();
+        Self::new(min_x, min_y, max_x - min_x, max_y - min_y)
+    }
+
+    /// Splits the standardized rectangle in two: a slice of `amount` (limited to the rectangle) from `edge`,
+    /// and the remainder. Both are null for a null rectangle.
+    pub fn divide(self, amount: CGFloat, edge: CGRectEdge) -> (Self, Self) {
+        if self.is_null() {
+            return (Self::NULL, Self::NULL);
+        }
+        let rect = self.standardized();
+        let (min_x, min_y, width, height) = (
+            rect.origin.x,
+            rect.origin.y,
+            rect.size.width,
+            rect.size.height,
+        );
+        match edge {
+            CGRectEdge::MIN_X_EDGE | CGRectEdge::MAX_X_EDGE => {
+                let amount = amount.max(0.).min(width);
+                let (slice_x, remainder_x) = if edge == CGRectEdge::MIN_X_EDGE {
+                    (min_x, min_x + amount)
+                } else {
+                    (min_x + width - amount, min_x)
+                };
+                (
+                    Self::new(slice_x, min_y, amount, height),
+                    Self::new(remainder_x, min_y, width - amount, height),
+                )
+            }
+            CGRectEdge::MIN_Y_EDGE | CGRectEdge::MAX_Y_EDGE => {
+                let amount = amount.max(0.).min(height);
+                let (slice_y, remainder_y) = if edge == CGRectEdge::MIN_Y_EDGE {
+                    (min_y, min_y + amount)
+                } else {
+                    (min_y + height - amount, min_y)
+                };
+                (
+                    Self::new(min_x, slice_y, width, amount),
+                    Self::new(min_x, remainder_y, width, height - amount),
+                )
+            }
+            _ => (rect, Self::NULL),
+        }
+    }
+
+    /// Whether the point is in the rectangle, points on the maximum edges not being included.
+    pub fn contains_point(self, point: CGPoint) -> bool {
+        !self.is_null()
+            && self.min_x() <= point.x
+            && point.x < self.max_x()
+            && self.min_y() <= point.y
+            && point.y < self.max_y()
+    }
+
+    /// Whether `other` is completely inside the rectangle (the edges being included).
+    pub fn contains_rect(self, other: Self) -> bool {
+        if self.is_null() || other.is_null() {
+            return false;
+        }
+        self.is_infinite()
+            || (self.min_x() <= other.min_x()
+                && other.max_x() <= self.max_x()
+                && self.min_y() <= other.min_y()
+                && other.max_y() <= self.max_y())
+    }
+
+    /// Whether the intersection of the rectangles is not null.
+    pub fn intersects(self, other: Self) -> bool {
+        !self.intersection(other).is_null()
+    }
+
+    /// Whether the origin and size are at most `tolerance` away from the ones of `other`.
+    pub fn approx_eq(self, other: Self, tolerance: CGFloat) -> bool {
+        self.origin.approx_eq(other.origin, tolerance) && self.size.approx_eq(other.size, tolerance)
+    }
 }
 
-#[derive(Copy, Clone, Default, PartialEq)]
+impl From<(CGPoint, CGSize)> for CGRect {
+    fn from((origin, size): (CGPoint, CGSize)) -> Self {
+        Self { origin, size }
+    }
+}
+
+/// Same format as `NSStringFromCGRect`, for example `{{0, 0}, {1920, 1080}}`.
+impl std::fmt::Display for CGRect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{{{}, {}}}", self.origin, self.size)
+    }
+}
+
+#[cfg(test)]
+mod cgrect_tests {
+    use super::*;
+
+    const NULL: CGRect = CGRect::NULL;
+    const INFINITE: CGRect = CGRect::INFINITE;
+
+    fn rect(x: CGFloat, y: CGFloat, width: CGFloat, height: CGFloat) -> CGRect {
+        CGRect::new(x, y, width, height)
+    }
+
+    /// Compares the bits of the values, so that 0. and -0. are different.
+    fn assert_identical(left: CGRect, right: CGRect, case: &str) {
+        let bits =
+            |r: CGRect| [r.origin.x, r.origin.y, r.size.width, r.size.height].map(|v| v.to_bits());
+        assert_eq!(
+            bits(left),
+            bits(right),
+            "{}: {:?} != {:?}",
+            case,
+            left,
+            right
+        );
+    }
+
+    #[test]
+    fn predicates() {
+        assert!(NULL.is_null());
+        assert!(rect(CGFloat::INFINITY, 0., 1., 1.).is_null());
+        assert!(!CGRect::ZERO.is_null());
+        assert!(NULL.is_empty());
+        assert!(CGRect::ZERO.is_empty());
+        assert!(rect(1., 1., 0., 3.).is_empty());
+        assert!(!rect(1., 1., -2., 3.).is_empty());
+        assert!(INFINITE.is_infinite());
+        assert!(!INFINITE.is_empty());
+        assert!(!rect(0., 0., CGFloat::INFINITY, CGFloat::INFINITY).is_infinite());
+    }
+
+    #[test]
+    fn standardized() {
+        let cases = [
+            (rect(1., 2., 3., 4.), rect(1., 2., 3., 4.)),
+            (rect(1., 2., -3., -4.), rect(-2., -2., 3., 4.)),
+            (rect(1., 2., -3., 4.), rect(-2., 2., 3., 4.)),
+            (NULL, NULL),
+            (INFINITE, INFINITE),
+        ];
+        for (input, expected) in cases {
+            assert_identical(input.standardized(), expected, &format!("{:?}", input));
+        }
+    }
+
+    #[test]
+    fn union() {
+        let cases = [
+            (
+                rect(0., 0., 1., 1.),
+                rect(2., 3., 1., 1.),
+                rect(0., 0., 3., 4.),
+            ),
+            (
+                rect(0., 0., -1., -1.),
+                rect(1., 1., 1., 1.),
+                rect(-1., -1., 3., 3.),
+            ),
+            (
+                rect(5., 5., 0., 0.),
+                rect(0., 0., 1., 1.),
+                rect(0., 0., 5., 5.),
+            ),
+            (NULL, rect(1., 2., -3., 4.), rect(-2., 2., 3., 4.)),
+            (rect(1., 2., 3., 4.), NULL, rect(1., 2., 3., 4.)),
+            (NULL, NULL, NULL),
+            (INFINITE, rect(1., 2., 3., 4.), INFINITE),
+            (rect(CGFloat::MAX, 0., 1., 1.), INFINITE, INFINITE),
+        ];
+        for (r1, r2, expected) in cases {
+            assert_identical(r1.union(r2), expected, &format!("{:?} | {:?}", r1, r2));
+        }
+    }
+
+    #[test]
+    fn intersection() {
+        let cases = [
+            (
+                rect(0., 0., 2., 2.),
+                rect(1., 1., 2., 2.),
+                rect(1., 1., 1., 1.),
+            ),
+            (
+                rect(2., 2., -2., -2.),
+                rect(1., 1., 2., 2.),
+                rect(1., 1., 1., 1.),
+            ),
+            (rect(0., 0., 1., 1.), rect(2., 2., 1., 1.), NULL),
+            // Shared edge.
+            (
+                rect(0., 0., 1., 1.),
+                rect(1., 0., 1., 1.),
+                rect(1., 0., 0., 1.),
+            ),
+            (
+                rect(0., 0., 4., 4.),
+                rect(1., 1., 0., 0.),
+                rect(1., 1., 0., 0.),
+            ),
+            (NULL, rect(0., 0., 1., 1.), NULL),
+            (INFINITE, NULL, NULL),
+            (INFINITE, rect(1., 2., -3., 4.), rect(-2., 2., 3., 4.)),
+            (INFINITE, INFINITE, INFINITE),
+        ];
+        for (r1, r2, expected) in cases {
+            assert_identical(
+                r1.intersection(r2),
+                expected,
+                &format!("{:?} & {:?}", r1, r2),
+            );
+            assert_identical(
+                r2.intersection(r1),
+                expected,
+                &format!("{:?} & {:?}", r2, r1),
+            );
+            assert_eq!(r1.intersects(r2), !expected.is_null());
+        }
+    }
+
+    #[test]
+    fn inset_and_offset() {
+        let cases = [
+            (rect(0., 0., 10., 10.), 1., 2., rect(1., 2., 8., 6.)),
+            (rect(10., 10., -10., -10.), 1., 2., rect(1., 2., 8., 6.)),
+            (rect(0., 0., 10., 10.), -1., -1., rect(-1., -1., 12., 12.)),
+            (rect(0., 0., 10., 10.), 5., 5., rect(5., 5., 0., 0.)),
+            (rect(0., 0., 10., 10.), 6., 0., NULL),
+            (NULL, 1., 1., NULL),
+            (INFINITE, 1., 1., INFINITE),
+        ];
+        for (input, dx, dy, expected) in cases {
+            assert_identical(input.inset_by(dx, dy), expected, &format!("{:?}", input));
+        }
+
+        assert_identical(
+            rect(1., 2., -3., 4.).offset_by(1., -1.),
+            rect(2., 1., -3., 4.),
+            "offset",
+        );
+        assert_identical(NULL.offset_by(1., 1.), NULL, "offset null");
+        assert_identical(INFINITE.offset_by(1., 1.), INFINITE, "offset infinite");
+    }
+
+    #[test]
+    fn integral() {
+        let cases = [
+            (rect(0.5, 0.5, 1., 1.), rect(0., 0., 2., 2.)),
+            (rect(-0.5, -1.5, 1., 1.), rect(-1., -2., 2., 2.)),
+            (rect(1.5, 1.5, -1., -1.), rect(0., 0., 2., 2.)),
+            (rect(1., 2., 3., 4.), rect(1., 2., 3., 4.)),
+            (rect(0.2, 0.2, 0., 0.), rect(0., 0., 1., 1.)),
+            (NULL, NULL),
+            (INFINITE, INFINITE),
+        ];
+        for (input, expected) in cases {
+            assert_identical(input.integral(), expected, &format!("{:?}", input));
+        }
+    }
+
+    #[test]
+    fn divide() {
+        let r = rect(0., 0., 10., 20.);
+        let cases = [
+            (
+                r,
+                3.,
+                CGRectEdge::MIN_X_EDGE,
+                rect(0., 0., 3., 20.),
+                rect(3., 0., 7., 20.),
+            ),
+            (
+                r,
+                3.,
+                CGRectEdge::MAX_X_EDGE,
+                rect(7., 0., 3., 20.),
+                rect(0., 0., 7., 20.),
+            ),
+            (
+                r,
+                3.,
+                CGRectEdge::MIN_Y_EDGE,
+                rect(0., 0., 10., 3.),
+                rect(0., 3., 10., 17.),
+            ),
+            (
+                r,
+                3.,
+                CGRectEdge::MAX_Y_EDGE,
+                rect(0., 17., 10., 3.),
+                rect(0., 0., 10., 17.),
+            ),
+            (
+                r,
+                15.,
+                CGRectEdge::MIN_X_EDGE,
+                rect(0., 0., 10., 20.),
+                rect(10., 0., 0., 20.),
+            ),
+            (
+                r,
+                -1.,
+                CGRectEdge::MIN_X_EDGE,
+                rect(0., 0., 0., 20.),
+                rect(0., 0., 10., 20.),
+            ),
+            (
+                rect(10., 20., -10., -20.),
+                3.,
+                CGRectEdge::MIN_X_EDGE,
+                rect(0., 0., 3., 20.),
+                rect(3., 0., 7., 20.),
+            ),
+            (NULL, 1., CGRectEdge::MIN_X_EDGE, NULL, NULL),
+        ];
+        for (input, amount, edge, slice, remainder) in cases {
+            let case = format!("{:?} {} {:?}", input, amount, edge);
+            let (s, r) = input.divide(amount, edge);
+            assert_identical(s, slice, &case);
+            assert_identical(r, remainder, &case);
+        }
+    }
+
+    #[test]
+    fn containment() {
+        let r = rect(10., 10., -10., -10.);
+        assert!(r.contains_point(CGPoint::new(0., 0.)));
+        assert!(r.contains_point(CGPoint::new(9.5, 5.)));
+        assert!(!r.contains_point(CGPoint::new(10., 5.)));
+        assert!(!r.contains_point(CGPoint::new(5., 10.)));
+        assert!(!r.contains_point(CGPoint::new(-0.1, 5.)));
+        assert!(!NULL.contains_point(CGPoint::new(CGFloat::INFINITY, CGFloat::INFINITY)));
+        assert!(INFINITE.contains_point(CGPoint::new(CGFloat::MAX / 4., -CGFloat::MAX / 4.)));
+
+        assert!(r.contains_rect(r));
+        assert!(r.contains_rect(rect(0., 0., 10., 10.)));
+        assert!(r.contains_rect(rect(2., 2., 0., 0.)));
+        assert!(!r.contains_rect(rect(2., 2., 9., 1.)));
+        assert!(!r.contains_rect(NULL));
+        assert!(!NULL.contains_rect(r));
+        assert!(INFINITE.contains_rect(rect(CGFloat::MAX, 0., 1., 1.)));
+        assert!(!r.contains_rect(INFINITE));
+    }
+}
+
+/// Affine transform, mapping `(x, y)` to `(a * x + c * y + tx, b * x + d * y + ty)`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
 #[repr(C)]
 pub struct CGAffineTransform {
+    pub a: CGFloat,
+    pub b: CGFloat,
+    pub c: CGFloat,
+    pub d: CGFloat,
+    pub tx: CGFloat,
+    pub ty: CGFloat,
+}
+
+crate::encode_struct!(CGAffineTransform = "CGAffineTransform" {
     a: CGFloat,
     b: CGFloat,
     c: CGFloat,
     d: CGFloat,
     tx: CGFloat,
     ty: CGFloat,
+});
+
+/// Result of `CGAffineTransform::decompose`, same as `CGAffineTransformComponents`.
+///
+/// The transform is the scale, followed by the horizontal shear, the rotation and finally the translation.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[repr(C)]
+pub struct CGAffineTransformComponents {
+    pub scale: CGSize,
+    pub horizontal_shear: CGFloat,
+    /// Rotation in radians, in `[-π, π]`.
+    pub rotation: CGFloat,
+    pub translation: CGVector,
+}
+
+impl CGAffineTransform {
+    pub const IDENTITY: Self = Self {
+        a: 1.,
+        b: 0.,
+        c: 0.,
+        d: 1.,
+        tx: 0.,
+        ty: 0.,
+    };
+
+    pub fn new(a: CGFloat, b: CGFloat, c: CGFloat, d: CGFloat, tx: CGFloat, ty: CGFloat) -> Self {
+        Self { a, b, c, d, tx, ty }
+    }
+
+    pub fn translation(tx: CGFloat, ty: CGFloat) -> Self {
+        Self::new(1., 0., 0., 1., tx, ty)
+    }
+
+    pub fn scale(sx: CGFloat, sy: CGFloat) -> Self {
+        Self::new(sx, 0., 0., sy, 0., 0.)
+    }
+
+    /// Rotation of `angle` radians (counterclockwise in a coordinate system where y goes up).
+    pub fn rotation(angle: CGFloat) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        Self::new(cos, sin, -sin, cos, 0., 0.)
+    }
+
+    /// Transform applying `self` then `other` (same as `CGAffineTransformConcat(self, other)`).
+    pub fn concat(self, other: Self) -> Self {
+        Self {
+            a: self.a * other.a + self.b * other.c,
+            b: self.a * other.b + self.b * other.d,
+            c: self.c * other.a + self.d * other.c,
+            d: self.c * other.b + self.d * other.d,
+            tx: self.tx * other.a + self.ty * other.c + other.tx,
+            ty: self.tx * other.b + self.ty * other.d + other.ty,
+        }
+    }
+
+    /// Translation followed by the transform (same as `CGAffineTransformTranslate`).
+    pub fn translated_by(self, tx: CGFloat, ty: CGFloat) -> Self {
+        Self::translation(tx, ty).concat(self)
+    }
+
+    /// Scale followed by the transform (same as `CGAffineTransformScale`).
+    pub fn scaled_by(self, sx: CGFloat, sy: CGFloat) -> Self {
+        Self::scale(sx, sy).concat(self)
+    }
+
+    /// Rotation followed by the transform (same as `CGAffineTransformRotate`).
+    pub fn rotated_by(self, angle: CGFloat) -> Self {
+        Self::rotation(angle).concat(self)
+    }
+
+    /// Inverse transform, `None` if the transform cannot be inverted.
+    pub fn invert(self) -> Option<Self> {
+        let determinant = self.a * self.d - self.b * self.c;
+        if determinant == 0. || !determinant.is_finite() {
+            return None;
+        }
+        Some(Self {
+            a: self.d / determinant,
+            b: -self.b / determinant,
+            c: -self.c / determinant,
+            d: self.a / determinant,
+            tx: (self.c * self.ty - self.d * self.tx) / determinant,
+            ty: (self.b * self.tx - self.a * self.ty) / determinant,
+        })
+    }
+
+    pub fn is_identity(self) -> bool {
+        self == Self::IDENTITY
+    }
+
+    pub fn apply_to_point(self, point: CGPoint) -> CGPoint {
+        CGPoint::new(
+            self.a * point.x + self.c * point.y + self.tx,
+            self.b * point.x + self.d * point.y + self.ty,
+        )
+    }
+
+    /// Applies the transform without the translation.
+    pub fn apply_to_size(self, size: CGSize) -> CGSize {
+        CGSize::new(
+            self.a * size.width + self.c * size.height,
+            self.b * size.width + self.d * size.height,
+        )
+    }
+
+    /// Smallest rectangle containing the transformed corners of the rectangle (same as `CGRectApplyAffineTransform`).
+    /// Null and infinite rectangles are not changed.
+    pub fn apply_to_rect(self, rect: CGRect) -> CGRect {
+        if rect.is_null() || rect.is_infinite() {
+            return rect;
+        }
+        let corners = [
+            CGPoint::new(rect.min_x(), rect.min_y()),
+            CGPoint::new(rect.max_x(), rect.min_y()),
+            CGPoint::new(rect.min_x(), rect.max_y()),
+            CGPoint::new(rect.max_x(), rect.max_y()),
+        ]
+        .map(|corner| self.apply_to_point(corner));
+        let mut min = corners[0];
+        let mut max = corners[0];
+        for corner in &corners[1..] {
+            min = CGPoint::new(min.x.min(corner.x), min.y.min(corner.y));
+            max = CGPoint::new(max.x.max(corner.x), max.y.max(corner.y));
+        }
+        CGRect::new(min.x, min.y, max.x - min.x, max.y - min.y)
+    }
+
+    /// Splits the transform into scale, shear, rotation and translation (same as `CGAffineTransformDecompose`).
+    ///
+    /// A transform that mirrors has a negative vertical scale.
+    pub fn decompose(self) -> CGAffineTransformComponents {
+        let translation = CGVector::new(self.tx, self.ty);
+        let sx = self.a.hypot(self.b);
+        if sx == 0. {
+            // Nothing left on the x axis, no rotation can be determined.
+            let shear = if self.d == 0. { 0. } else { self.c / self.d };
+            return CGAffineTransformComponents {
+                scale: CGSize::new(0., self.d),
+                horizontal_shear: shear,
+                rotation: 0.,
+                translation,
+            };
+        }
+        let determinant = self.a * self.d - self.b * self.c;
+        let sy = determinant / sx;
+        let shear = if determinant == 0. {
+            0.
+        } else {
+            (self.a * self.c + self.b * self.d) / determinant
+        };
+        CGAffineTransformComponents {
+            scale: CGSize::new(sx, sy),
+            horizontal_shear: shear,
+            rotation: self.b.atan2(self.a),
+            translation,
+        }
+    }
+
+    /// Transform made of the components (same as `CGAffineTransformMakeWithComponents`).
+    pub fn from_components(components: CGAffineTransformComponents) -> Self {
+        let scale = Self::scale(components.scale.width, components.scale.height);
+        let shear = Self::new(1., 0., components.horizontal_shear, 1., 0., 0.);
+        let translation = components.translation;
+        scale
+            .concat(shear)
+            .concat(Self::rotation(components.rotation))
+            .concat(Self::translation(translation.dx, translation.dy))
+    }
+}
+
+#[cfg(test)]
+mod cgaffinetransform_tests {
+    use super::*;
+    #[cfg(target_pointer_width = "32")]
+    use std::f32::consts::{FRAC_PI_2, PI};
+    #[cfg(target_pointer_width = "64")]
+    use std::f64::consts::{FRAC_PI_2, PI};
+
+    fn assert_close(left: CGAffineTransform, right: CGAffineTransform) {
+        let values = |t: CGAffineTransform| [t.a, t.b, t.c, t.d, t.tx, t.ty];
+        let close = values(left)
+            .iter()
+            .zip(values(right).iter())
+            .all(|(l, r)| (l - r).abs() < 1e-9);
+        assert!(close, "{:?} != {:?}", left, right);
+    }
+
+    #[test]
+    fn constructors() {
+        assert!(CGAffineTransform::IDENTITY.is_identity());
+        assert!(CGAffineTransform::translation(0., 0.).is_identity());
+        assert!(CGAffineTransform::scale(1., 1.).is_identity());
+        assert!(CGAffineTransform::rotation(0.).is_identity());
+        assert!(!CGAffineTransform::translation(1., 0.).is_identity());
+
+        let point = CGPoint::new(1., 2.);
+        assert_eq!(
+            CGAffineTransform::translation(3., 4.).apply_to_point(point),
+            CGPoint::new(4., 6.)
+        );
+        assert_eq!(
+            CGAffineTransform::scale(3., -1.).apply_to_point(point),
+            CGPoint::new(3., -2.)
+        );
+        assert_eq!(
+            CGAffineTransform::rotation(FRAC_PI_2)
+                .apply_to_point(CGPoint::new(1., 0.))
+                .y,
+            1.
+        );
+    }
+
+    #[test]
+    fn concat() {
+        let translate = CGAffineTransform::translation(10., 0.);
+        let scale = CGAffineTransform::scale(2., 2.);
+        let point = CGPoint::new(1., 1.);
+        // The first transform is applied first.
+        assert_eq!(
+            translate.concat(scale).apply_to_point(point),
+            CGPoint::new(22., 2.)
+        );
+        assert_eq!(
+            scale.concat(translate).apply_to_point(point),
+            CGPoint::new(12., 2.)
+        );
+        // The `*_by` variants apply the new transform before the existing one.
+        assert_eq!(scale.translated_by(10., 0.), translate.concat(scale));
+        assert_eq!(translate.scaled_by(2., 2.), scale.concat(translate));
+        assert_close(
+            translate.rotated_by(PI),
+            CGAffineTransform::new(-1., 0., 0., -1., 10., 0.),
+        );
+        assert_eq!(CGAffineTransform::IDENTITY.concat(translate), translate);
+    }
+
+    #[test]
+    fn invert() {
+        let transform = CGAffineTransform::new(1., 2., 3., 4., 5., 6.);
+        let inverse = transform.invert().unwrap();
+        assert_close(transform.concat(inverse), CGAffineTransform::IDENTITY);
+        assert_close(inverse.concat(transform), CGAffineTransform::IDENTITY);
+        let point = CGPoint::new(-7., 3.);
+        assert_eq!(
+            inverse.apply_to_point(transform.apply_to_point(point)),
+            point
+        );
+
+        assert_eq!(CGAffineTransform::scale(0., 1.).invert(), None);
+        assert_eq!(
+            CGAffineTransform::new(1., 2., 2., 4., 0., 0.).invert(),
+            None
+        );
+        assert_eq!(
+            CGAffineTransform::IDENTITY.invert(),
+            Some(CGAffineTransform::IDENTITY)
+        );
+    }
+
+    #[test]
+    fn apply_to_size_and_rect() {
+        let transform = CGAffineTransform::new(0., 1., -1., 0., 1080., 0.);
+        assert_eq!(
+            transform.apply_to_size(CGSize::new(1920., 1080.)),
+            CGSize::new(-1080., 1920.)
+        );
+        assert_eq!(
+            transform.apply_to_rect(CGRect::new(0., 0., 1920., 1080.)),
+            CGRect::new(0., 0., 1080., 1920.)
+        );
+        // The result is standardized.
+        assert_eq!(
+            CGAffineTransform::scale(-1., 1.).apply_to_rect(CGRect::new(1., 1., 2., -1.)),
+            CGRect::new(-3., 0., 2., 1.)
+        );
+        assert!(transform.apply_to_rect(CGRect::NULL).is_null());
+        assert!(transform.apply_to_rect(CGRect::INFINITE).is_infinite());
+    }
+
+    #[test]
+    fn decompose() {
+        // Typical preferred transform of a video recorded in portrait.
+        let portrait = CGAffineTransform::new(0., 1., -1., 0., 1080., 0.);
+        let components = portrait.decompose();
+        assert_eq!(components.rotation, FRAC_PI_2);
+        assert_eq!(components.scale, CGSize::new(1., 1.));
+        assert_eq!(components.horizontal_shear, 0.);
+        assert_eq!(components.translation, CGVector::new(1080., 0.));
+
+        let upside_down = CGAffineTransform::new(-1., 0., 0., -1., 1920., 1080.);
+        assert_eq!(upside_down.decompose().rotation, PI);
+
+        let mirrored = CGAffineTransform::scale(1., -1.).decompose();
+        assert_eq!(mirrored.scale, CGSize::new(1., -1.));
+        assert_eq!(mirrored.rotation, 0.);
+
+        let components = CGAffineTransformComponents {
+            scale: CGSize::new(2., 3.),
+            horizontal_shear: 0.5,
+            rotation: -1.,
+            translation: CGVector::new(4., 5.),
+        };
+        let transform = CGAffineTransform::from_components(components);
+        let decomposed = transform.decompose();
+        assert!((decomposed.scale.width - 2.).abs() < 1e-9);
+        assert!((decomposed.scale.height - 3.).abs() < 1e-9);
+        assert!((decomposed.horizontal_shear - 0.5).abs() < 1e-9);
+        assert!((decomposed.rotation + 1.).abs() < 1e-9);
+        assert_eq!(decomposed.translation, CGVector::new(4., 5.));
+        assert_close(CGAffineTransform::from_components(decomposed), transform);
+
+        let degenerate = CGAffineTransform::new(0., 0., 1., 2., 0., 0.).decompose();
+        assert_eq!(degenerate.scale, CGSize::new(0., 2.));
+        assert_eq!(degenerate.horizontal_shear, 0.5);
+    }
 }
@@ -1,85 +1,8 @@
 use crate::base::core_foundation::*;
 use crate::base::objc::*;
-use crate::base::{fourcc, ptr};
+use crate::base::ptr;
 use crate::core_graphics::CGRect;
-
-pub(crate) mod prelude {
-    pub use super::CMAudioFormatDescriptionInterface;
-    pub use super::CMClosedCaptionFormatDescriptionInterface;
-    pub use super::CMFormatDescriptionInterface;
-    pub use super::CMMetadataFormatDescriptionInterface;
-    pub use super::CMMuxedFormatDescriptionInterface;
-    pub use super::CMTextFormatDescriptionInterface;
-    pub use super::CMTimeCodeFormatDescriptionInterface;
-    pub use super::CMVideoFormatDescriptionInterface;
-}
-
-//-------------------------------------------------------------------
-// CMTime
-
-#[derive(Copy, Clone, Eq, PartialEq)]
-#[repr(transparent)]
-pub struct CMTimeValue(pub i64);
-#[derive(Copy, Clone, Eq, PartialEq)]
-#[repr(transparent)]
-pub struct CMTimeScale(pub i32);
-#[derive(Copy, Clone, Eq, PartialEq)]
-#[repr(transparent)]
-pub struct CMTimeEpoch(pub i64);
-
-#[derive(Copy, Clone, Eq, PartialEq)]
-#[repr(transparent)]
-pub struct CMTimeFlags(pub u32);
-
-impl CMTimeFlags {
-    pub const VALID: Self = Self(1 << 0);
-    pub const HAS_BEEN_ROUNDED: Self = Self(1 << 1);
-    pub const POSITIVE_INFINITY: Self = Self(1 << 2);
-    pub const NEGATIVE_INFINITY: Self = Self(1 << 3);
-    pub const INDEFINITE: Self = Self(1 << 4);
-    pub const IMPLIED_VALUE_FLAGS_MASK: Self =
-        Self(Self::POSITIVE_INFINITY.0 | Self::NEGATIVE_INFINITY.0 | Self::INDEFINITE.0);
-}
-
-impl std::ops::BitOr for CMTimeFlags {
-    type Output = Self;
-    fn bitor(self, rhs: Self) -> Self::Output {
-        Self(self.0 | rhs.0)
-    }
-}
-
-impl std::ops::BitAnd for CMTimeFlags {
-    type Output = Self;
-    fn bitand(self, rhs: Self) -> Self::Output {
-        Self(self.0 & rhs.0)
-    }
-}
-
-#[derive(Copy, Clone)]
-#[repr(C)]
-pub struct CMTime {
-    pub value: CMTimeValue,
-    pub timescale: CMTimeScale,
-    pub flags: CMTimeFlags,
-    pub epoch: CMTimeEpoch,
-}
-
-#[cfg(test)]
-mod cmtime_tests {
-    use super::*;
-
-    #[test]
-    fn size() {
-        assert_eq!(std::mem::size_of::<CMTime>(), 24);
-    }
-}
-
-#[derive(Copy, Clone)]
-#[repr(C)]
-pub struct CMTimeRange {
-    pub start: CMTime,
-    pub duration: CMTime,
-}
+use super::CMMediaType;
 
 //-------------------------------------------------------------------
 // CMFormatDescriptionRef
@@ -95,21 +18,6 @@ extern "C" {
     fn CMVideoFormatDescriptionGetDimensions(video_desc: ptr::cf::RawRef) -> CMVideoDimensions;
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
-#[repr(transparent)]
-pub struct CMMediaType(pub u32);
-
-impl CMMediaType {
-    pub const AUDIO: Self = Self(fourcc("soun"));
-    pub const VIDEO: Self = Self(fourcc("vide"));
-    pub const MUXED: Self = Self(fourcc("muxx"));
-    pub const METADATA: Self = Self(fourcc("meta"));
-    pub const TEXT: Self = Self(fourcc("text"));
-    pub const TIME_CODE: Self = Self(fourcc("tmcd"));
-    pub const CLOSED_CAPTION: Self = Self(fourcc("clcp"));
-    pub const SUBTITLE: Self = Self(fourcc("sbtl"));
-}
-
 pub enum TypedCMFormatDescription {
     Audio(CMAudioFormatDescription),
     Video(CMVideoFormatDescription),
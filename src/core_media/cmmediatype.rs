@@ -0,0 +1,102 @@
+//-------------------------------------------------------------------
+// Media and codec types
+//
+// Kept out of the format descriptions, as they do not need CoreMedia.
+
+use crate::base::{FourCC, FourCCKind};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[repr(transparent)]
+pub struct CMMediaType(pub FourCC);
+
+impl CMMediaType {
+    pub const AUDIO: Self = Self(FourCC::from_text("soun"));
+    pub const VIDEO: Self = Self(FourCC::from_text("vide"));
+    pub const MUXED: Self = Self(FourCC::from_text("muxx"));
+    pub const METADATA: Self = Self(FourCC::from_text("meta"));
+    pub const TEXT: Self = Self(FourCC::from_text("text"));
+    pub const TIME_CODE: Self = Self(FourCC::from_text("tmcd"));
+    pub const CLOSED_CAPTION: Self = Self(FourCC::from_text("clcp"));
+    pub const SUBTITLE: Self = Self(FourCC::from_text("sbtl"));
+
+    pub fn known_name(self) -> Option<&'static str> {
+        self.0.known_name(FourCCKind::MediaType)
+    }
+}
+
+impl std::fmt::Display for CMMediaType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[repr(transparent)]
+pub struct CMVideoCodecType(pub FourCC);
+
+impl CMVideoCodecType {
+    pub const H264: Self = Self(FourCC::from_text("avc1"));
+    pub const HEVC: Self = Self(FourCC::from_text("hvc1"));
+    pub const HEVC_WITH_ALPHA: Self = Self(FourCC::from_text("muha"));
+    pub const APPLE_PRO_RES_422: Self = Self(FourCC::from_text("apcn"));
+    pub const APPLE_PRO_RES_422_HQ: Self = Self(FourCC::from_text("apch"));
+    pub const APPLE_PRO_RES_422_LT: Self = Self(FourCC::from_text("apcs"));
+    pub const APPLE_PRO_RES_422_PROXY: Self = Self(FourCC::from_text("apco"));
+    pub const APPLE_PRO_RES_4444: Self = Self(FourCC::from_text("ap4h"));
+    pub const APPLE_PRO_RES_4444_XQ: Self = Self(FourCC::from_text("ap4x"));
+    pub const JPEG: Self = Self(FourCC::from_text("jpeg"));
+    pub const MPEG4_VIDEO: Self = Self(FourCC::from_text("mp4v"));
+    pub const MPEG2_VIDEO: Self = Self(FourCC::from_text("mp2v"));
+    pub const VP9: Self = Self(FourCC::from_text("vp09"));
+    pub const AV1: Self = Self(FourCC::from_text("av01"));
+
+    pub fn known_name(self) -> Option<&'static str> {
+        self.0.known_name(FourCCKind::VideoCodecType)
+    }
+}
+
+impl std::fmt::Display for CMVideoCodecType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[repr(transparent)]
+pub struct CMTimeCodeFormatType(pub FourCC);
+
+impl CMTimeCodeFormatType {
+    pub const TIME_CODE_32: Self = Self(FourCC::from_text("tmcd"));
+    pub const TIME_CODE_64: Self = Self(FourCC::from_text("tc64"));
+    pub const COUNTER_32: Self = Self(FourCC::from_text("cn32"));
+    pub const COUNTER_64: Self = Self(FourCC::from_text("cn64"));
+
+    pub fn known_name(self) -> Option<&'static str> {
+        self.0.known_name(FourCCKind::TimeCodeFormatType)
+    }
+}
+
+impl std::fmt::Display for CMTimeCodeFormatType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod cmmediatype_tests {
+    use super::*;
+
+    #[test]
+    fn display_and_names() {
+        assert_eq!(CMMediaType::VIDEO.to_string(), "'vide'");
+        assert_eq!(format!("{:?}", CMMediaType::AUDIO), "CMMediaType('soun')");
+        assert_eq!(CMMediaType::TIME_CODE.known_name(), Some("Time code"));
+        assert_eq!(CMMediaType(FourCC(1)).known_name(), None);
+        assert_eq!(CMVideoCodecType::HEVC.known_name(), Some("HEVC"));
+        assert_eq!(CMVideoCodecType(FourCC(32)).to_string(), "0x00000020");
+        assert_eq!(
+            CMTimeCodeFormatType::TIME_CODE_32.known_name(),
+            Some("32-bit time code")
+        );
+    }
+}
@@ -0,0 +1,1132 @@
+//-------------------------------------------------------------------
+// CMTime
+//
+// The arithmetic is implemented in Rust, following the rules described in CoreMedia's CMTime.h,
+// so it can be used (and tested) without any call to CoreMedia.
+
+use crate::base::{Encode, Encoding};
+use std::cmp::Ordering;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct CMTimeValue(pub i64);
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct CMTimeScale(pub i32);
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct CMTimeEpoch(pub i64);
+
+impl CMTimeScale {
+    /// Largest timescale CoreMedia will choose when it has to pick a timescale by itself (`kCMTimeMaxTimescale`).
+    pub const MAX: Self = Self(1_000_000_000);
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct CMTimeFlags(pub u32);
+
+impl CMTimeFlags {
+    pub const VALID: Self = Self(1 << 0);
+    pub const HAS_BEEN_ROUNDED: Self = Self(1 << 1);
+    pub const POSITIVE_INFINITY: Self = Self(1 << 2);
+    pub const NEGATIVE_INFINITY: Self = Self(1 << 3);
+    pub const INDEFINITE: Self = Self(1 << 4);
+    pub const IMPLIED_VALUE_FLAGS_MASK: Self =
+        Self(Self::POSITIVE_INFINITY.0 | Self::NEGATIVE_INFINITY.0 | Self::INDEFINITE.0);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for CMTimeFlags {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitAnd for CMTimeFlags {
+    type Output = Self;
+    fn bitand(self, rhs: Self) -> Self::Output {
+        Self(self.0 & rhs.0)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct CMTimeRoundingMethod(pub u32);
+
+impl CMTimeRoundingMethod {
+    pub const ROUND_HALF_AWAY_FROM_ZERO: Self = Self(1);
+    pub const ROUND_TOWARD_ZERO: Self = Self(2);
+    pub const ROUND_AWAY_FROM_ZERO: Self = Self(3);
+    /// Rounds toward zero when converting to a smaller timescale, away from zero when converting to a larger one,
+    /// and never rounds a negative value to zero.
+    pub const QUICK_TIME: Self = Self(4);
+    pub const ROUND_TOWARD_POSITIVE_INFINITY: Self = Self(5);
+    pub const ROUND_TOWARD_NEGATIVE_INFINITY: Self = Self(6);
+    pub const DEFAULT: Self = Self::ROUND_HALF_AWAY_FROM_ZERO;
+}
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct CMTime {
+    pub value: CMTimeValue,
+    pub timescale: CMTimeScale,
+    pub flags: CMTimeFlags,
+    pub epoch: CMTimeEpoch,
+}
+
+/// Rounds `numerator / denominator`, also returning whether rounding was needed.
+/// `denominator` must be positive.
+fn div_round(numerator: i128, denominator: i128, method: CMTimeRoundingMethod) -> (i128, bool) {
+    debug_assert!(denominator > 0);
+    let quotient = numerator / denominator;
+    let remainder = numerator % denominator;
+    if remainder == 0 {
+        return (quotient, false);
+    }
+    let away_from_zero = quotient + numerator.signum();
+    let rounded = match method {
+        CMTimeRoundingMethod::ROUND_TOWARD_ZERO => quotient,
+        CMTimeRoundingMethod::ROUND_AWAY_FROM_ZERO => away_from_zero,
+        CMTimeRoundingMethod::ROUND_TOWARD_POSITIVE_INFINITY => quotient.max(away_from_zero),
+        CMTimeRoundingMethod::ROUND_TOWARD_NEGATIVE_INFINITY => quotient.min(away_from_zero),
+        // ROUND_HALF_AWAY_FROM_ZERO, and anything unknown is considered as the default rounding.
+        _ => {
+            if remainder.abs() * 2 >= denominator {
+                away_from_zero
+            } else {
+                quotient
+            }
+        }
+    };
+    (rounded, true)
+}
+
+fn gcd(mut a: i64, mut b: i64) -> i64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Exact rational value used as an intermediate result.
+/// `denominator` must be positive.
+#[derive(Copy, Clone)]
+struct Rational {
+    numerator: i128,
+    denominator: i128,
+}
+
+impl CMTime {
+    /// Invalid time, all fields set to 0 (`kCMTimeInvalid`).
+    pub const INVALID: Self = Self {
+        value: CMTimeValue(0),
+        timescale: CMTimeScale(0),
+        flags: CMTimeFlags(0),
+        epoch: CMTimeEpoch(0),
+    };
+    pub const INDEFINITE: Self = Self::non_numeric(CMTimeFlags::INDEFINITE);
+    pub const POSITIVE_INFINITY: Self = Self::non_numeric(CMTimeFlags::POSITIVE_INFINITY);
+    pub const NEGATIVE_INFINITY: Self = Self::non_numeric(CMTimeFlags::NEGATIVE_INFINITY);
+    pub const ZERO: Self = Self::new(0, 1);
+
+    const fn non_numeric(flag: CMTimeFlags) -> Self {
+        Self {
+            value: CMTimeValue(0),
+            timescale: CMTimeScale(0),
+            flags: CMTimeFlags(CMTimeFlags::VALID.0 | flag.0),
+            epoch: CMTimeEpoch(0),
+        }
+    }
+
+    /// Valid time of `value / timescale` seconds (same as `CMTimeMake`).
+    pub const fn new(value: i64, timescale: i32) -> Self {
+        Self::new_with_epoch(value, timescale, 0)
+    }
+
+    /// Same as `CMTimeMakeWithEpoch`.
+    pub const fn new_with_epoch(value: i64, timescale: i32, epoch: i64) -> Self {
+        Self {
+            value: CMTimeValue(value),
+            timescale: CMTimeScale(timescale),
+            flags: CMTimeFlags::VALID,
+            epoch: CMTimeEpoch(epoch),
+        }
+    }
+
+    pub fn is_valid(self) -> bool {
+        self.flags.contains(CMTimeFlags::VALID)
+    }
+
+    pub fn is_invalid(self) -> bool {
+        !self.is_valid()
+    }
+
+    pub fn is_positive_infinity(self) -> bool {
+        self.is_valid() && self.flags.contains(CMTimeFlags::POSITIVE_INFINITY)
+    }
+
+    pub fn is_negative_infinity(self) -> bool {
+        self.is_valid() && self.flags.contains(CMTimeFlags::NEGATIVE_INFINITY)
+    }
+
+    pub fn is_indefinite(self) -> bool {
+        self.is_valid() && self.flags.contains(CMTimeFlags::INDEFINITE)
+    }
+
+    /// Valid and not infinite or indefinite.
+    pub fn is_numeric(self) -> bool {
+        (self.flags & (CMTimeFlags::VALID | CMTimeFlags::IMPLIED_VALUE_FLAGS_MASK))
+            == CMTimeFlags::VALID
+    }
+
+    pub fn has_been_rounded(self) -> bool {
+        self.is_numeric() && self.flags.contains(CMTimeFlags::HAS_BEEN_ROUNDED)
+    }
+
+    /// Numeric time with a usable timescale. Arithmetic considers numeric times with a non-positive timescale invalid.
+    fn as_rational(self) -> Option<Rational> {
+        if self.is_numeric() && self.timescale.0 > 0 {
+            Some(Rational {
+                numerator: self.value.0 as i128,
+                denominator: self.timescale.0 as i128,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn rounded_flag(rounded: bool) -> CMTimeFlags {
+        if rounded {
+            CMTimeFlags::HAS_BEEN_ROUNDED
+        } else {
+            CMTimeFlags(0)
+        }
+    }
+
+    /// Converts an exact value to `timescale` with the default rounding,
+    /// halving the timescale until the value fits, as described for `CMTimeAdd` and `CMTimeMultiply`.
+    fn from_rational(exact: Rational, timescale: i32, rounded: bool, epoch: i64) -> Self {
+        let mut timescale = timescale;
+        loop {
+            let (value, needed_rounding) = div_round(
+                exact.numerator * timescale as i128,
+                exact.denominator,
+                CMTimeRoundingMethod::DEFAULT,
+            );
+            if let Ok(value) = i64::try_from(value) {
+                return Self {
+                    value: CMTimeValue(value),
+                    timescale: CMTimeScale(timescale),
+                    flags: CMTimeFlags::VALID | Self::rounded_flag(rounded || needed_rounding),
+                    epoch: CMTimeEpoch(epoch),
+                };
+            }
+            if timescale == 1 {
+                return if exact.numerator > 0 {
+                    Self::POSITIVE_INFINITY
+                } else {
+                    Self::NEGATIVE_INFINITY
+                };
+            }
+            timescale /= 2;
+        }
+    }
+
+    /// Returns the time converted to a new timescale (same as `CMTimeConvertScale`).
+    ///
+    /// Non-numeric times are returned as is, and a non-positive timescale gives an invalid time.
+    /// If the converted value does not fit in a `CMTimeValue`, the result is an infinity of the same sign.
+    pub fn convert_scale(self, timescale: i32, method: CMTimeRoundingMethod) -> Self {
+        if !self.is_numeric() {
+            return self;
+        }
+        let exact = match self.as_rational() {
+            Some(exact) if timescale > 0 => exact,
+            _ => return Self::INVALID,
+        };
+        if self.timescale.0 == timescale {
+            return self;
+        }
+
+        let (method, is_quick_time) = if method == CMTimeRoundingMethod::QUICK_TIME {
+            if timescale < self.timescale.0 {
+                (CMTimeRoundingMethod::ROUND_TOWARD_ZERO, true)
+            } else {
+                (CMTimeRoundingMethod::ROUND_AWAY_FROM_ZERO, true)
+            }
+        } else {
+            (method, false)
+        };
+        let (mut value, rounded) = div_round(
+            exact.numerator * timescale as i128,
+            exact.denominator,
+            method,
+        );
+        if is_quick_time && value == 0 && exact.numerator < 0 {
+            value = -1;
+        }
+        match i64::try_from(value) {
+            Ok(value) => Self {
+                value: CMTimeValue(value),
+                timescale: CMTimeScale(timescale),
+                flags: self.flags | Self::rounded_flag(rounded),
+                epoch: self.epoch,
+            },
+            Err(_) if value > 0 => Self::POSITIVE_INFINITY,
+            Err(_) => Self::NEGATIVE_INFINITY,
+        }
+    }
+
+    /// Common implementation of `CMTimeAdd` and `CMTimeSubtract`.
+    fn add_or_subtract(self, rhs: Self, subtract: bool) -> Self {
+        if self.is_invalid() || rhs.is_invalid() {
+            return Self::INVALID;
+        }
+
+        // For infinities, subtracting is the same as adding the opposite infinity.
+        let rhs_positive_infinity = if subtract {
+            rhs.is_negative_infinity()
+        } else {
+            rhs.is_positive_infinity()
+        };
+        let rhs_negative_infinity = if subtract {
+            rhs.is_positive_infinity()
+        } else {
+            rhs.is_negative_infinity()
+        };
+        match (
+            self.is_positive_infinity(),
+            self.is_negative_infinity(),
+            rhs_positive_infinity,
+            rhs_negative_infinity,
+        ) {
+            (true, _, _, true) | (_, true, true, _) => return Self::INVALID,
+            (true, _, _, _) | (_, _, true, _) => return Self::POSITIVE_INFINITY,
+            (_, true, _, _) | (_, _, _, true) => return Self::NEGATIVE_INFINITY,
+            _ => {}
+        }
+        if self.is_indefinite() || rhs.is_indefinite() {
+            return Self::INDEFINITE;
+        }
+
+        let (lhs_exact, rhs_exact) = match (self.as_rational(), rhs.as_rational()) {
+            (Some(lhs_exact), Some(rhs_exact)) => (lhs_exact, rhs_exact),
+            _ => return Self::INVALID,
+        };
+
+        // Times in epoch 0 are durations that can be added to (or subtracted from) times in any epoch.
+        let epoch = match (self.epoch.0, rhs.epoch.0) {
+            (lhs, rhs) if lhs == rhs => {
+                if subtract {
+                    0
+                } else {
+                    lhs
+                }
+            }
+            (lhs, 0) => lhs,
+            (0, rhs) if !subtract => rhs,
+            _ => return Self::INVALID,
+        };
+
+        let lhs_timescale = self.timescale.0 as i64;
+        let rhs_timescale = rhs.timescale.0 as i64;
+        let lcm = lhs_timescale / gcd(lhs_timescale, rhs_timescale) * rhs_timescale;
+        let timescale = if lcm > CMTimeScale::MAX.0 as i64 {
+            CMTimeScale::MAX.0
+        } else {
+            lcm as i32
+        };
+
+        let lhs_numerator = lhs_exact.numerator * (lcm / lhs_timescale) as i128;
+        let rhs_numerator = rhs_exact.numerator * (lcm / rhs_timescale) as i128;
+        let exact = Rational {
+            numerator: if subtract {
+                lhs_numerator - rhs_numerator
+            } else {
+                lhs_numerator + rhs_numerator
+            },
+            denominator: lcm as i128,
+        };
+        Self::from_rational(
+            exact,
+            timescale,
+            self.has_been_rounded() || rhs.has_been_rounded(),
+            epoch,
+        )
+    }
+
+    /// Same as `CMTimeMultiply`.
+    ///
+    /// The result keeps the timescale of `self`, unless it has to be halved for the value to fit.
+    pub fn multiply(self, multiplier: i32) -> Self {
+        self.multiply_by_ratio(multiplier, 1)
+    }
+
+    /// Same as `CMTimeMultiplyByRatio`: returns `self * multiplier / divisor`.
+    ///
+    /// The result keeps the timescale of `self`, unless it has to be halved for the value to fit.
+    /// A `divisor` of 0 gives an invalid time.
+    pub fn multiply_by_ratio(self, multiplier: i32, divisor: i32) -> Self {
+        if self.is_invalid() || divisor == 0 {
+            return Self::INVALID;
+        }
+        let negate = (multiplier < 0) != (divisor < 0);
+        if self.is_positive_infinity() || self.is_negative_infinity() {
+            return if self.is_positive_infinity() != negate {
+                Self::POSITIVE_INFINITY
+            } else {
+                Self::NEGATIVE_INFINITY
+            };
+        }
+        if self.is_indefinite() {
+            return Self::INDEFINITE;
+        }
+        let exact = match self.as_rational() {
+            Some(exact) => exact,
+            None => return Self::INVALID,
+        };
+
+        // Keep the denominator positive.
+        let divisor = divisor as i128;
+        let exact = Rational {
+            numerator: exact.numerator * multiplier as i128 * divisor.signum(),
+            denominator: exact.denominator * divisor.abs(),
+        };
+        Self::from_rational(
+            exact,
+            self.timescale.0,
+            self.has_been_rounded(),
+            self.epoch.0,
+        )
+    }
+}
+
+impl std::ops::Add for CMTime {
+    type Output = Self;
+
+    /// Same as `CMTimeAdd`.
+    ///
+    /// If the timescales differ, the result uses their least common multiple (capped to `CMTimeScale::MAX`),
+    /// halved until the value fits if needed.
+    fn add(self, rhs: Self) -> Self::Output {
+        self.add_or_subtract(rhs, false)
+    }
+}
+
+impl std::ops::Sub for CMTime {
+    type Output = Self;
+
+    /// Same as `CMTimeSubtract`.
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.add_or_subtract(rhs, true)
+    }
+}
+
+impl std::ops::Neg for CMTime {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        self.multiply(-1)
+    }
+}
+
+impl Ord for CMTime {
+    /// Same as `CMTimeCompare`.
+    ///
+    /// From smallest to largest: negative infinity, numeric times, indefinite, positive infinity, invalid.
+    /// Numeric times in a larger epoch are always after the ones in a smaller epoch.
+    /// As in arithmetic, numeric times with a non-positive timescale are ranked with invalid ones.
+    fn cmp(&self, other: &Self) -> Ordering {
+        fn rank(time: CMTime) -> u8 {
+            if time.is_invalid() || (time.is_numeric() && time.as_rational().is_none()) {
+                4
+            } else if time.is_positive_infinity() {
+                3
+            } else if time.is_indefinite() {
+                2
+            } else if time.is_negative_infinity() {
+                0
+            } else {
+                1
+            }
+        }
+
+        let (self_rank, other_rank) = (rank(*self), rank(*other));
+        if self_rank != 1 || other_rank != 1 {
+            return self_rank.cmp(&other_rank);
+        }
+        self.epoch.0.cmp(&other.epoch.0).then_with(|| {
+            let (lhs, rhs) = (self.as_rational().unwrap(), other.as_rational().unwrap());
+            // Cross-multiplying positive denominators, that cannot overflow in 128 bits.
+            (lhs.numerator * rhs.denominator).cmp(&(rhs.numerator * lhs.denominator))
+        })
+    }
+}
+
+impl PartialOrd for CMTime {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Equality as defined by `CMTimeCompare`, so `1/2` and `2/4` are equal.
+impl PartialEq for CMTime {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for CMTime {}
+
+impl Default for CMTime {
+    fn default() -> Self {
+        Self::INVALID
+    }
+}
+
+unsafe impl Encode for CMTimeValue {
+    fn encoding() -> Encoding {
+        i64::encoding()
+    }
+}
+
+unsafe impl Encode for CMTimeScale {
+    fn encoding() -> Encoding {
+        i32::encoding()
+    }
+}
+
+unsafe impl Encode for CMTimeFlags {
+    fn encoding() -> Encoding {
+        u32::encoding()
+    }
+}
+
+unsafe impl Encode for CMTimeEpoch {
+    fn encoding() -> Encoding {
+        i64::encoding()
+    }
+}
+
+crate::encode_struct!(CMTime = "?" {
+    value: CMTimeValue,
+    timescale: CMTimeScale,
+    flags: CMTimeFlags,
+    epoch: CMTimeEpoch,
+});
+
+#[cfg(test)]
+mod cmtime_tests {
+    use super::*;
+
+    /// Compares all fields, contrary to `==`.
+    #[track_caller]
+    fn assert_identical(actual: CMTime, expected: CMTime) {
+        assert_eq!(
+            (actual.value, actual.timescale, actual.flags, actual.epoch),
+            (
+                expected.value,
+                expected.timescale,
+                expected.flags,
+                expected.epoch
+            ),
+        );
+    }
+
+    fn rounded(value: i64, timescale: i32) -> CMTime {
+        let mut time = CMTime::new(value, timescale);
+        time.flags = time.flags | CMTimeFlags::HAS_BEEN_ROUNDED;
+        time
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(CMTime::encoding().to_string(), "{?=qiIq}");
+    }
+
+    #[test]
+    fn size() {
+        assert_eq!(std::mem::size_of::<CMTime>(), 24);
+    }
+
+    #[test]
+    fn constants() {
+        let flags = |time: CMTime| time.flags.0;
+        assert_eq!(flags(CMTime::INVALID), 0);
+        assert_eq!(flags(CMTime::POSITIVE_INFINITY), 5);
+        assert_eq!(flags(CMTime::NEGATIVE_INFINITY), 9);
+        assert_eq!(flags(CMTime::INDEFINITE), 17);
+        assert_eq!(flags(CMTime::ZERO), 1);
+        assert!(CMTime::ZERO.is_numeric());
+        assert!(!CMTime::INDEFINITE.is_numeric());
+        assert!(CMTime::INVALID.is_invalid());
+    }
+
+    #[test]
+    fn add_same_timescale() {
+        assert_identical(CMTime::new(1, 30) + CMTime::new(2, 30), CMTime::new(3, 30));
+        assert_identical(CMTime::new(1, 30) - CMTime::new(2, 30), CMTime::new(-1, 30));
+    }
+
+    #[test]
+    fn add_uses_lcm() {
+        assert_identical(CMTime::new(1, 6) + CMTime::new(1, 4), CMTime::new(5, 12));
+        assert_identical(
+            CMTime::new(1, 600) + CMTime::new(1, 44100),
+            CMTime::new(147 + 2, 88200),
+        );
+        assert_identical(CMTime::new(1, 6) - CMTime::new(1, 4), CMTime::new(-1, 12));
+    }
+
+    #[test]
+    fn add_with_lcm_over_max_timescale() {
+        // lcm(999_999_937, 3) is too large, so the result is rounded to the max timescale.
+        let sum = CMTime::new(1, 999_999_937) + CMTime::new(1, 3);
+        assert_identical(sum, rounded(333_333_334, 1_000_000_000));
+        assert!(sum.has_been_rounded());
+    }
+
+    #[test]
+    fn add_overflow_halves_timescale() {
+        let sum = CMTime::new(i64::MAX, 4) + CMTime::new(i64::MAX, 4);
+        assert_identical(sum, CMTime::new(i64::MAX, 2));
+        let sum = CMTime::new(i64::MAX, 3) + CMTime::new(2, 3);
+        // (i64::MAX + 2) / 3 is an integer so no rounding is needed.
+        assert_identical(sum, CMTime::new(i64::MAX / 3 + 1, 1));
+        let sum = CMTime::new(i64::MAX, 3) + CMTime::new(1, 3);
+        assert_identical(sum, rounded(i64::MAX / 3 + 1, 1));
+        assert_identical(
+            CMTime::new(i64::MAX, 1) + CMTime::new(1, 1),
+            CMTime::POSITIVE_INFINITY,
+        );
+        assert_identical(
+            CMTime::new(i64::MIN, 1) - CMTime::new(1, 1),
+            CMTime::NEGATIVE_INFINITY,
+        );
+    }
+
+    #[test]
+    fn rounded_flag_is_kept() {
+        assert!((rounded(1, 2) + CMTime::new(1, 2)).has_been_rounded());
+        assert!((CMTime::new(1, 2) - rounded(1, 2)).has_been_rounded());
+        assert!(!(CMTime::new(1, 2) + CMTime::new(1, 2)).has_been_rounded());
+    }
+
+    #[test]
+    fn add_non_numeric() {
+        use CMTime as T;
+        let numeric = T::new(1, 2);
+        let cases = [
+            (T::INVALID, numeric, T::INVALID, T::INVALID),
+            (numeric, T::INVALID, T::INVALID, T::INVALID),
+            (T::INVALID, T::POSITIVE_INFINITY, T::INVALID, T::INVALID),
+            (
+                T::POSITIVE_INFINITY,
+                numeric,
+                T::POSITIVE_INFINITY,
+                T::POSITIVE_INFINITY,
+            ),
+            (
+                numeric,
+                T::POSITIVE_INFINITY,
+                T::POSITIVE_INFINITY,
+                T::NEGATIVE_INFINITY,
+            ),
+            (
+                numeric,
+                T::NEGATIVE_INFINITY,
+                T::NEGATIVE_INFINITY,
+                T::POSITIVE_INFINITY,
+            ),
+            (
+                T::POSITIVE_INFINITY,
+                T::POSITIVE_INFINITY,
+                T::POSITIVE_INFINITY,
+                T::INVALID,
+            ),
+            (
+                T::NEGATIVE_INFINITY,
+                T::NEGATIVE_INFINITY,
+                T::NEGATIVE_INFINITY,
+                T::INVALID,
+            ),
+            (
+                T::POSITIVE_INFINITY,
+                T::NEGATIVE_INFINITY,
+                T::INVALID,
+                T::POSITIVE_INFINITY,
+            ),
+            (
+                T::NEGATIVE_INFINITY,
+                T::POSITIVE_INFINITY,
+                T::INVALID,
+                T::NEGATIVE_INFINITY,
+            ),
+            (
+                T::INDEFINITE,
+                T::POSITIVE_INFINITY,
+                T::POSITIVE_INFINITY,
+                T::NEGATIVE_INFINITY,
+            ),
+            (T::INDEFINITE, numeric, T::INDEFINITE, T::INDEFINITE),
+            (numeric, T::INDEFINITE, T::INDEFINITE, T::INDEFINITE),
+            (T::INDEFINITE, T::INDEFINITE, T::INDEFINITE, T::INDEFINITE),
+        ];
+        for (lhs, rhs, sum, difference) in cases {
+            assert_identical(lhs + rhs, sum);
+            assert_identical(lhs - rhs, difference);
+        }
+    }
+
+    #[test]
+    fn epochs() {
+        let epoch1 = CMTime::new_with_epoch(1, 2, 1);
+        let other_epoch1 = CMTime::new_with_epoch(1, 4, 1);
+        let epoch2 = CMTime::new_with_epoch(1, 2, 2);
+        let duration = CMTime::new(1, 2);
+
+        assert_identical(epoch1 + other_epoch1, CMTime::new_with_epoch(3, 4, 1));
+        assert_identical(epoch1 - other_epoch1, CMTime::new(1, 4));
+        assert_identical(epoch1 + duration, CMTime::new_with_epoch(2, 2, 1));
+        assert_identical(duration + epoch1, CMTime::new_with_epoch(2, 2, 1));
+        assert_identical(epoch1 - duration, CMTime::new_with_epoch(0, 2, 1));
+        assert_identical(duration - epoch1, CMTime::INVALID);
+        assert_identical(epoch1 + epoch2, CMTime::INVALID);
+        assert_identical(epoch1 - epoch2, CMTime::INVALID);
+    }
+
+    #[test]
+    fn neg() {
+        assert_identical(-CMTime::new(1, 2), CMTime::new(-1, 2));
+        assert_identical(-CMTime::POSITIVE_INFINITY, CMTime::NEGATIVE_INFINITY);
+        assert_identical(-CMTime::NEGATIVE_INFINITY, CMTime::POSITIVE_INFINITY);
+        assert_identical(-CMTime::INDEFINITE, CMTime::INDEFINITE);
+        assert_identical(-CMTime::INVALID, CMTime::INVALID);
+        // -i64::MIN does not fit so the timescale has to be halved.
+        assert_identical(-CMTime::new(i64::MIN, 2), CMTime::new(1 << 62, 1));
+    }
+
+    #[test]
+    fn compare() {
+        let ordered = [
+            CMTime::NEGATIVE_INFINITY,
+            CMTime::new(i64::MIN, 1),
+            CMTime::new(-1, 2),
+            CMTime::ZERO,
+            CMTime::new(1, 1_000_000_000),
+            CMTime::new(1, 3),
+            CMTime::new(1, 2),
+            CMTime::new(i64::MAX, 1),
+            CMTime::new_with_epoch(-5, 1, 1),
+            CMTime::new_with_epoch(0, 1, 2),
+            CMTime::INDEFINITE,
+            CMTime::POSITIVE_INFINITY,
+            CMTime::INVALID,
+        ];
+        for (i, lhs) in ordered.iter().enumerate() {
+            for (j, rhs) in ordered.iter().enumerate() {
+                assert_eq!(lhs.cmp(rhs), i.cmp(&j), "comparing {:?} and {:?}", lhs, rhs);
+            }
+        }
+        assert_eq!(CMTime::new(1, 2), CMTime::new(2, 4));
+        assert_eq!(CMTime::new(1, 2), rounded(1, 2));
+        assert_eq!(
+            CMTime::new(1, 2).max(CMTime::INVALID),
+            CMTime::INVALID,
+            "invalid is larger than anything"
+        );
+        assert_eq!(CMTime::new(1, 2).min(CMTime::new(1, 3)), CMTime::new(1, 3));
+
+        // Non-positive timescales compare like invalid times, keeping the order transitive.
+        for unusable in [CMTime::new(1, 0), CMTime::new(-1, -2)] {
+            assert_eq!(unusable, CMTime::INVALID);
+            assert!(unusable > CMTime::POSITIVE_INFINITY);
+            assert!(unusable > CMTime::new(i64::MAX, 1));
+        }
+    }
+
+    #[test]
+    fn convert_scale_rounding() {
+        use CMTimeRoundingMethod as M;
+        // For each value converted from a timescale of 4 to 2: expected values for each rounding method.
+        let cases: [(i64, [i64; 6]); 8] = [
+            // half away, toward 0, away from 0, QuickTime, toward +inf, toward -inf
+            (3, [2, 1, 2, 1, 2, 1]),
+            (1, [1, 0, 1, 0, 1, 0]),
+            (2, [1, 1, 1, 1, 1, 1]),
+            (5, [3, 2, 3, 2, 3, 2]),
+            (-1, [-1, 0, -1, -1, 0, -1]),
+            (-3, [-2, -1, -2, -1, -1, -2]),
+            (-4, [-2, -2, -2, -2, -2, -2]),
+            (0, [0, 0, 0, 0, 0, 0]),
+        ];
+        let methods = [
+            M::ROUND_HALF_AWAY_FROM_ZERO,
+            M::ROUND_TOWARD_ZERO,
+            M::ROUND_AWAY_FROM_ZERO,
+            M::QUICK_TIME,
+            M::ROUND_TOWARD_POSITIVE_INFINITY,
+            M::ROUND_TOWARD_NEGATIVE_INFINITY,
+        ];
+        for (value, expected_values) in cases {
+            for (method, expected_value) in methods.iter().zip(expected_values) {
+                let converted = CMTime::new(value, 4).convert_scale(2, *method);
+                assert_eq!(
+                    converted.value.0, expected_value,
+                    "{} with {:?}",
+                    value, method
+                );
+                assert_eq!(converted.timescale.0, 2);
+                assert_eq!(converted.has_been_rounded(), value % 2 != 0);
+            }
+        }
+    }
+
+    #[test]
+    fn convert_scale_quick_time_to_larger_timescale() {
+        // 1/3 to a timescale of 10 gives 3.33..., rounded away from zero.
+        let converted = CMTime::new(1, 3).convert_scale(10, CMTimeRoundingMethod::QUICK_TIME);
+        assert_identical(converted, rounded(4, 10));
+        let converted = CMTime::new(-1, 3).convert_scale(10, CMTimeRoundingMethod::QUICK_TIME);
+        assert_identical(converted, rounded(-4, 10));
+    }
+
+    #[test]
+    fn convert_scale_special_cases() {
+        let method = CMTimeRoundingMethod::DEFAULT;
+        assert_identical(
+            CMTime::new(3, 2).convert_scale(600, method),
+            CMTime::new(900, 600),
+        );
+        assert_identical(
+            CMTime::POSITIVE_INFINITY.convert_scale(600, method),
+            CMTime::POSITIVE_INFINITY,
+        );
+        assert_identical(
+            CMTime::INDEFINITE.convert_scale(600, method),
+            CMTime::INDEFINITE,
+        );
+        assert_identical(CMTime::INVALID.convert_scale(600, method), CMTime::INVALID);
+        assert_identical(CMTime::new(1, 2).convert_scale(0, method), CMTime::INVALID);
+        assert_identical(
+            CMTime::new(i64::MAX, 1).convert_scale(2, method),
+            CMTime::POSITIVE_INFINITY,
+        );
+        assert_identical(
+            CMTime::new_with_epoch(1, 2, 3).convert_scale(4, method),
+            CMTime::new_with_epoch(2, 4, 3),
+        );
+    }
+
+    #[test]
+    fn multiply() {
+        assert_identical(CMTime::new(3, 10).multiply(4), CMTime::new(12, 10));
+        assert_identical(CMTime::new(3, 10).multiply(-1), CMTime::new(-3, 10));
+        assert_identical(
+            CMTime::new(1 << 62, 10).multiply(2),
+            CMTime::new(1 << 62, 5),
+        );
+        assert_identical(
+            CMTime::new((1 << 62) + 1, 3).multiply(2),
+            rounded(3074457345618258603, 1),
+        );
+        assert_identical(
+            CMTime::new(i64::MAX, 1).multiply(2),
+            CMTime::POSITIVE_INFINITY,
+        );
+        assert_identical(
+            CMTime::POSITIVE_INFINITY.multiply(-2),
+            CMTime::NEGATIVE_INFINITY,
+        );
+        assert_identical(CMTime::INDEFINITE.multiply(2), CMTime::INDEFINITE);
+        assert_identical(CMTime::INVALID.multiply(2), CMTime::INVALID);
+        assert!(rounded(1, 2).multiply(2).has_been_rounded());
+    }
+
+    #[test]
+    fn multiply_by_ratio() {
+        assert_identical(
+            CMTime::new(3, 10).multiply_by_ratio(2, 3),
+            CMTime::new(2, 10),
+        );
+        assert_identical(CMTime::new(1, 10).multiply_by_ratio(1, 3), rounded(0, 10));
+        assert_identical(CMTime::new(2, 10).multiply_by_ratio(1, 3), rounded(1, 10));
+        assert_identical(CMTime::new(2, 10).multiply_by_ratio(1, -3), rounded(-1, 10));
+        assert_identical(CMTime::new(3, 10).multiply_by_ratio(1, 0), CMTime::INVALID);
+        assert_identical(
+            CMTime::NEGATIVE_INFINITY.multiply_by_ratio(-1, -3),
+            CMTime::NEGATIVE_INFINITY,
+        );
+        assert_identical(
+            CMTime::NEGATIVE_INFINITY.multiply_by_ratio(1, -3),
+            CMTime::POSITIVE_INFINITY,
+        );
+        // The intermediate value does not need to fit.
+        assert_identical(
+            CMTime::new(i64::MAX, 10).multiply_by_ratio(4, 8),
+            rounded(i64::MAX / 2 + 1, 10),
+        );
+    }
+}
+
+//-------------------------------------------------------------------
+// CMTime conversions
+
+impl CMTime {
+    /// Time closest to `secs` seconds in the given timescale (same as `CMTimeMakeWithSeconds`).
+    ///
+    /// As for arithmetic, the timescale is halved until the value fits.
+    /// NaN gives an invalid time, and infinite values the infinity of the same sign.
+    pub fn from_seconds(secs: f64, preferred_timescale: i32) -> Self {
+        if secs.is_nan() || preferred_timescale <= 0 {
+            return Self::INVALID;
+        }
+        if secs.is_infinite() {
+            return if secs > 0. {
+                Self::POSITIVE_INFINITY
+            } else {
+                Self::NEGATIVE_INFINITY
+            };
+        }
+        let mut timescale = preferred_timescale;
+        loop {
+            let exact = secs * timescale as f64;
+            // i64::MAX is not representable as a f64, 2^63 is the first value that does not fit.
+            if exact.abs() < 9_223_372_036_854_775_808. {
+                let value = exact.round();
+                return Self {
+                    value: CMTimeValue(value as i64),
+                    timescale: CMTimeScale(timescale),
+                    flags: CMTimeFlags::VALID | Self::rounded_flag(value != exact),
+                    epoch: CMTimeEpoch(0),
+                };
+            }
+            if timescale == 1 {
+                return if secs > 0. {
+                    Self::POSITIVE_INFINITY
+                } else {
+                    Self::NEGATIVE_INFINITY
+                };
+            }
+            timescale /= 2;
+        }
+    }
+
+    /// Time in seconds (same as `CMTimeGetSeconds`).
+    ///
+    /// Infinities give infinite values, invalid and indefinite times give NaN.
+    pub fn seconds(self) -> f64 {
+        if self.is_positive_infinity() {
+            f64::INFINITY
+        } else if self.is_negative_infinity() {
+            f64::NEG_INFINITY
+        } else if let Some(exact) = self.as_rational() {
+            exact.numerator as f64 / exact.denominator as f64
+        } else {
+            f64::NAN
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CMTimeToDurationError {
+    /// The time was invalid, infinite or indefinite.
+    NotNumeric,
+    Negative,
+}
+
+impl std::fmt::Display for CMTimeToDurationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotNumeric => f.write_str("CMTime is not numeric"),
+            Self::Negative => f.write_str("CMTime is negative"),
+        }
+    }
+}
+
+impl std::error::Error for CMTimeToDurationError {}
+
+impl TryFrom<CMTime> for std::time::Duration {
+    type Error = CMTimeToDurationError;
+
+    /// Converts to the nearest nanosecond.
+    fn try_from(time: CMTime) -> Result<Self, Self::Error> {
+        let exact = time
+            .as_rational()
+            .ok_or(CMTimeToDurationError::NotNumeric)?;
+        if exact.numerator < 0 {
+            return Err(CMTimeToDurationError::Negative);
+        }
+        let (nanos, _) = div_round(
+            exact.numerator * 1_000_000_000,
+            exact.denominator,
+            CMTimeRoundingMethod::DEFAULT,
+        );
+        let secs = (nanos / 1_000_000_000) as u64;
+        let nanos = (nanos % 1_000_000_000) as u32;
+        Ok(Self::new(secs, nanos))
+    }
+}
+
+impl From<std::time::Duration> for CMTime {
+    /// Converts to a time with a timescale of 1 000 000 000 (nanoseconds),
+    /// halved until the value fits for very long durations.
+    fn from(duration: std::time::Duration) -> Self {
+        let exact = Rational {
+            numerator: duration.as_nanos() as i128,
+            denominator: 1_000_000_000,
+        };
+        Self::from_rational(exact, 1_000_000_000, false, 0)
+    }
+}
+
+/// Same format as `CMTimeCopyDescription`, for example `{1/30 = 0.033}` or `{INDEFINITE}`.
+impl std::fmt::Display for CMTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_invalid() {
+            return f.write_str("{INVALID}");
+        }
+        if self.is_positive_infinity() {
+            return f.write_str("{+INFINITY}");
+        }
+        if self.is_negative_infinity() {
+            return f.write_str("{-INFINITY}");
+        }
+        if self.is_indefinite() {
+            return f.write_str("{INDEFINITE}");
+        }
+        write!(
+            f,
+            "{{{}/{} = {:.3}",
+            self.value.0,
+            self.timescale.0,
+            self.seconds()
+        )?;
+        if self.has_been_rounded() {
+            f.write_str(", rounded")?;
+        }
+        if self.epoch.0 != 0 {
+            write!(f, ", epoch {}", self.epoch.0)?;
+        }
+        f.write_str("}")
+    }
+}
+
+#[cfg(test)]
+mod cmtime_conversion_tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn from_seconds() {
+        let time = CMTime::from_seconds(0.5, 600);
+        assert_eq!((time.value.0, time.timescale.0), (300, 600));
+        assert!(!time.has_been_rounded());
+
+        let time = CMTime::from_seconds(1. / 3., 100);
+        assert_eq!((time.value.0, time.timescale.0), (33, 100));
+        assert!(time.has_been_rounded());
+
+        let time = CMTime::from_seconds(-2.5, 1);
+        assert_eq!((time.value.0, time.timescale.0), (-3, 1));
+
+        // Too large for the timescale, that has to be halved.
+        let time = CMTime::from_seconds(1e10, 1_000_000_000);
+        assert_eq!(time.timescale.0, 500_000_000);
+        assert_eq!(time.seconds(), 1e10);
+
+        assert!(CMTime::from_seconds(f64::NAN, 600).is_invalid());
+        assert!(CMTime::from_seconds(1., 0).is_invalid());
+        assert!(CMTime::from_seconds(f64::INFINITY, 600).is_positive_infinity());
+        assert!(CMTime::from_seconds(-1e300, 600).is_negative_infinity());
+    }
+
+    #[test]
+    fn seconds() {
+        assert_eq!(CMTime::new(3, 2).seconds(), 1.5);
+        assert_eq!(CMTime::new(-1, 4).seconds(), -0.25);
+        assert_eq!(CMTime::POSITIVE_INFINITY.seconds(), f64::INFINITY);
+        assert_eq!(CMTime::NEGATIVE_INFINITY.seconds(), f64::NEG_INFINITY);
+        assert!(CMTime::INDEFINITE.seconds().is_nan());
+        assert!(CMTime::INVALID.seconds().is_nan());
+    }
+
+    #[test]
+    fn to_duration() {
+        assert_eq!(
+            Duration::try_from(CMTime::new(3, 2)),
+            Ok(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            Duration::try_from(CMTime::new(1, 3)),
+            Ok(Duration::from_nanos(333_333_333))
+        );
+        assert_eq!(
+            Duration::try_from(CMTime::new(2, 3)),
+            Ok(Duration::from_nanos(666_666_667))
+        );
+        assert_eq!(
+            Duration::try_from(CMTime::new(i64::MAX, 1)),
+            Ok(Duration::from_secs(i64::MAX as u64))
+        );
+        assert_eq!(Duration::try_from(CMTime::ZERO), Ok(Duration::ZERO));
+        assert_eq!(
+            Duration::try_from(CMTime::new(-1, 2)),
+            Err(CMTimeToDurationError::Negative)
+        );
+        assert_eq!(
+            Duration::try_from(CMTime::POSITIVE_INFINITY),
+            Err(CMTimeToDurationError::NotNumeric)
+        );
+        assert_eq!(
+            Duration::try_from(CMTime::INVALID),
+            Err(CMTimeToDurationError::NotNumeric)
+        );
+    }
+
+    #[test]
+    fn from_duration() {
+        let time = CMTime::from(Duration::from_millis(1500));
+        assert_eq!(
+            (time.value.0, time.timescale.0),
+            (1_500_000_000, 1_000_000_000)
+        );
+        // Too many nanoseconds to fit in a CMTimeValue, that has to use a smaller timescale.
+        let time = CMTime::from(Duration::from_secs(10_000_000_000));
+        assert_eq!(time.timescale.0, 500_000_000);
+        assert!(!time.has_been_rounded());
+        assert_eq!(time.seconds(), 1e10);
+        assert!(CMTime::from(Duration::MAX).is_positive_infinity());
+    }
+
+    #[test]
+    fn duration_round_trip() {
+        for nanos in [0, 1, 999_999_999, 1_000_000_000, 123_456_789_012_345] {
+            let duration = Duration::from_nanos(nanos);
+            assert_eq!(Duration::try_from(CMTime::from(duration)), Ok(duration));
+        }
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(CMTime::new(1, 30).to_string(), "{1/30 = 0.033}");
+        assert_eq!(CMTime::new(-3, 2).to_string(), "{-3/2 = -1.500}");
+        assert_eq!(
+            CMTime::from_seconds(1. / 3., 100).to_string(),
+            "{33/100 = 0.330, rounded}"
+        );
+        assert_eq!(
+            CMTime::new_with_epoch(1, 1, 2).to_string(),
+            "{1/1 = 1.000, epoch 2}"
+        );
+        assert_eq!(CMTime::INVALID.to_string(), "{INVALID}");
+        assert_eq!(CMTime::POSITIVE_INFINITY.to_string(), "{+INFINITY}");
+        assert_eq!(CMTime::NEGATIVE_INFINITY.to_string(), "{-INFINITY}");
+        assert_eq!(CMTime::INDEFINITE.to_string(), "{INDEFINITE}");
+    }
+}
@@ -0,0 +1,358 @@
+//-------------------------------------------------------------------
+// CMTimeRange
+//
+// Like for CMTime, implemented in Rust following the rules described in CoreMedia's CMTimeRange.h.
+// The end of a range is not part of it: a range contains times `t` with `start <= t < end`.
+// All comparisons are done with `CMTime`'s ordering, so infinite and indefinite times behave as they do with `CMTimeCompare`.
+
+use super::CMTime;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(C)]
+pub struct CMTimeRange {
+    pub start: CMTime,
+    pub duration: CMTime,
+}
+
+impl CMTimeRange {
+    /// Empty range starting at 0 (`kCMTimeRangeZero`).
+    pub const ZERO: Self = Self::new(CMTime::ZERO, CMTime::ZERO);
+    pub const INVALID: Self = Self::new(CMTime::INVALID, CMTime::INVALID);
+
+    /// Same as `CMTimeRangeMake`.
+    pub const fn new(start: CMTime, duration: CMTime) -> Self {
+        Self { start, duration }
+    }
+
+    /// Range going from `start` to `end` (same as `CMTimeRangeFromTimeToTime`).
+    pub fn from_start_end(start: CMTime, end: CMTime) -> Self {
+        Self::new(start, end - start)
+    }
+
+    /// Valid start and duration, the duration being a non-negative time in epoch 0 (same as `CMTIMERANGE_IS_VALID`).
+    pub fn is_valid(self) -> bool {
+        self.start.is_valid()
+            && self.duration.is_valid()
+            && self.duration.epoch.0 == 0
+            && self.duration.value.0 >= 0
+    }
+
+    pub fn is_invalid(self) -> bool {
+        !self.is_valid()
+    }
+
+    /// Valid range with an indefinite start or duration (same as `CMTIMERANGE_IS_INDEFINITE`).
+    pub fn is_indefinite(self) -> bool {
+        self.is_valid() && (self.start.is_indefinite() || self.duration.is_indefinite())
+    }
+
+    /// Valid range with a zero duration (same as `CMTIMERANGE_IS_EMPTY`).
+    pub fn is_empty(self) -> bool {
+        self.is_valid() && self.duration == CMTime::ZERO
+    }
+
+    /// Time just after the end of the range (same as `CMTimeRangeGetEnd`).
+    ///
+    /// Invalid if the range is invalid.
+    pub fn end(self) -> CMTime {
+        if self.is_invalid() {
+            return CMTime::INVALID;
+        }
+        self.start + self.duration
+    }
+
+    /// Same as `CMTimeRangeContainsTime`.
+    pub fn contains_time(self, time: CMTime) -> bool {
+        self.is_valid() && time.is_valid() && time >= self.start && time < self.end()
+    }
+
+    /// Whether `other` is fully inside `self` (same as `CMTimeRangeContainsTimeRange`).
+    pub fn contains_range(self, other: Self) -> bool {
+        self.is_valid()
+            && other.is_valid()
+            && other.start >= self.start
+            && other.end() <= self.end()
+    }
+
+    /// Range covered by both `self` and `other` (same as `CMTimeRangeGetIntersection`).
+    ///
+    /// If the ranges do not overlap, the result is `CMTimeRange::ZERO`.
+    pub fn intersection(self, other: Self) -> Self {
+        if self.is_invalid() || other.is_invalid() {
+            return Self::INVALID;
+        }
+        let start = self.start.max(other.start);
+        let end = self.end().min(other.end());
+        if end <= start {
+            return Self::ZERO;
+        }
+        Self::from_start_end(start, end)
+    }
+
+    /// Smallest range containing both `self` and `other` (same as `CMTimeRangeGetUnion`).
+    ///
+    /// If the ranges do not overlap, the result also covers the gap between them.
+    pub fn union(self, other: Self) -> Self {
+        if self.is_invalid() || other.is_invalid() {
+            return Self::INVALID;
+        }
+        let start = self.start.min(other.start);
+        let end = self.end().max(other.end());
+        Self::from_start_end(start, end)
+    }
+
+    /// Nearest time inside the range (same as `CMTimeClampToRange`).
+    ///
+    /// Times before the start give the start, times after the end give the end, and other times are returned unmodified.
+    /// The result is invalid if the time is invalid or the range is invalid or empty.
+    pub fn clamp_time(self, time: CMTime) -> CMTime {
+        if time.is_invalid() || self.is_invalid() || self.is_empty() {
+            return CMTime::INVALID;
+        }
+        let end = self.end();
+        if time < self.start {
+            self.start
+        } else if time > end {
+            end
+        } else {
+            time
+        }
+    }
+}
+
+impl Default for CMTimeRange {
+    fn default() -> Self {
+        Self::INVALID
+    }
+}
+
+crate::encode_struct!(CMTimeRange = "?" {
+    start: CMTime,
+    duration: CMTime,
+});
+
+#[cfg(test)]
+mod cmtimerange_tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn range(start: i64, duration: i64, timescale: i32) -> CMTimeRange {
+        CMTimeRange::new(
+            CMTime::new(start, timescale),
+            CMTime::new(duration, timescale),
+        )
+    }
+
+    #[test]
+    fn size() {
+        assert_eq!(std::mem::size_of::<CMTimeRange>(), 48);
+    }
+
+    #[test]
+    fn encoding() {
+        use crate::base::Encode;
+        assert_eq!(CMTimeRange::encoding().to_string(), "{?={?=qiIq}{?=qiIq}}");
+    }
+
+    #[test]
+    fn validity() {
+        assert!(CMTimeRange::ZERO.is_valid());
+        assert!(CMTimeRange::ZERO.is_empty());
+        assert!(CMTimeRange::INVALID.is_invalid());
+        assert!(!CMTimeRange::INVALID.is_empty());
+        assert!(range(1, 2, 3).is_valid());
+        assert!(!range(1, 2, 3).is_empty());
+        assert!(range(1, -2, 3).is_invalid());
+        assert!(CMTimeRange::new(CMTime::new(1, 2), CMTime::new_with_epoch(1, 2, 1)).is_invalid());
+        assert!(CMTimeRange::new(CMTime::INVALID, CMTime::new(1, 2)).is_invalid());
+
+        let indefinite = CMTimeRange::new(CMTime::new(1, 2), CMTime::INDEFINITE);
+        assert!(indefinite.is_valid());
+        assert!(indefinite.is_indefinite());
+        assert!(!range(1, 2, 3).is_indefinite());
+        assert!(CMTimeRange::new(CMTime::ZERO, CMTime::POSITIVE_INFINITY).is_valid());
+    }
+
+    #[test]
+    fn end() {
+        assert_eq!(range(1, 2, 3).end(), CMTime::new(1, 1));
+        assert_eq!(CMTimeRange::INVALID.end(), CMTime::INVALID);
+        assert_eq!(
+            CMTimeRange::new(CMTime::ZERO, CMTime::POSITIVE_INFINITY).end(),
+            CMTime::POSITIVE_INFINITY
+        );
+        assert!(CMTimeRange::new(CMTime::ZERO, CMTime::INDEFINITE)
+            .end()
+            .is_indefinite());
+    }
+
+    #[test]
+    fn from_start_end() {
+        let range = CMTimeRange::from_start_end(CMTime::new(1, 2), CMTime::new(3, 4));
+        assert_eq!(range.start, CMTime::new(1, 2));
+        assert_eq!(range.duration, CMTime::new(1, 4));
+        let infinite = CMTimeRange::from_start_end(CMTime::new(1, 2), CMTime::POSITIVE_INFINITY);
+        assert!(infinite.is_valid());
+        assert!(infinite.duration.is_positive_infinity());
+        assert!(CMTimeRange::from_start_end(CMTime::new(1, 2), CMTime::ZERO).is_invalid());
+    }
+
+    #[test]
+    fn contains() {
+        let range = range(10, 10, 1);
+        assert!(!range.contains_time(CMTime::new(9, 1)));
+        assert!(range.contains_time(CMTime::new(10, 1)));
+        assert!(range.contains_time(CMTime::new(39, 2)));
+        assert!(!range.contains_time(CMTime::new(20, 1)), "end is excluded");
+        assert!(!range.contains_time(CMTime::INVALID));
+        assert!(!range.contains_time(CMTime::POSITIVE_INFINITY));
+        assert!(!CMTimeRange::ZERO.contains_time(CMTime::ZERO));
+
+        assert!(range.contains_range(range));
+        assert!(range.contains_range(self::range(20, 10, 2)));
+        assert!(!range.contains_range(self::range(9, 2, 1)));
+        assert!(!range.contains_range(self::range(19, 2, 1)));
+        assert!(!range.contains_range(CMTimeRange::INVALID));
+
+        let infinite = CMTimeRange::new(CMTime::ZERO, CMTime::POSITIVE_INFINITY);
+        assert!(infinite.contains_time(CMTime::new(i64::MAX, 1)));
+        assert!(infinite.contains_range(range));
+        assert!(!range.contains_range(infinite));
+    }
+
+    #[test]
+    fn intersection() {
+        assert_eq!(
+            range(0, 10, 1).intersection(range(5, 10, 1)),
+            range(5, 5, 1)
+        );
+        assert_eq!(range(0, 10, 1).intersection(range(2, 3, 1)), range(2, 3, 1));
+        assert_eq!(
+            range(0, 10, 1).intersection(range(10, 10, 1)),
+            CMTimeRange::ZERO,
+            "touching ranges do not overlap"
+        );
+        assert_eq!(
+            range(0, 10, 1).intersection(range(20, 10, 1)),
+            CMTimeRange::ZERO
+        );
+        assert!(range(0, 10, 1)
+            .intersection(CMTimeRange::INVALID)
+            .is_invalid());
+
+        let infinite = CMTimeRange::new(CMTime::new(5, 1), CMTime::POSITIVE_INFINITY);
+        assert_eq!(range(0, 10, 1).intersection(infinite), range(5, 5, 1));
+        assert_eq!(infinite.intersection(infinite), infinite);
+    }
+
+    #[test]
+    fn union() {
+        assert_eq!(range(0, 10, 1).union(range(5, 10, 1)), range(0, 15, 1));
+        assert_eq!(range(0, 10, 1).union(range(2, 3, 1)), range(0, 10, 1));
+        assert_eq!(range(0, 10, 1).union(range(20, 10, 1)), range(0, 30, 1));
+        assert!(range(0, 10, 1).union(CMTimeRange::INVALID).is_invalid());
+
+        let infinite = CMTimeRange::new(CMTime::new(5, 1), CMTime::POSITIVE_INFINITY);
+        assert_eq!(
+            range(0, 10, 1).union(infinite),
+            CMTimeRange::new(CMTime::ZERO, CMTime::POSITIVE_INFINITY)
+        );
+    }
+
+    #[test]
+    fn clamp_time() {
+        let range = range(10, 10, 1);
+        assert_eq!(range.clamp_time(CMTime::new(5, 1)), CMTime::new(10, 1));
+        assert_eq!(range.clamp_time(CMTime::new(25, 1)), CMTime::new(20, 1));
+        assert_eq!(range.clamp_time(CMTime::new(20, 1)), CMTime::new(20, 1));
+        assert_eq!(range.clamp_time(CMTime::new(15, 1)), CMTime::new(15, 1));
+        assert_eq!(
+            range.clamp_time(CMTime::NEGATIVE_INFINITY),
+            CMTime::new(10, 1)
+        );
+        assert!(range.clamp_time(CMTime::INVALID).is_invalid());
+        assert!(CMTimeRange::ZERO.clamp_time(CMTime::ZERO).is_invalid());
+        assert!(CMTimeRange::INVALID.clamp_time(CMTime::ZERO).is_invalid());
+
+        // Unmodified means with the same timescale.
+        let time = CMTime::new(30, 2);
+        assert_eq!(range.clamp_time(time).timescale, time.timescale);
+    }
+
+    const TIMESCALES: [i32; 6] = [1, 2, 3, 30, 600, 44100];
+
+    fn numeric_time() -> impl Strategy<Value = CMTime> {
+        (-10_000i64..10_000, prop::sample::select(&TIMESCALES[..]))
+            .prop_map(|(value, timescale)| CMTime::new(value, timescale))
+    }
+
+    fn time() -> impl Strategy<Value = CMTime> {
+        prop_oneof![
+            8 => numeric_time(),
+            1 => Just(CMTime::POSITIVE_INFINITY),
+            1 => Just(CMTime::NEGATIVE_INFINITY),
+        ]
+    }
+
+    fn valid_range() -> impl Strategy<Value = CMTimeRange> {
+        let duration = prop_oneof![
+            8 => (0i64..10_000, prop::sample::select(&TIMESCALES[..]))
+                .prop_map(|(value, timescale)| CMTime::new(value, timescale)),
+            1 => Just(CMTime::POSITIVE_INFINITY),
+        ];
+        (numeric_time(), duration).prop_map(|(start, duration)| CMTimeRange::new(start, duration))
+    }
+
+    proptest! {
+        #[test]
+        fn end_of_from_start_end(start in numeric_time(), duration in numeric_time()) {
+            let end = start + CMTime::new(duration.value.0.abs(), duration.timescale.0);
+            prop_assert_eq!(CMTimeRange::from_start_end(start, end).end(), end);
+        }
+
+        #[test]
+        fn commutative(a in valid_range(), b in valid_range()) {
+            prop_assert_eq!(a.intersection(b), b.intersection(a));
+            prop_assert_eq!(a.union(b), b.union(a));
+        }
+
+        #[test]
+        fn intersection_contains_common_times(a in valid_range(), b in valid_range(), time in time()) {
+            let intersection = a.intersection(b);
+            prop_assert!(intersection.is_valid());
+            prop_assert_eq!(
+                intersection.contains_time(time),
+                a.contains_time(time) && b.contains_time(time)
+            );
+            if !intersection.is_empty() {
+                prop_assert!(a.contains_range(intersection));
+                prop_assert!(b.contains_range(intersection));
+            }
+        }
+
+        #[test]
+        fn union_contains_both(a in valid_range(), b in valid_range(), time in time()) {
+            let union = a.union(b);
+            prop_assert!(union.is_valid());
+            prop_assert!(union.contains_range(a));
+            prop_assert!(union.contains_range(b));
+            if a.contains_time(time) || b.contains_time(time) {
+                prop_assert!(union.contains_time(time));
+            }
+        }
+
+        #[test]
+        fn clamped_time_is_in_range(range in valid_range(), time in time()) {
+            let clamped = range.clamp_time(time);
+            if range.is_empty() {
+                prop_assert!(clamped.is_invalid());
+            } else {
+                prop_assert!(clamped >= range.start);
+                prop_assert!(clamped <= range.end());
+                if range.contains_time(time) {
+                    prop_assert_eq!(clamped, time);
+                }
+            }
+        }
+    }
+}
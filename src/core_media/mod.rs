@@ -0,0 +1,25 @@
+mod cmmediatype;
+mod cmtime;
+mod cmtimerange;
+mod timecode;
+pub use cmmediatype::*;
+pub use cmtime::*;
+pub use cmtimerange::*;
+pub use timecode::*;
+
+// The format descriptions still use the previous pointer API, and need the CoreMedia framework (only available on Apple platforms).
+// #[cfg(target_vendor = "apple")]
+// mod cmformatdescription;
+// #[cfg(target_vendor = "apple")]
+// pub use cmformatdescription::*;
+
+// pub(crate) mod prelude {
+//     pub use super::CMAudioFormatDescriptionInterface;
+//     pub use super::CMClosedCaptionFormatDescriptionInterface;
+//     pub use super::CMFormatDescriptionInterface;
+//     pub use super::CMMetadataFormatDescriptionInterface;
+//     pub use super::CMMuxedFormatDescriptionInterface;
+//     pub use super::CMTextFormatDescriptionInterface;
+//     pub use super::CMTimeCodeFormatDescriptionInterface;
+//     pub use super::CMVideoFormatDescriptionInterface;
+// }
@@ -0,0 +1,398 @@
+//-------------------------------------------------------------------
+// SMPTE timecodes
+//
+// Conversion between frame numbers, CMTime and HH:MM:SS:FF timecodes,
+// following the rules used by CoreMedia's timecode format descriptions (kCMTimeCodeFlag_DropFrame...).
+
+use super::CMTime;
+use std::str::FromStr;
+
+/// Frame rate of a timecode, similar to what is stored in a timecode format description.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TimecodeRate {
+    frame_duration: CMTime,
+    frame_quanta: u32,
+    drop_frame: bool,
+}
+
+impl TimecodeRate {
+    pub const FPS_23_976: Self = Self::new(CMTime::new(1001, 24000), 24, false).unwrap();
+    pub const FPS_24: Self = Self::new(CMTime::new(1, 24), 24, false).unwrap();
+    pub const FPS_25: Self = Self::new(CMTime::new(1, 25), 25, false).unwrap();
+    pub const FPS_29_97_DROP_FRAME: Self = Self::new(CMTime::new(1001, 30000), 30, true).unwrap();
+    pub const FPS_29_97: Self = Self::new(CMTime::new(1001, 30000), 30, false).unwrap();
+    pub const FPS_30: Self = Self::new(CMTime::new(1, 30), 30, false).unwrap();
+    pub const FPS_50: Self = Self::new(CMTime::new(1, 50), 50, false).unwrap();
+    pub const FPS_59_94_DROP_FRAME: Self = Self::new(CMTime::new(1001, 60000), 60, true).unwrap();
+    pub const FPS_59_94: Self = Self::new(CMTime::new(1001, 60000), 60, false).unwrap();
+    pub const FPS_60: Self = Self::new(CMTime::new(1, 60), 60, false).unwrap();
+
+    /// `None` if `frame_quanta` is 0, or if `drop_frame` is set and `frame_quanta` is not a multiple of 30.
+    pub const fn new(frame_duration: CMTime, frame_quanta: u32, drop_frame: bool) -> Option<Self> {
+        if frame_quanta == 0 || (drop_frame && !frame_quanta.is_multiple_of(30)) {
+            return None;
+        }
+        Some(Self {
+            frame_duration,
+            frame_quanta,
+            drop_frame,
+        })
+    }
+
+    /// Duration of a single frame.
+    pub const fn frame_duration(&self) -> CMTime {
+        self.frame_duration
+    }
+
+    /// Number of frames per (timecode) second, for example 30 for 29.97.
+    pub const fn frame_quanta(&self) -> u32 {
+        self.frame_quanta
+    }
+
+    /// Whether frame numbers are skipped to keep the timecode in sync with the clock.
+    pub const fn drop_frame(&self) -> bool {
+        self.drop_frame
+    }
+
+    /// Number of frame numbers skipped at the start of each minute (except every tenth minute).
+    fn dropped_frames_per_minute(self) -> u32 {
+        if self.drop_frame {
+            self.frame_quanta / 15
+        } else {
+            0
+        }
+    }
+}
+
+/// A SMPTE timecode, displayed as `HH:MM:SS:FF` (or `HH:MM:SS;FF` for drop frame timecodes).
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Timecode {
+    pub hours: u32,
+    pub minutes: u32,
+    pub seconds: u32,
+    pub frames: u32,
+    pub drop_frame: bool,
+}
+
+impl Timecode {
+    pub const fn new(
+        hours: u32,
+        minutes: u32,
+        seconds: u32,
+        frames: u32,
+        drop_frame: bool,
+    ) -> Self {
+        Self {
+            hours,
+            minutes,
+            seconds,
+            frames,
+            drop_frame,
+        }
+    }
+
+    /// Timecode of the frame with the given number, the first frame being frame 0.
+    pub fn from_frame_number(frame_number: u32, rate: TimecodeRate) -> Self {
+        let quanta = u64::from(rate.frame_quanta);
+        let dropped = u64::from(rate.dropped_frames_per_minute());
+        let mut frame_number = u64::from(frame_number);
+        if dropped > 0 {
+            let frames_per_ten_minutes = quanta * 600 - dropped * 9;
+            let frames_per_minute = quanta * 60 - dropped;
+            let ten_minutes = frame_number / frames_per_ten_minutes;
+            let remainder = frame_number % frames_per_ten_minutes;
+            // Add back the frame numbers skipped since the start.
+            frame_number += dropped * 9 * ten_minutes;
+            if remainder > dropped {
+                frame_number += dropped * ((remainder - dropped) / frames_per_minute);
+            }
+        }
+        Self {
+            hours: (frame_number / (quanta * 3600)) as u32,
+            minutes: (frame_number / (quanta * 60) % 60) as u32,
+            seconds: (frame_number / quanta % 60) as u32,
+            frames: (frame_number % quanta) as u32,
+            drop_frame: rate.drop_frame,
+        }
+    }
+
+    /// Whether the timecode can be used with that rate: same drop frame mode, frames in range
+    /// and not one of the frame numbers skipped by drop frame timecodes.
+    pub fn is_valid_for(&self, rate: TimecodeRate) -> bool {
+        let dropped = rate.dropped_frames_per_minute();
+        self.drop_frame == rate.drop_frame
+            && self.minutes < 60
+            && self.seconds < 60
+            && self.frames < rate.frame_quanta
+            && !(self.seconds == 0 && !self.minutes.is_multiple_of(10) && self.frames < dropped)
+    }
+
+    /// Number of the frame with this timecode, `None` if it is not valid for the rate or too large.
+    pub fn frame_number(&self, rate: TimecodeRate) -> Option<u32> {
+        if !self.is_valid_for(rate) {
+            return None;
+        }
+        let quanta = u64::from(rate.frame_quanta);
+        let dropped = u64::from(rate.dropped_frames_per_minute());
+        let total_minutes = u64::from(self.hours) * 60 + u64::from(self.minutes);
+        let total_seconds = total_minutes * 60 + u64::from(self.seconds);
+        let frame_number = total_seconds * quanta + u64::from(self.frames)
+            - dropped * (total_minutes - total_minutes / 10);
+        u32::try_from(frame_number).ok()
+    }
+
+    /// Timecode of the frame displayed at `time`, `None` if the time is not numeric or negative,
+    /// or if the frame duration is not usable.
+    pub fn from_time(time: CMTime, rate: TimecodeRate) -> Option<Self> {
+        let frame_duration = rate.frame_duration;
+        if !time.is_numeric() || time.timescale.0 <= 0 || !frame_duration.is_numeric() {
+            return None;
+        }
+        if time.value.0 < 0 || frame_duration.value.0 <= 0 || frame_duration.timescale.0 <= 0 {
+            return None;
+        }
+        // time / frame_duration, rounded down.
+        let numerator = i128::from(time.value.0) * i128::from(frame_duration.timescale.0);
+        let denominator = i128::from(time.timescale.0) * i128::from(frame_duration.value.0);
+        let frame_number = u32::try_from(numerator / denominator).ok()?;
+        Some(Self::from_frame_number(frame_number, rate))
+    }
+
+    /// Time at which the frame with this timecode starts.
+    pub fn to_time(&self, rate: TimecodeRate) -> Option<CMTime> {
+        let frame_number = self.frame_number(rate)?;
+        let value = rate
+            .frame_duration
+            .value
+            .0
+            .checked_mul(i64::from(frame_number))?;
+        Some(CMTime::new(value, rate.frame_duration.timescale.0))
+    }
+}
+
+impl std::fmt::Display for Timecode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let separator = if self.drop_frame { ';' } else { ':' };
+        write!(
+            f,
+            "{:02}:{:02}:{:02}{}{:02}",
+            self.hours, self.minutes, self.seconds, separator, self.frames
+        )
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ParseTimecodeError;
+
+impl std::fmt::Display for ParseTimecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("expecting a timecode in the HH:MM:SS:FF or HH:MM:SS;FF format")
+    }
+}
+
+impl std::error::Error for ParseTimecodeError {}
+
+/// Parses `HH:MM:SS:FF`, or `HH:MM:SS;FF` for drop frame timecodes.
+/// The values are not checked against any rate, see [`Timecode::is_valid_for`].
+impl FromStr for Timecode {
+    type Err = ParseTimecodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (rest, frames, drop_frame) = if let Some((rest, frames)) = s.rsplit_once(';') {
+            (rest, frames, true)
+        } else if let Some((rest, frames)) = s.rsplit_once(':') {
+            (rest, frames, false)
+        } else {
+            return Err(ParseTimecodeError);
+        };
+        let mut components = rest.split(':');
+        let next_number = |component: Option<&str>| -> Result<u32, ParseTimecodeError> {
+            match component {
+                Some(digits)
+                    if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) =>
+                {
+                    digits.parse().map_err(|_| ParseTimecodeError)
+                }
+                _ => Err(ParseTimecodeError),
+            }
+        };
+        let hours = next_number(components.next())?;
+        let minutes = next_number(components.next())?;
+        let seconds = next_number(components.next())?;
+        let frames = next_number(Some(frames))?;
+        if components.next().is_some() {
+            return Err(ParseTimecodeError);
+        }
+        Ok(Self::new(hours, minutes, seconds, frames, drop_frame))
+    }
+}
+
+#[cfg(test)]
+mod timecode_tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const RATES: [TimecodeRate; 10] = [
+        TimecodeRate::FPS_23_976,
+        TimecodeRate::FPS_24,
+        TimecodeRate::FPS_25,
+        TimecodeRate::FPS_29_97_DROP_FRAME,
+        TimecodeRate::FPS_29_97,
+        TimecodeRate::FPS_30,
+        TimecodeRate::FPS_50,
+        TimecodeRate::FPS_59_94_DROP_FRAME,
+        TimecodeRate::FPS_59_94,
+        TimecodeRate::FPS_60,
+    ];
+
+    fn timecode(frame_number: u32, rate: TimecodeRate) -> String {
+        Timecode::from_frame_number(frame_number, rate).to_string()
+    }
+
+    #[test]
+    fn non_drop_frame() {
+        let rate = TimecodeRate::FPS_25;
+        assert_eq!(timecode(0, rate), "00:00:00:00");
+        assert_eq!(timecode(24, rate), "00:00:00:24");
+        assert_eq!(timecode(25, rate), "00:00:01:00");
+        assert_eq!(timecode(25 * 3600 + 25 * 61 + 3, rate), "01:01:01:03");
+        assert_eq!(timecode(25 * 3600 * 100, rate), "100:00:00:00");
+        assert_eq!(timecode(1800, TimecodeRate::FPS_29_97), "00:01:00:00");
+    }
+
+    #[test]
+    fn drop_frame_29_97() {
+        let rate = TimecodeRate::FPS_29_97_DROP_FRAME;
+        assert_eq!(timecode(1799, rate), "00:00:59;29");
+        assert_eq!(timecode(1800, rate), "00:01:00;02");
+        assert_eq!(timecode(3597, rate), "00:01:59;29");
+        assert_eq!(timecode(3598, rate), "00:02:00;02");
+        assert_eq!(timecode(17981, rate), "00:09:59;29");
+        assert_eq!(timecode(17982, rate), "00:10:00;00");
+        assert_eq!(timecode(17983, rate), "00:10:00;01");
+        assert_eq!(timecode(19781, rate), "00:10:59;29");
+        assert_eq!(timecode(19782, rate), "00:11:00;02");
+        // 29.97 drop frame timecodes stay in sync with the clock every 10 minutes.
+        assert_eq!(timecode(107892, rate), "01:00:00;00");
+    }
+
+    #[test]
+    fn drop_frame_59_94() {
+        let rate = TimecodeRate::FPS_59_94_DROP_FRAME;
+        assert_eq!(timecode(3599, rate), "00:00:59;59");
+        assert_eq!(timecode(3600, rate), "00:01:00;04");
+        assert_eq!(timecode(35964, rate), "00:10:00;00");
+        assert_eq!(timecode(215784, rate), "01:00:00;00");
+    }
+
+    #[test]
+    fn invalid_rates() {
+        assert_eq!(TimecodeRate::new(CMTime::new(1, 25), 0, false), None);
+        assert_eq!(TimecodeRate::new(CMTime::new(1, 25), 0, true), None);
+        assert_eq!(TimecodeRate::new(CMTime::new(1, 24), 24, true), None);
+        assert_eq!(TimecodeRate::new(CMTime::new(1, 25), 25, true), None);
+        let rate = TimecodeRate::new(CMTime::new(1, 120), 120, true).unwrap();
+        assert_eq!(rate.frame_quanta(), 120);
+        assert!(rate.drop_frame());
+    }
+
+    #[test]
+    fn dropped_frame_numbers_are_invalid() {
+        let rate = TimecodeRate::FPS_29_97_DROP_FRAME;
+        assert_eq!(Timecode::new(0, 1, 0, 0, true).frame_number(rate), None);
+        assert_eq!(Timecode::new(0, 1, 0, 1, true).frame_number(rate), None);
+        assert_eq!(
+            Timecode::new(0, 1, 0, 2, true).frame_number(rate),
+            Some(1800)
+        );
+        assert_eq!(
+            Timecode::new(0, 10, 0, 0, true).frame_number(rate),
+            Some(17982)
+        );
+        assert_eq!(Timecode::new(0, 0, 0, 30, true).frame_number(rate), None);
+        assert_eq!(Timecode::new(0, 60, 0, 0, true).frame_number(rate), None);
+        // Drop frame mode must match.
+        assert_eq!(Timecode::new(0, 0, 1, 0, false).frame_number(rate), None);
+        assert_eq!(
+            Timecode::new(0, 0, 1, 0, true).frame_number(TimecodeRate::FPS_29_97),
+            None
+        );
+    }
+
+    #[test]
+    fn times() {
+        let rate = TimecodeRate::FPS_29_97_DROP_FRAME;
+        let frame_duration = rate.frame_duration();
+        let time = CMTime::new(1800 * 1001, 30000);
+        let timecode = Timecode::from_time(time, rate).unwrap();
+        assert_eq!(timecode.to_string(), "00:01:00;02");
+        assert_eq!(timecode.to_time(rate), Some(time));
+        // Any time during the frame gives the same timecode.
+        let later = time + CMTime::new(1, 30000);
+        assert_eq!(Timecode::from_time(later, rate), Some(timecode));
+        let next = time + frame_duration;
+        assert_eq!(
+            Timecode::from_time(next, rate).unwrap().to_string(),
+            "00:01:00;03"
+        );
+
+        assert_eq!(
+            Timecode::from_time(CMTime::new(3, 2), TimecodeRate::FPS_25)
+                .unwrap()
+                .to_string(),
+            "00:00:01:12"
+        );
+        assert_eq!(Timecode::from_time(CMTime::new(-1, 2), rate), None);
+        assert_eq!(Timecode::from_time(CMTime::INDEFINITE, rate), None);
+        assert_eq!(Timecode::from_time(CMTime::INVALID, rate), None);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("01:02:03:04".parse(), Ok(Timecode::new(1, 2, 3, 4, false)));
+        assert_eq!("00:01:00;02".parse(), Ok(Timecode::new(0, 1, 0, 2, true)));
+        assert_eq!(
+            "100:00:00:00".parse(),
+            Ok(Timecode::new(100, 0, 0, 0, false))
+        );
+        for invalid in [
+            "",
+            "00:00:00",
+            "00:00:00:00:00",
+            "00;00:00:00",
+            "00:00:00;",
+            "aa:00:00:00",
+            "+1:00:00:00",
+            "00:00:00:99999999999",
+        ] {
+            assert_eq!(
+                invalid.parse::<Timecode>(),
+                Err(ParseTimecodeError),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn frame_number_round_trip(frame_number in 0u32..100_000_000, rate_index in 0..RATES.len()) {
+            let rate = RATES[rate_index];
+            let timecode = Timecode::from_frame_number(frame_number, rate);
+            prop_assert!(timecode.is_valid_for(rate));
+            prop_assert_eq!(timecode.frame_number(rate), Some(frame_number));
+            prop_assert_eq!(timecode.to_string().parse(), Ok(timecode));
+            let time = timecode.to_time(rate).unwrap();
+            prop_assert_eq!(Timecode::from_time(time, rate), Some(timecode));
+        }
+
+        #[test]
+        fn consecutive_frames(frame_number in 0u32..100_000_000, rate_index in 0..RATES.len()) {
+            let rate = RATES[rate_index];
+            let timecode = Timecode::from_frame_number(frame_number, rate);
+            let next = Timecode::from_frame_number(frame_number + 1, rate);
+            let position = |t: Timecode| (t.hours, t.minutes, t.seconds, t.frames);
+            prop_assert!(position(timecode) < position(next));
+        }
+    }
+}
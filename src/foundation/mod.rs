@@ -1,13 +1,13 @@
 use crate::base::{
-    AsRaw, IsKindOf, NSUInteger, NonStatic, ObjCClass, Ownership, Ptr, RawClassPtr, RawObjPtr,
-    Retained, Type, TypeKind, BOOL,
+    AsRaw, IsKindOf, NSInteger, NSUInteger, NonStatic, ObjCClass, Ownership, Ptr, RawClassPtr,
+    RawObjPtr, Retained, Type, TypeKind, BOOL,
 };
 
 mod nsarray;
 mod nsdictionary;
 mod nsstring;
 pub use nsarray::*;
-// pub use nsdictionary::*;
+pub use nsdictionary::*;
 pub use nsstring::*;
 // pub(crate) mod prelude;
 
@@ -150,6 +150,98 @@ mod tests {
         assert!(obj1.is_equal(&obj2));
         assert!(obj1 == obj2);
     }
+
+    #[test]
+    fn reflection() {
+        use crate::base::{Class, Protocol};
+        use crate::sel;
+
+        let class = Class::get("NSString").unwrap();
+        assert_eq!(class, Class::of::<NSString>());
+        assert_eq!(class.name(), c"NSString");
+        assert_eq!(class.superclass(), Some(Class::of::<NSObject>()));
+        assert!(class.responds_to(sel!(length)));
+        // Inherited from NSObject.
+        assert!(class.responds_to(sel!(hash)));
+        assert!(!class.responds_to(sel!(objectAtIndex:)));
+        assert!(class
+            .instance_methods()
+            .iter()
+            .any(|method| method.sel() == sel!(characterAtIndex:)));
+        assert!(class.metaclass().responds_to(sel!(stringWithUTF8String:)));
+
+        let protocol = Protocol::get("NSCopying").unwrap();
+        assert_eq!(protocol.name(), c"NSCopying");
+        assert!(class.conforms_to(protocol));
+        assert!(Class::of::<NSObject>()
+            .adopted_protocols()
+            .iter()
+            .any(|protocol| protocol.name() == c"NSObject"));
+        assert_eq!(Class::get("NSDoesNotExist"), None);
+    }
+
+    struct Hashed {
+        _private: std::marker::PhantomData<*const ()>,
+    }
+
+    impl Type for Hashed {
+        const KIND: TypeKind = TypeKind::ObjC;
+    }
+
+    impl ObjCClass for Hashed {
+        fn class() -> RawClassPtr {
+            crate::base::declared_class::<Self>()
+        }
+    }
+
+    unsafe impl IsKindOf<NSObject> for Hashed {}
+    impl NSObjectProtocol for Hashed {}
+    impl NSObjectInterface for Hashed {}
+
+    unsafe impl crate::base::DeclareClass for Hashed {
+        type Super = NSObject;
+        type State = NSUInteger;
+        const NAME: &'static str = "ChocoTestsHashed";
+
+        fn declare(decl: &mut crate::base::ClassDecl<Self>) {
+            extern "C-unwind" fn hash(this: &Hashed, _sel: crate::base::Sel) -> NSUInteger {
+                *this.state()
+            }
+
+            unsafe { decl.add_method(crate::sel!(hash), hash as extern "C-unwind" fn(&_, _) -> _) };
+        }
+    }
+
+    #[test]
+    fn declared_subclass() {
+        use crate::base::DeclareClass;
+
+        let hashed = Hashed::with_state(42);
+        assert_eq!(hashed.hash(), 42);
+        assert!(hashed.is_kind_of(NSObject::class()));
+        let obj: Ptr<NSObject> = hashed.upcast();
+        assert_eq!(obj.hash(), 42);
+    }
+
+    #[test]
+    fn associated_values() {
+        use crate::base::AssociationKey;
+        use std::sync::Arc;
+
+        static KEY: AssociationKey = AssociationKey::new();
+
+        let value = Arc::new(());
+        let obj = NSObject::new();
+        obj.set_associated(&KEY, value.clone());
+        assert!(Arc::ptr_eq(
+            &obj.get_associated::<Arc<()>>(&KEY).unwrap(),
+            &value
+        ));
+        assert!(obj.get_associated::<String>(&KEY).is_none());
+        assert_eq!(Arc::strong_count(&value), 2);
+        drop(obj);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
 }
 
 //-------------------------------------------------------------------
@@ -215,6 +307,13 @@ struct NSFastEnumerationState {
     extra: [usize; 5],
 }
 
+crate::encode_struct!(NSFastEnumerationState = "?" {
+    state: usize,
+    items: *mut Option<RawObjPtr>,
+    mutations: *mut usize,
+    extra: [usize; 5],
+});
+
 impl NSFastEnumerationState {
     fn new() -> Self {
         Self {
@@ -729,114 +828,204 @@ unsafe impl<O: NonStatic> Sync for Ptr<NSDate, O> {}
 // //     }
 // // }
 
-// // //-------------------------------------------------------------------
-// // // NSError
+//-------------------------------------------------------------------
+// NSError
 
-// // extern "C" {
-// //     fn choco_Foundation_NSError_class() -> objc::ClassPtr;
-// //     fn choco_Foundation_NSErrorInterface_instance_code(self_: RawObjPtr) -> NSInteger;
-// //     fn choco_Foundation_NSErrorInterface_instance_domain(
-// //         self_: RawObjPtr,
-// //     ) -> Option<RawObjPtr>;
-// //     fn choco_Foundation_NSErrorInterface_instance_localizedDescription(
-// //         self_: RawObjPtr,
-// //     ) -> Option<RawObjPtr>;
-// // }
+extern "C" {
+    fn choco_Foundation_NSError_class() -> RawClassPtr;
+    fn choco_Foundation_NSErrorInterface_instance_code(self_: RawObjPtr) -> NSInteger;
+    fn choco_Foundation_NSErrorInterface_instance_domain(self_: RawObjPtr) -> Option<RawObjPtr>;
+    fn choco_Foundation_NSErrorInterface_instance_localizedDescription(
+        self_: RawObjPtr,
+    ) -> Option<RawObjPtr>;
+}
 
-// // pub trait NSErrorInterface: NSObjectInterface
-// // where
-// //     Self: NSCopyingProtocol,
-// // {
-// //     fn code(&self) -> NSInteger {
-// //         let raw_self = self.as_raw_ptr();
-// //         unsafe { choco_Foundation_NSErrorInterface_instance_code(raw_self) }
-// //     }
+pub trait NSErrorInterfaceInstanceMethods: AsRaw {
+    fn code(&self) -> NSInteger {
+        let raw_self = self.as_raw();
+        unsafe { choco_Foundation_NSErrorInterface_instance_code(raw_self) }
+    }
 
-// //     fn domain(&self) -> NSString {
-// //         let raw_self = self.as_raw_ptr();
-// //         unsafe {
-// //             let owned_ptr = choco_Foundation_NSErrorInterface_instance_domain(raw_self)
-// //                 .unwrap()
-// //                 .consider_owned();
-// //             NSString::from_owned_ptr_unchecked(owned_ptr)
-// //         }
-// //     }
+    fn domain(&self) -> Ptr<NSString, Retained> {
+        let raw_self = self.as_raw();
+        unsafe {
+            let raw = choco_Foundation_NSErrorInterface_instance_domain(raw_self);
+            Ptr::from_autoreleased_return_value(raw).unwrap()
+        }
+    }
 
-// //     fn localized_description(&self) -> NSString {
-// //         let raw_self = self.as_raw_ptr();
-// //         unsafe {
-// //             let owned_ptr =
-// //                 choco_Foundation_NSErrorInterface_instance_localizedDescription(raw_self)
-// //                     .unwrap()
-// //                     .consider_owned();
-// //             NSString::from_owned_ptr_unchecked(owned_ptr)
-// //         }
-// //     }
-// // }
+    fn localized_description(&self) -> Ptr<NSString, Retained> {
+        let raw_self = self.as_raw();
+        unsafe {
+            let raw = choco_Foundation_NSErrorInterface_instance_localizedDescription(raw_self);
+            Ptr::from_autoreleased_return_value(raw).unwrap()
+        }
+    }
+}
 
-// // pub struct NSError {
-// //     ptr: objc::OwnedPtr,
-// // }
+pub trait NSErrorInterface: NSObjectInterface {}
 
-// // impl FromOwned for NSError {
-// //     unsafe fn from_owned_ptr_unchecked(ptr: objc::OwnedPtr) -> Self {
-// //         Self { ptr }
-// //     }
-// // }
+impl<T, O> NSErrorInterfaceInstanceMethods for Ptr<T, O>
+where
+    T: NSErrorInterface,
+    O: Ownership,
+{
+}
 
-// // impl NSObjectProtocol for NSError {
-// //     type Owned = Self;
+pub struct NSError {}
 
-// //     fn class() -> objc::ClassPtr {
-// //         unsafe { choco_Foundation_NSError_class() }
-// //     }
-// // }
+impl Type for NSError {
+    const KIND: TypeKind = TypeKind::ObjC;
+}
 
-// // impl NSObjectInterface for NSError {}
-// // impl NSErrorInterface for NSError {}
-// // impl NSCopyingProtocol for NSError {
-// //     type Immutable = Self;
-// // }
-// // impl ValidObjCGeneric for NSError {}
-// // unsafe impl IsKindOf<NSObject> for NSError {}
-
-// // // A NSError is immutable so can be shared between threads.
-// // unsafe impl Send for NSError {}
-// // unsafe impl Sync for NSError {}
-
-// // impl std::fmt::Debug for NSError {
-// //     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-// //         let description = self.debug_description();
-// //         let rusty = description.to_string_lossy();
-// //         f.write_str(&rusty)
-// //     }
-// // }
+impl ObjCClass for NSError {
+    fn class() -> RawClassPtr {
+        unsafe { choco_Foundation_NSError_class() }
+    }
+}
+impl NSObjectProtocol for NSError {}
+impl NSObjectInterface for NSError {}
+impl NSErrorInterface for NSError {}
+unsafe impl IsKindOf<NSObject> for NSError {}
+
+// A NSError is immutable so can be shared between threads.
+unsafe impl<O: NonStatic> Send for Ptr<NSError, O> {}
+unsafe impl<O: NonStatic> Sync for Ptr<NSError, O> {}
+
+/// Makes a `Result` from the value and error a method or completion handler got.
+///
+/// # Safety
+/// If non null, `raw_unowned_error` must point to a `NSError` we do not own (probably autoreleased,
+/// or an argument of the completion handler), that must still be alive.
+pub unsafe fn make_value_result_unchecked<T>(
+    value: T,
+    raw_unowned_error: Option<RawObjPtr>,
+) -> Result<T, Ptr<NSError, Retained>> {
+    match raw_unowned_error {
+        None => Ok(value),
+        Some(raw_error) => Err(raw_error.retain()),
+    }
+}
 
-// // /// # Safety
-// // /// - if non null, raw_ptr must be owned and of type T.
-// // /// - if non null, raw_unowned_error must not be owned (probably autoreleased) and point to a NSError.
-// // pub(crate) unsafe fn make_object_result_unchecked<T: NSObjectInterface>(
-// //     raw_ptr: Option<RawObjPtr>,
-// //     raw_unowned_error: Option<RawObjPtr>,
-// // ) -> Result<T::Owned, NSError> {
-// //     // Create the object before checking the error,
-// //     // because if both the new object and error are not null,
-// //     // we want to the object to be properly released.
-// //     let obj = raw_ptr.map(|raw_ptr| T::Owned::from_owned_ptr_unchecked(raw_ptr.consider_owned()));
-// //     match raw_unowned_error {
-// //         None => Ok(obj.expect("expecting non null return value pointer when no error")),
-// //         Some(raw_error) => Err(NSError::from_owned_ptr_unchecked(raw_error.retain())),
-// //     }
-// // }
+//-------------------------------------------------------------------
+// NSException
 
-// // /// # Safety
-// // /// - if non null, raw_unowned_error must not be owned (probably autoreleased) and point to a NSError.
-// // pub(crate) unsafe fn make_value_result_unchecked<T>(
-// //     value: T,
-// //     raw_unowned_error: Option<RawObjPtr>,
-// // ) -> Result<T, NSError> {
-// //     match raw_unowned_error {
-// //         None => Ok(value),
-// //         Some(raw_error) => Err(NSError::from_owned_ptr_unchecked(raw_error.retain())),
-// //     }
-// // }
+extern "C" {
+    fn choco_Foundation_NSException_class() -> RawClassPtr;
+    fn choco_Foundation_NSExceptionInterface_instance_name(self_: RawObjPtr) -> Option<RawObjPtr>;
+    fn choco_Foundation_NSExceptionInterface_instance_reason(self_: RawObjPtr)
+        -> Option<RawObjPtr>;
+    fn choco_Foundation_NSExceptionInterface_instance_userInfo(
+        self_: RawObjPtr,
+    ) -> Option<RawObjPtr>;
+}
+
+// Rust panics are let through, so it has to be "C-unwind" too.
+extern "C-unwind" {
+    fn choco_Foundation_catchException(
+        f: unsafe extern "C-unwind" fn(context: *mut std::ffi::c_void),
+        context: *mut std::ffi::c_void,
+    ) -> Option<RawObjPtr>;
+}
+
+pub trait NSExceptionInterfaceInstanceMethods: AsRaw {
+    fn name(&self) -> Ptr<NSString, Retained> {
+        let raw_self = self.as_raw();
+        unsafe {
+            let raw = choco_Foundation_NSExceptionInterface_instance_name(raw_self).unwrap();
+            Ptr::from_raw_unchecked(raw)
+        }
+    }
+
+    fn reason(&self) -> Option<Ptr<NSString, Retained>> {
+        let raw_self = self.as_raw();
+        unsafe {
+            choco_Foundation_NSExceptionInterface_instance_reason(raw_self)
+                .map(|raw| Ptr::from_raw_unchecked(raw))
+        }
+    }
+
+    fn user_info(&self) -> Option<Ptr<NSDictionary<NSObject, NSObject>, Retained>> {
+        let raw_self = self.as_raw();
+        unsafe {
+            choco_Foundation_NSExceptionInterface_instance_userInfo(raw_self)
+                .map(|raw| Ptr::from_raw_unchecked(raw))
+        }
+    }
+}
+
+pub trait NSExceptionInterface: NSObjectInterface {}
+
+impl<T, O> NSExceptionInterfaceInstanceMethods for Ptr<T, O>
+where
+    T: NSExceptionInterface,
+    O: Ownership,
+{
+}
+
+pub struct NSException {}
+
+impl Type for NSException {
+    const KIND: TypeKind = TypeKind::ObjC;
+}
+
+impl ObjCClass for NSException {
+    fn class() -> RawClassPtr {
+        unsafe { choco_Foundation_NSException_class() }
+    }
+}
+impl NSObjectProtocol for NSException {}
+impl NSObjectInterface for NSException {}
+impl NSExceptionInterface for NSException {}
+unsafe impl IsKindOf<NSObject> for NSException {}
+
+// A NSException is immutable so can be shared between threads.
+unsafe impl<O: NonStatic> Send for Ptr<NSException, O> {}
+unsafe impl<O: NonStatic> Sync for Ptr<NSException, O> {}
+
+/// Calls `f`, returning the `NSException` raised while running it, if any.
+///
+/// Most shims abort the process on exceptions, the exceptions can only come from
+/// the ones declared `extern "C-unwind"` (used by the `try_` methods).
+/// If an exception is raised, the Rust code it unwinds through is left the same way as for a panic.
+/// Panics in `f` are not caught.
+pub fn catch_exception<F, R>(f: F) -> Result<R, Ptr<NSException, Retained>>
+where
+    F: FnOnce() -> R,
+{
+    unsafe extern "C-unwind" fn call<F, R>(context: *mut std::ffi::c_void)
+    where
+        F: FnOnce() -> R,
+    {
+        let (f, ret) = &mut *(context as *mut (Option<F>, Option<R>));
+        *ret = Some(f.take().unwrap()());
+    }
+
+    let mut context: (Option<F>, Option<R>) = (Some(f), None);
+    let raw_exception = unsafe {
+        choco_Foundation_catchException(
+            call::<F, R>,
+            &mut context as *mut (Option<F>, Option<R>) as *mut std::ffi::c_void,
+        )
+    };
+    match raw_exception {
+        None => Ok(context.1.unwrap()),
+        Some(raw) => Err(unsafe { Ptr::from_raw_unchecked(raw) }),
+    }
+}
+
+#[cfg(test)]
+mod exception_tests {
+    use super::*;
+
+    #[test]
+    fn no_exception() {
+        assert_eq!(catch_exception(|| 42).ok(), Some(42));
+    }
+
+    #[test]
+    #[should_panic(expected = "panicking in catch_exception")]
+    fn panics_go_through() {
+        let _ = catch_exception(|| panic!("panicking in catch_exception"));
+    }
+}
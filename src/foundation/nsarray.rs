@@ -1,4 +1,4 @@
-use super::{NSObject, NSObjectInterface, NSObjectProtocol};
+use super::{catch_exception, NSException, NSObject, NSObjectInterface, NSObjectProtocol};
 use crate::base::{
     AsRaw, IsKindOf, ObjCClass, Ptr, Ownership, RawClassPtr, RawObjPtr, Retained, Type, TypeKind,
 };
@@ -24,6 +24,13 @@ extern "C" {
     ) -> Option<RawObjPtr>;
 }
 
+extern "C-unwind" {
+    fn choco_Foundation_NSArrayInterface_instance_objectAtIndex_throwing(
+        self_: RawObjPtr,
+        index: usize,
+    ) -> Option<RawObjPtr>;
+}
+
 pub trait NSArrayInterface: NSObjectInterface
 // Self: NSCopyingProtocol + NSMutableCopyingProtocol + NSFastEnumerationProtocol<T>,
 // Self: NSFastEnumerationProtocol<T>,
@@ -58,6 +65,15 @@ trait NSArrayInterfaceInstanceMethods: AsRaw {
         }
     }
 
+    /// Same as `object_at`, but returns the exception instead of aborting if `index` is out of bounds.
+    fn try_object_at(&self, index: usize) -> Result<Ptr<Self::Item, Retained>, Ptr<NSException>> {
+        let raw_self = self.as_raw();
+        let raw = catch_exception(|| unsafe {
+            choco_Foundation_NSArrayInterface_instance_objectAtIndex_throwing(raw_self, index)
+        })?;
+        Ok(unsafe { Ptr::from_raw_unchecked(raw.unwrap()) })
+    }
+
     fn count(&self) -> usize {
         let raw_self = self.as_raw();
         unsafe { choco_Foundation_NSArrayInterface_instance_count(raw_self) }
@@ -124,8 +140,10 @@ impl<T: Type> NSArrayInterface for NSArray<T> {
 #[cfg(test)]
 mod array_tests {
     use super::*;
+    use crate::foundation::NSExceptionInterfaceInstanceMethods as _;
     use crate::foundation::NSObjectProtocolInstanceMethods as _;
     use crate::foundation::NSString;
+    use crate::foundation::NSStringInterfaceInstanceMethods as _;
 
     #[test]
     fn empty_arrays() {
@@ -156,6 +174,27 @@ mod array_tests {
         let array1 = array1.adding_object(&str1);
         assert_eq!(array1.count(), 2);
     }
+
+    #[test]
+    fn out_of_bounds() {
+        let array: Ptr<NSArray<NSObject>> = NSArray::new();
+        let array = array.adding_object(&NSObject::new());
+        assert!(array.try_object_at(0).is_ok());
+        let exception = array.try_object_at(1).err().unwrap();
+        assert_eq!(exception.name().to_string_lossy(), "NSRangeException");
+        assert!(exception.reason().is_some());
+
+        // What was done before the exception was raised is kept.
+        let mut steps = 0;
+        let raw_array = array.as_raw();
+        let result = catch_exception(|| unsafe {
+            steps += 1;
+            choco_Foundation_NSArrayInterface_instance_objectAtIndex_throwing(raw_array, 1);
+            steps += 1;
+        });
+        assert!(result.is_err());
+        assert_eq!(steps, 1);
+    }
 }
 
 //-------------------------------------------------------------------
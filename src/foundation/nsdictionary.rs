@@ -163,6 +163,14 @@ extern "C" {
     fn choco_Foundation_NSMutableDictionaryInterface_instance_removeAllObjects(self_: RawObjPtr);
 }
 
+extern "C-unwind" {
+    fn choco_Foundation_NSMutableDictionaryInterface_instance_setObject_forKey_throwing(
+        self_: RawObjPtr,
+        object: RawObjPtr,
+        key: RawObjPtr,
+    );
+}
+
 pub trait NSMutableDictionaryInterface: NSDictionaryInterface {}
 
 pub trait NSMutableDictionaryInterfaceInstanceMethods:
@@ -188,6 +196,28 @@ pub trait NSMutableDictionaryInterfaceInstanceMethods:
         }
     }
 
+    /// Same as `set`, but returns the exception instead of aborting, for example if the key can't be copied.
+    fn try_set<PassedKey, PassedValue, PassedKeyOwnership, PassedValueOwnership>(
+        &self,
+        key: &Ptr<PassedKey, PassedKeyOwnership>,
+        value: &Ptr<PassedValue, PassedValueOwnership>,
+    ) -> Result<(), Ptr<NSException>>
+    where
+        PassedKey: IsKindOf<Self::Key>,
+        PassedValue: IsKindOf<Self::Value>,
+        PassedKeyOwnership: Ownership,
+        PassedValueOwnership: Ownership,
+    {
+        let raw_self = self.as_raw();
+        let raw_key = key.as_raw();
+        let raw_value = value.as_raw();
+        catch_exception(|| unsafe {
+            choco_Foundation_NSMutableDictionaryInterface_instance_setObject_forKey_throwing(
+                raw_self, raw_value, raw_key,
+            )
+        })
+    }
+
     fn remove<PassedKey, PassedKeyOwnership>(&self, key: &Ptr<PassedKey, PassedKeyOwnership>)
     where
         PassedKey: IsKindOf<Self::Key>,
@@ -334,4 +364,21 @@ mod mutable_dictionary_tests {
         let got = dic.get(&key).unwrap();
         assert!(got.is_equal(&date));
     }
+
+    #[test]
+    fn key_not_copyable() {
+        let dic = NSMutableDictionary::<NSObject, NSObject>::new();
+        let key = NSString::new_with_str("abcd");
+        assert!(dic.try_set(&key, &NSObject::new()).is_ok());
+        // NSObject does not implement NSCopying.
+        let exception = dic
+            .try_set(&NSObject::new(), &NSObject::new())
+            .err()
+            .unwrap();
+        assert_eq!(
+            exception.name().to_string_lossy(),
+            "NSInvalidArgumentException"
+        );
+        assert_eq!(dic.count(), 1);
+    }
 }
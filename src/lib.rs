@@ -1,10 +1,17 @@
 #![warn(rust_2018_idioms)]
 #![allow(dead_code)] // Still in development, some dead code is fine.
 #![allow(unused_parens)] // Sometimes parens makes things easier to read.
+#![allow(clippy::items_after_test_module)] // Tests are kept next to the part of the file they are testing.
 
+// AVFoundation is only available on Apple platforms,
+// so it should stay behind `#[cfg(target_vendor = "apple")]` once enabled again.
+// #[cfg(target_vendor = "apple")]
 // pub mod avfoundation;
 pub mod base;
-// pub mod core_graphics;
-// pub mod core_media;
+pub mod core_graphics;
+// Only the parts of CoreMedia implemented in Rust are available outside of Apple platforms.
+pub mod core_media;
+// Foundation needs a real Objective-C runtime, and goes through the shims of `choco.mm`.
+#[cfg(all(feature = "shims", not(feature = "mock-runtime")))]
 pub mod foundation;
 // pub mod prelude;